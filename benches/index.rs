@@ -0,0 +1,72 @@
+use std::{env, fs, path::PathBuf, time::Instant};
+
+use shit::index::Index;
+use shit::workspace::Workspace;
+use tempfile::TempDir;
+
+/*
+    Coarse throughput benchmark for index writes and workspace walks.
+    Run with `cargo bench`, the number of entries is read from BENCH_N.
+    Smoke versions with a coarse time bound run under `cargo test`, in the
+    tests of src/index.rs and src/workspace.rs.
+*/
+fn main() {
+    let n: usize = env::var("BENCH_N")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10_000);
+
+    bench_write_updates(n);
+    bench_list_files(n);
+}
+
+fn bench_write_updates(n: usize) {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let file_path = dir.path().join("file.txt");
+    fs::write(&file_path, "bench").expect("failed to write bench file");
+    let stat = fs::metadata(&file_path).expect("failed to stat bench file");
+    let object_id = b"0123456789abcdefghij";
+
+    let mut index = Index::new(dir.path().join("index"));
+    let start = Instant::now();
+    for i in 0..n {
+        let path = PathBuf::from(format!("dir{}/file{}.txt", i % 100, i));
+        index
+            .add(&path, object_id, stat.clone())
+            .expect("failed to add bench entry");
+    }
+    let staged = start.elapsed();
+    assert!(index.write_updates().expect("failed to write index"));
+    let total = start.elapsed();
+
+    println!(
+        "write_updates: {} entries staged in {:?}, written in {:?}",
+        n,
+        staged,
+        total - staged
+    );
+}
+
+fn bench_list_files(n: usize) {
+    let dir = TempDir::new().expect("failed to create temp dir");
+
+    // Spread the files over a tree ten directories deep.
+    let mut deepest = dir.path().to_path_buf();
+    for depth in 0..10 {
+        deepest.push(format!("level{}", depth));
+    }
+    fs::create_dir_all(&deepest).expect("failed to create bench tree");
+    for i in 0..n {
+        let mut path = dir.path().to_path_buf();
+        for depth in 0..(i % 10) {
+            path.push(format!("level{}", depth));
+        }
+        path.push(format!("file{}.txt", i));
+        fs::write(path, "bench").expect("failed to write bench file");
+    }
+
+    let workspace = Workspace::new(dir.path().to_path_buf());
+    let start = Instant::now();
+    let files = workspace.list_files(dir.path(), None);
+    println!("list_files: {} files in {:?}", files.len(), start.elapsed());
+}
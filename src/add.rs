@@ -0,0 +1,520 @@
+use std::{
+    fs::Metadata,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::blob::Blob;
+use crate::database::Database;
+use crate::filesystem::FileSystem;
+use crate::hash;
+use crate::index::Index;
+use crate::workspace::Workspace;
+
+#[derive(Debug, Default)]
+pub struct AddOptions {
+    // Record paths in the index without staging their content.
+    pub intent_to_add: bool,
+    // core.ignorecase: paths differing only in case are the same entry.
+    pub ignore_case: bool,
+    // Stage pathspecs even when they name an ignored path.
+    pub force: bool,
+    // Skip pathspecs that match nothing, and paths that vanish or stop
+    // being files before they are staged, instead of failing the add.
+    pub ignore_missing: bool,
+    // Only restage tracked files, dropping the ones that were deleted,
+    // and never pick up untracked files.
+    pub update: bool,
+}
+
+/*
+    Reads pathspecs for add --pathspec-from-file, one per line or, when nul
+    is set, separated by NUL bytes. Empty pathspecs are skipped.
+*/
+pub fn read_pathspecs<R: Read>(mut reader: R, nul: bool) -> io::Result<Vec<String>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let separator = if nul { b'\0' } else { b'\n' };
+    data.split(|&b| b == separator)
+        .filter(|pathspec| !pathspec.is_empty())
+        .map(|pathspec| {
+            String::from_utf8(pathspec.to_vec()).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("bad pathspec: {}", err))
+            })
+        })
+        .collect()
+}
+
+/*
+    Stages every file under each of pathnames, relative to the workspace
+    root. Blobs are stored in the database and the index is updated, but
+    not written. A pathspec that doesn't exist is an error unless
+    ignore_missing is set. The progress callback, when given, is invoked
+    once per file after it has been staged, which is how add -v reports
+    each path. Paths that can't be listed or read are reported and
+    skipped, and their number is returned.
+*/
+pub fn add(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    pathnames: &[String],
+    options: &AddOptions,
+    mut progress: Option<&mut dyn FnMut(&Path)>,
+) -> io::Result<usize> {
+    if options.update {
+        return update_tracked(workspace, database, index, pathnames, progress);
+    }
+    // A pathspec naming nothing fails the add before anything is staged.
+    let mut pathspecs = Vec::new();
+    for pathname in pathnames {
+        let pathspec = PathBuf::from(pathname);
+        match workspace.symlink_metadata(&pathspec) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if options.ignore_missing {
+                    continue;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("pathspec '{}' did not match any files", pathname),
+                ));
+            }
+            _ => pathspecs.push(pathspec),
+        }
+    }
+    let mut skipped = 0;
+    for path in workspace.list_pathspecs(&pathspecs, options.force, None) {
+        // Report paths that can't be listed or read and stage the rest.
+        let path = match path {
+            Ok(path) => path,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                skipped += 1;
+                continue;
+            }
+        };
+        // Index paths are UTF-8, other names are reported and skipped.
+        let Some(pathname) = path.to_str().map(str::to_string) else {
+            eprintln!("error: skipping non-UTF-8 path '{}'", path.display());
+            skipped += 1;
+            continue;
+        };
+        // Paths that only differ in case collide on checkout to a
+        // case insensitive filesystem.
+        let mut entry_path = path.clone();
+        if let Some(existing) = index.find_case_collision(&pathname) {
+            if options.ignore_case {
+                entry_path = PathBuf::from(existing);
+            } else {
+                eprintln!(
+                    "warning: '{}' collides with '{}' on a case-insensitive filesystem",
+                    pathname, existing
+                );
+            }
+        }
+
+        // The file may have changed since it was listed.
+        let stat = match stat_stageable(workspace, &path) {
+            Ok(stat) => stat,
+            Err(err) if options.ignore_missing => {
+                eprintln!("warning: skipping '{}': {}", pathname, err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        if options.intent_to_add {
+            index.add_intent_to_add(&entry_path, stat)?;
+        } else {
+            // Get file data and store blob.
+            let data = match read_blob(workspace, &path) {
+                Ok(data) => data,
+                Err(err) => {
+                    eprintln!("error: unable to read '{}': {}", pathname, err);
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let mut blob = Blob::new(&data);
+            database.store(&mut blob)?;
+            index.add(&entry_path, hash::raw_oid(blob.object_id.as_bytes())?, stat)?;
+        }
+        if let Some(callback) = progress.as_mut() {
+            callback(&entry_path);
+        }
+    }
+    Ok(skipped)
+}
+
+/*
+    The add -u mode. Every tracked file under pathnames, or every tracked
+    file when there are none, is restaged if its stat changed, and its
+    entry is removed if it no longer exists in the working tree.
+*/
+fn update_tracked(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    pathnames: &[String],
+    mut progress: Option<&mut dyn FnMut(&Path)>,
+) -> io::Result<usize> {
+    let pathspecs = pathnames
+        .iter()
+        .map(|pathname| pathname.trim_end_matches('/'))
+        .collect::<Vec<_>>();
+    let matches = |path: &str| {
+        pathspecs.is_empty()
+            || pathspecs.iter().any(|spec| {
+                *spec == "." || path == *spec || path.starts_with(&format!("{}/", spec))
+            })
+    };
+
+    let mut skipped = 0;
+    for entry in index.each_entry() {
+        if !matches(entry.path()) || entry.skip_worktree() {
+            continue;
+        }
+        let path = PathBuf::from(entry.path());
+        let stat = match workspace.symlink_metadata(&path) {
+            Ok(stat) => stat,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                index.remove(entry.path());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if entry.stat_matches(&stat) && !entry.intent_to_add() {
+            continue;
+        }
+        let data = match read_blob(workspace, &path) {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln!("error: unable to read '{}': {}", entry.path(), err);
+                skipped += 1;
+                continue;
+            }
+        };
+        let mut blob = Blob::new(&data);
+        database.store(&mut blob)?;
+        index.add(&path, hash::raw_oid(blob.object_id.as_bytes())?, stat)?;
+        if let Some(callback) = progress.as_mut() {
+            callback(&path);
+        }
+    }
+    Ok(skipped)
+}
+
+/*
+    Stats path for staging. It must still exist and be a file, a symlink,
+    or a directory holding a nested repository.
+*/
+fn stat_stageable(workspace: &Workspace, path: &Path) -> io::Result<Metadata> {
+    let stat = workspace.symlink_metadata(path).map_err(|err| {
+        io::Error::new(err.kind(), format!("'{}' no longer exists", path.display()))
+    })?;
+    if stat.is_dir() && workspace.metadata(&path.join(".git")).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is a directory, not a file", path.display()),
+        ));
+    }
+    Ok(stat)
+}
+
+// The filtered content of a file as the text a blob holds.
+fn read_blob<F: FileSystem>(workspace: &Workspace<F>, path: &Path) -> io::Result<String> {
+    String::from_utf8(workspace.read_blob(path)?).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", workspace.root().join(path).display(), err),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        ffi::OsStr,
+        fs,
+        os::unix::{ffi::OsStrExt, fs::symlink},
+    };
+
+    use crate::filesystem::mock::MockFs;
+    use crate::test_support::TestRepo;
+    use crate::utils;
+
+    fn add_with(repo: &TestRepo, pathspecs: &[&str], options: &AddOptions) -> Index {
+        let mut index = repo.index();
+        let pathspecs = pathspecs.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        add(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &pathspecs,
+            options,
+            None,
+        )
+        .unwrap();
+        index
+    }
+
+    #[test]
+    fn pathspecs_are_read_per_line_or_per_nul() {
+        let read = |data: &[u8], nul| read_pathspecs(data, nul).unwrap();
+        assert_eq!(
+            read(b"a.txt\nb c.txt\n\nlib\n", false),
+            ["a.txt", "b c.txt", "lib"]
+        );
+        assert_eq!(
+            read(b"a.txt\0with\nnewline\0", true),
+            ["a.txt", "with\nnewline"]
+        );
+        assert!(read(b"", false).is_empty());
+        let err = read_pathspecs(&b"\xff\n"[..], false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn progress_is_reported_once_per_staged_file() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        repo.write("lib/b.txt", "b\n");
+
+        let mut seen = Vec::new();
+        let mut progress = |path: &Path| seen.push(path.to_path_buf());
+        let mut index = repo.index();
+        add(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &[".".to_string()],
+            &AddOptions::default(),
+            Some(&mut progress),
+        )
+        .unwrap();
+        seen.sort();
+        assert_eq!(seen, [PathBuf::from("a.txt"), PathBuf::from("lib/b.txt")]);
+        assert_eq!(index.each_entry().len(), 2);
+    }
+
+    #[test]
+    fn case_collision_keeps_both_entries_by_default() {
+        let repo = TestRepo::new();
+        repo.write("Readme", "old\n");
+        repo.stage(&["Readme"]);
+        repo.write("README", "new\n");
+
+        let mut index = add_with(&repo, &["README"], &AddOptions::default());
+        assert_eq!(index.each_entry().len(), 2);
+        assert_eq!(index.find_case_collision("README"), Some("Readme"));
+    }
+
+    #[test]
+    fn ignore_case_folds_collision_into_existing_entry() {
+        let repo = TestRepo::new();
+        repo.write("Readme", "old\n");
+        repo.stage(&["Readme"]);
+        let old = repo.index().get("Readme").unwrap().oid().to_vec();
+        repo.write("README", "new\n");
+
+        let options = AddOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let mut index = add_with(&repo, &["README"], &options);
+        assert_eq!(index.each_entry().len(), 1);
+        assert!(index.get("README").is_none());
+        assert_ne!(index.get("Readme").unwrap().oid(), old);
+    }
+
+    #[test]
+    fn unreadable_file_is_skipped_and_the_rest_staged() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        fs::write(repo.root().join("bin.dat"), [0xff, 0xfe, 0x00]).unwrap();
+        repo.write("z.txt", "z\n");
+
+        let mut index = repo.index();
+        let skipped = add(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &[".".to_string()],
+            &AddOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(skipped, 1);
+        assert!(index.get("a.txt").is_some());
+        assert!(index.get("z.txt").is_some());
+        assert!(index.get("bin.dat").is_none());
+    }
+
+    #[test]
+    fn permission_denied_file_fails_to_read_alone() {
+        let mut mock = MockFs::new();
+        mock.add_file(Path::new("/repo/a.txt"), b"a\n");
+        mock.add_file(Path::new("/repo/secret.txt"), b"s\n");
+        mock.add_file(Path::new("/repo/z.txt"), b"z\n");
+        mock.deny_read(Path::new("/repo/secret.txt"));
+        let workspace = Workspace::with_fs(PathBuf::from("/repo"), mock);
+
+        let files = workspace.list_pathspecs(&[PathBuf::from(".")], false, None);
+        let files = files.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(files.len(), 3);
+        for file in files {
+            let read = read_blob(&workspace, &file);
+            if file == Path::new("secret.txt") {
+                assert_eq!(read.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+            } else {
+                assert!(read.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn non_utf8_name_is_skipped_and_the_rest_staged() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        fs::write(repo.root().join(OsStr::from_bytes(b"bad\xff.txt")), "b\n").unwrap();
+
+        let index = add_with(&repo, &["."], &AddOptions::default());
+        assert_eq!(index.len(), 1);
+        assert!(index.get("a.txt").is_some());
+    }
+
+    #[test]
+    fn vanished_path_is_a_clean_error() {
+        let repo = TestRepo::new();
+        repo.write("kept.txt", "kept\n");
+        repo.write("gone.txt", "soon gone\n");
+        fs::remove_file(repo.root().join("gone.txt")).unwrap();
+        let pathspecs = ["kept.txt".to_string(), "gone.txt".to_string()];
+        let mut index = repo.index();
+        let err = add(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &pathspecs,
+            &AddOptions::default(),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'gone.txt' did not match any files"
+        );
+        assert!(index.is_empty());
+
+        let options = AddOptions {
+            ignore_missing: true,
+            ..Default::default()
+        };
+        let index = add_with(&repo, &["kept.txt", "gone.txt"], &options);
+        assert!(index.get("kept.txt").is_some());
+        assert!(index.get("gone.txt").is_none());
+    }
+
+    #[test]
+    fn file_gone_after_listing_is_not_stageable() {
+        let repo = TestRepo::new();
+        repo.write("gone.txt", "soon gone\n");
+        fs::remove_file(repo.root().join("gone.txt")).unwrap();
+        let err = stat_stageable(&repo.workspace(), Path::new("gone.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "'gone.txt' no longer exists");
+    }
+
+    #[test]
+    fn directory_is_not_stageable_unless_a_nested_repository() {
+        let repo = TestRepo::new();
+        fs::create_dir_all(repo.root().join("dir")).unwrap();
+        let err = stat_stageable(&repo.workspace(), Path::new("dir")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "'dir' is a directory, not a file");
+
+        fs::create_dir_all(repo.root().join("dir").join(".git")).unwrap();
+        assert!(stat_stageable(&repo.workspace(), Path::new("dir")).is_ok());
+    }
+
+    #[test]
+    fn symlink_is_staged_as_its_target_path() {
+        let repo = TestRepo::new();
+        repo.write("target.txt", "target content\n");
+        symlink("target.txt", repo.root().join("link")).unwrap();
+        // A dangling link has nothing to follow but is still staged.
+        symlink("missing.txt", repo.root().join("dangling")).unwrap();
+        let index = add_with(&repo, &["link", "dangling"], &AddOptions::default());
+
+        for (path, target) in [("link", "target.txt"), ("dangling", "missing.txt")] {
+            let entry = index.get(path).unwrap();
+            assert_eq!(entry.mode(), 0o120000);
+            let oid = utils::u8_to_hex_str(entry.oid().to_vec());
+            let (kind, data) = repo.database().read_object(&oid).unwrap();
+            assert_eq!(
+                (kind.as_str(), data.as_slice()),
+                ("blob", target.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn adding_a_directory_applies_root_and_nested_ignores() {
+        let repo = TestRepo::new();
+        repo.write(".gitignore", "*.log\n");
+        repo.write("somedir/.gitignore", "secret.txt\n");
+        repo.write("somedir/kept.txt", "k\n");
+        repo.write("somedir/secret.txt", "s\n");
+        repo.write("somedir/debug.log", "d\n");
+        repo.write("somedir/deep/secret.txt", "s\n");
+        repo.write("somedir/deep/also.txt", "a\n");
+
+        let mut index = add_with(&repo, &["somedir"], &AddOptions::default());
+        let paths = index
+            .each_entry()
+            .iter()
+            .map(|entry| entry.path().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "somedir/.gitignore",
+                "somedir/deep/also.txt",
+                "somedir/kept.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn update_restages_tracked_files_and_ignores_untracked() {
+        let repo = TestRepo::new();
+        repo.write("modified.txt", "one\n");
+        repo.write("deleted.txt", "gone\n");
+        repo.write("kept.txt", "kept\n");
+        repo.stage(&["."]);
+        repo.commit("initial");
+        let kept = repo.index().get("kept.txt").unwrap().oid().to_vec();
+
+        repo.write("modified.txt", "two\n");
+        fs::remove_file(repo.root().join("deleted.txt")).unwrap();
+        repo.write("untracked.txt", "new\n");
+        let update = AddOptions {
+            update: true,
+            ..Default::default()
+        };
+        let mut index = add_with(&repo, &[], &update);
+
+        let paths = index
+            .each_entry()
+            .iter()
+            .map(|entry| entry.path().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["kept.txt", "modified.txt"]);
+        let modified = utils::u8_to_hex_str(index.get("modified.txt").unwrap().oid().to_vec());
+        assert_eq!(repo.database().read_object(&modified).unwrap().1, b"two\n");
+        assert_eq!(index.get("kept.txt").unwrap().oid(), kept.as_slice());
+    }
+}
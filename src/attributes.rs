@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use crate::ignore::IgnorePattern;
+
+/*
+    The state a .gitattributes line gives an attribute: set by naming it,
+    unset with a leading "-", or given a value with "name=value". A
+    leading "!" returns it to unspecified, as if no line had named it.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified,
+}
+
+// The attributes the "binary" macro stands for.
+const BINARY_MACRO: [&str; 3] = ["diff", "merge", "text"];
+
+/*
+    A single line of a .gitattributes file: a pattern, matched like a
+    .gitignore pattern, followed by the attributes it assigns.
+*/
+#[derive(Debug, Clone)]
+struct AttributeLine {
+    pattern: IgnorePattern,
+    attributes: Vec<(String, State)>,
+}
+
+impl AttributeLine {
+    // Negated patterns are not allowed in .gitattributes and are skipped.
+    fn parse(line: &str, base: &Path) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next().filter(|pattern| !pattern.starts_with('!'))?;
+        let pattern = IgnorePattern::parse(pattern, base)?;
+
+        let mut attributes = Vec::new();
+        for field in fields {
+            if field == "binary" {
+                attributes.extend(BINARY_MACRO.map(|name| (name.to_string(), State::Unset)));
+                continue;
+            }
+            let attribute = if let Some(name) = field.strip_prefix('-') {
+                (name.to_string(), State::Unset)
+            } else if let Some(name) = field.strip_prefix('!') {
+                (name.to_string(), State::Unspecified)
+            } else if let Some((name, value)) = field.split_once('=') {
+                (name.to_string(), State::Value(value.to_string()))
+            } else {
+                (field.to_string(), State::Set)
+            };
+            attributes.push(attribute);
+        }
+        Some(AttributeLine {
+            pattern,
+            attributes,
+        })
+    }
+}
+
+/*
+    The attribute lines in effect for a path: those of every
+    .gitattributes from the workspace root down to its directory, in that
+    order, so deeper files and later lines take precedence.
+*/
+#[derive(Debug, Default, Clone)]
+pub struct AttributeStack {
+    lines: Vec<AttributeLine>,
+}
+
+impl AttributeStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds the lines of a .gitattributes found in base.
+    pub fn push(&mut self, contents: &str, base: &Path) {
+        self.lines.extend(
+            contents
+                .lines()
+                .filter_map(|line| AttributeLine::parse(line, base)),
+        );
+    }
+
+    /*
+        The state of the attribute name for path, relative to the workspace
+        root, decided by the last line that matches path and names it.
+    */
+    pub fn get(&self, path: &Path, name: &str) -> State {
+        self.lines
+            .iter()
+            .rev()
+            .filter(|line| line.pattern.matches(path, false))
+            .find_map(|line| {
+                line.attributes
+                    .iter()
+                    .rev()
+                    .find(|(attribute, _)| attribute == name)
+            })
+            .map_or(State::Unspecified, |(_, state)| state.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_set_unset_and_assign_attributes() {
+        let mut attributes = AttributeStack::new();
+        attributes.push(
+            "*.txt text eol=crlf\n*.png binary\n# comment\n",
+            Path::new(""),
+        );
+        let get = |path: &str, name: &str| attributes.get(Path::new(path), name);
+        assert_eq!(get("a.txt", "text"), State::Set);
+        assert_eq!(get("docs/a.txt", "eol"), State::Value(String::from("crlf")));
+        assert_eq!(get("a.png", "text"), State::Unset);
+        assert_eq!(get("a.png", "diff"), State::Unset);
+        assert_eq!(get("a.png", "eol"), State::Unspecified);
+        assert_eq!(get("a.rs", "text"), State::Unspecified);
+    }
+
+    #[test]
+    fn deeper_files_and_later_lines_take_precedence() {
+        let mut attributes = AttributeStack::new();
+        attributes.push("*.txt text\n*.txt -text\n", Path::new(""));
+        attributes.push("*.txt eol=lf\nkeep.txt !text\n", Path::new("docs"));
+        let get = |path: &str, name: &str| attributes.get(Path::new(path), name);
+        assert_eq!(get("a.txt", "text"), State::Unset);
+        assert_eq!(get("a.txt", "eol"), State::Unspecified);
+        assert_eq!(get("docs/a.txt", "eol"), State::Value(String::from("lf")));
+        assert_eq!(get("docs/keep.txt", "text"), State::Unspecified);
+        assert_eq!(get("keep.txt", "text"), State::Unset);
+    }
+}
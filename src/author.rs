@@ -1,6 +1,10 @@
-use std::fmt;
+use std::{env, fmt};
 
-#[derive(Debug)]
+use chrono::{DateTime, FixedOffset};
+
+use crate::config::Config;
+
+#[derive(Debug, Clone)]
 pub struct Author {
     pub name: String,
     pub email: String,
@@ -11,10 +15,240 @@ impl Author {
     pub fn new(name: String, email: String, time: String) -> Self {
         Author { name, email, time }
     }
+
+    /*
+        Parses the value of an author or committer header,
+        "Name <email> <epoch seconds> <+hhmm offset>".
+    */
+    pub fn parse(s: &str) -> Option<Self> {
+        let (identity, offset) = s.rsplit_once(' ')?;
+        let (identity, seconds) = identity.rsplit_once(' ')?;
+        let (name, email) = match identity.rsplit_once('<') {
+            Some((name, email)) => (name.trim_end(), email.trim_end_matches('>')),
+            None => identity.rsplit_once(' ').unwrap_or(("", identity)),
+        };
+        Some(Author::new(
+            name.to_string(),
+            email.to_string(),
+            format!("{} {}", seconds, offset),
+        ))
+    }
+
+    /*
+        Returns the timestamp in the author's own timezone,
+        or None if the stored time is malformed.
+    */
+    pub fn datetime(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_str(&self.time, "%s %z").ok()
+    }
+
+    /*
+        Formats the timestamp the way git log does by default,
+        e.g. "Mon Oct 14 03:42:00 2026 +0000".
+    */
+    pub fn readable_time(&self) -> String {
+        match self.datetime() {
+            Some(datetime) => datetime.format("%a %b %-d %H:%M:%S %Y %z").to_string(),
+            None => self.time.clone(),
+        }
+    }
+
+    /*
+        Formats the timestamp relative to now, in epoch seconds, the way
+        git log --relative-date does, e.g. "3 days ago" or
+        "2 years, 1 month ago". Each unit is rounded to the nearest whole.
+    */
+    pub fn relative_time(&self, now: i64) -> String {
+        let Some(datetime) = self.datetime() else {
+            return self.time.clone();
+        };
+        let diff = now - datetime.timestamp();
+        if diff < 0 {
+            return String::from("in the future");
+        }
+        if diff < 90 {
+            return ago(diff, "second");
+        }
+        let minutes = (diff + 30) / 60;
+        if minutes < 90 {
+            return ago(minutes, "minute");
+        }
+        let hours = (minutes + 30) / 60;
+        if hours < 36 {
+            return ago(hours, "hour");
+        }
+        let days = (hours + 12) / 24;
+        if days < 14 {
+            return ago(days, "day");
+        }
+        if days < 70 {
+            return ago((days + 3) / 7, "week");
+        }
+        if days < 365 {
+            return ago((days + 15) / 30, "month");
+        }
+        if days < 1825 {
+            let total_months = (days * 12 * 2 + 365) / (365 * 2);
+            let (years, months) = (total_months / 12, total_months % 12);
+            if months == 0 {
+                return ago(years, "year");
+            }
+            return format!("{}, {}", plural(years, "year"), ago(months, "month"));
+        }
+        ago((days + 183) / 365, "year")
+    }
+}
+
+/*
+    How commit dates are shown by show.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+    // "Mon Oct 14 03:42:00 2026 +0000"
+    Default,
+    // "3 days ago", relative to now in epoch seconds.
+    Relative(i64),
+}
+
+impl DateFormat {
+    pub fn format(&self, author: &Author) -> String {
+        match self {
+            DateFormat::Default => author.readable_time(),
+            DateFormat::Relative(now) => author.relative_time(*now),
+        }
+    }
+}
+
+fn plural(n: i64, unit: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, unit)
+    } else {
+        format!("{} {}s", n, unit)
+    }
+}
+
+fn ago(n: i64, unit: &str) -> String {
+    format!("{} ago", plural(n, unit))
+}
+
+/*
+    The name and email for role, "AUTHOR" or "COMMITTER", from
+    GIT_<role>_NAME and GIT_<role>_EMAIL or else from user.name and
+    user.email. Angle brackets around the email are dropped.
+*/
+pub fn identity(role: &str, config: &Config) -> Option<(String, String)> {
+    let lookup = |key: &str, config_key: &str| {
+        env::var(format!("GIT_{}_{}", role, key))
+            .ok()
+            .or_else(|| config.get(config_key).map(str::to_string))
+            .filter(|value| !value.is_empty())
+    };
+    let name = lookup("NAME", "user.name")?;
+    let email = lookup("EMAIL", "user.email")?;
+    Some((
+        name,
+        email.trim_matches(|c| c == '<' || c == '>').to_string(),
+    ))
+}
+
+/*
+    Parses a date given on the command line or in GIT_AUTHOR_DATE into the
+    "<epoch seconds> <+hhmm offset>" form stored in commits. Accepts the
+    raw form, optionally prefixed with "@", and RFC 2822 dates such as
+    "Tue, 14 Nov 2023 22:13:20 +0000". A bare epoch is taken as UTC.
+*/
+pub fn parse_date(s: &str) -> Option<String> {
+    let s = s.trim();
+    let raw = s.strip_prefix('@').unwrap_or(s);
+    let (seconds, offset) = raw.split_once(' ').unwrap_or((raw, "+0000"));
+    if seconds.parse::<i64>().is_ok() {
+        let offset = offset.trim();
+        return DateTime::parse_from_str(&format!("0 {}", offset), "%s %z")
+            .ok()
+            .map(|_| format!("{} {}", seconds, offset));
+    }
+    DateTime::parse_from_rfc2822(s)
+        .ok()
+        .map(|datetime| datetime.format("%s %z").to_string())
 }
 
 impl fmt::Display for Author {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {} {}", self.name, self.email, self.time)
+        write!(f, "{} <{}> {}", self.name, self.email, self.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_date_accepts_raw_and_rfc2822() {
+        assert_eq!(
+            parse_date("@1700000000 +0000").as_deref(),
+            Some("1700000000 +0000")
+        );
+        assert_eq!(
+            parse_date("1700000000 -0130").as_deref(),
+            Some("1700000000 -0130")
+        );
+        assert_eq!(
+            parse_date("@1700000000").as_deref(),
+            Some("1700000000 +0000")
+        );
+        assert_eq!(
+            parse_date("Tue, 14 Nov 2023 22:13:20 +0000").as_deref(),
+            Some("1700000000 +0000")
+        );
+        assert_eq!(
+            parse_date("Tue, 14 Nov 2023 23:13:20 +0100").as_deref(),
+            Some("1700000000 +0100")
+        );
+    }
+
+    #[test]
+    fn parse_date_rejects_garbage() {
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_date("@1700000000 0000x"), None);
+    }
+
+    #[test]
+    fn relative_time_rounds_to_the_nearest_unit() {
+        let author = Author::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            String::from("1700000000 +0100"),
+        );
+        let then = 1_700_000_000;
+        let minute = 60;
+        let hour = 60 * minute;
+        let day = 24 * hour;
+        for (offset, expected) in [
+            (0, "0 seconds ago"),
+            (1, "1 second ago"),
+            (89, "89 seconds ago"),
+            (90, "2 minutes ago"),
+            (89 * minute, "89 minutes ago"),
+            (2 * hour, "2 hours ago"),
+            (35 * hour, "35 hours ago"),
+            (3 * day, "3 days ago"),
+            (20 * day, "3 weeks ago"),
+            (100 * day, "3 months ago"),
+            (365 * day, "1 year ago"),
+            (400 * day, "1 year, 1 month ago"),
+            (3 * 365 * day, "3 years ago"),
+            (10 * 365 * day, "10 years ago"),
+            (-1, "in the future"),
+        ] {
+            assert_eq!(author.relative_time(then + offset), expected, "{}", offset);
+        }
+        assert_eq!(
+            DateFormat::Relative(then + 3 * day).format(&author),
+            "3 days ago"
+        );
+        assert_eq!(
+            DateFormat::Default.format(&author),
+            "Tue Nov 14 23:13:20 2023 +0100"
+        );
     }
 }
@@ -27,7 +27,7 @@ impl Blob {
 impl Object for Blob {
     fn to_string(&self) -> String {
         let kind = format!("{:?}", self.kind).to_lowercase();
-        let bytesize = self.data.bytes().len();
+        let bytesize = self.data.len();
         format!("{} {}\0{}", kind, bytesize, self.data)
     }
 
@@ -0,0 +1,116 @@
+/*
+    The tree ids cached in the TREE index extension. Each node covers a
+    directory, the root being the whole index, and records how many index
+    entries lie under it and the id of the tree they were last written as.
+    A node whose contents change is invalidated, so the next tree write
+    only has to rebuild the directories that changed.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTree {
+    // The number of index entries under this directory, or -1 once it has
+    // been invalidated.
+    pub entry_count: i32,
+    // The raw object id of the tree, empty while invalidated.
+    pub oid: Vec<u8>,
+    // Subdirectories by name.
+    pub children: Vec<(String, CacheTree)>,
+}
+
+impl Default for CacheTree {
+    fn default() -> Self {
+        CacheTree {
+            entry_count: -1,
+            oid: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+}
+
+impl CacheTree {
+    /*
+        Parses the data of a TREE extension. Each node is written as
+        "<name>\0<entry count> <subtree count>\n", the 20 byte tree id when
+        the entry count isn't negative, and then its subtrees. The root has
+        an empty name.
+    */
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (_, tree, rest) = Self::parse_node(data)?;
+        rest.is_empty().then_some(tree)
+    }
+
+    fn parse_node(data: &[u8]) -> Option<(String, Self, &[u8])> {
+        let nul = data.iter().position(|&b| b == 0)?;
+        let name = String::from_utf8(data[..nul].to_vec()).ok()?;
+        let rest = &data[nul + 1..];
+        let newline = rest.iter().position(|&b| b == b'\n')?;
+        let (entry_count, subtrees) = std::str::from_utf8(&rest[..newline])
+            .ok()?
+            .split_once(' ')?;
+        let entry_count = entry_count.parse::<i32>().ok()?;
+        let subtrees = subtrees.parse::<usize>().ok()?;
+
+        let mut rest = &rest[newline + 1..];
+        let mut oid = Vec::new();
+        if entry_count >= 0 {
+            oid = rest.get(..20)?.to_vec();
+            rest = &rest[20..];
+        }
+        let mut children = Vec::new();
+        for _ in 0..subtrees {
+            let (name, child, after) = Self::parse_node(rest)?;
+            children.push((name, child));
+            rest = after;
+        }
+        let tree = CacheTree {
+            entry_count,
+            oid,
+            children,
+        };
+        Some((name, tree, rest))
+    }
+
+    // Serializes the extension data in the form parse reads.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_node("", &mut out);
+        out
+    }
+
+    fn write_node(&self, name: &str, out: &mut Vec<u8>) {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.extend_from_slice(format!("{} {}\n", self.entry_count, self.children.len()).as_bytes());
+        if self.entry_count >= 0 {
+            out.extend_from_slice(&self.oid);
+        }
+        for (name, child) in &self.children {
+            child.write_node(name, out);
+        }
+    }
+
+    // The cached id of this directory, if it is still valid.
+    pub fn valid_oid(&self) -> Option<&[u8]> {
+        (self.entry_count >= 0).then_some(self.oid.as_slice())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&CacheTree> {
+        self.children
+            .iter()
+            .find(|(child, _)| child == name)
+            .map(|(_, tree)| tree)
+    }
+
+    /*
+        Invalidates every directory containing path, relative to the root
+        of the index, from the root down to its parent.
+    */
+    pub fn invalidate(&mut self, path: &str) {
+        self.entry_count = -1;
+        self.oid.clear();
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some((_, child)) = self.children.iter_mut().find(|(name, _)| name == dir) {
+                child.invalidate(rest);
+            }
+        }
+    }
+}
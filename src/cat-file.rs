@@ -3,11 +3,24 @@ use std::io;
 use std::io::Write;
 use std::path::Path;
 
-mod utils;
+use shit::{database, repository::Repository, utils};
 
 fn main() -> io::Result<()> {
-    let db_path = utils::get_db_path();
+    let db_path = Repository::discover(env::current_dir()?)?.db_path();
     let args: Vec<String> = env::args().collect();
+
+    // --batch and --batch-check read object ids from stdin instead.
+    let batch = args.get(1).map(String::as_str);
+    if batch == Some("--batch") || batch == Some("--batch-check") {
+        let database = database::Database::new(db_path);
+        let stdout = io::stdout();
+        return database.cat_file_batch(
+            io::stdin().lock(),
+            &mut stdout.lock(),
+            batch == Some("--batch"),
+        );
+    }
+
     let path_str = args.get(1).expect("expect path to object file");
     let path_buf = Path::new(path_str);
     let object_filename = path_buf.file_name().unwrap();
@@ -0,0 +1,305 @@
+use std::{collections::BTreeSet, io, path::Path};
+
+use crate::commit;
+use crate::database::Database;
+use crate::diff;
+use crate::hash;
+use crate::index::Index;
+use crate::refs::Refs;
+use crate::status;
+use crate::tree;
+use crate::utils;
+use crate::workspace::Workspace;
+
+/*
+    Switches the working tree, the index and HEAD to branch. Only paths
+    that differ between the current and the target commit are touched,
+    and nothing is changed when one of them has uncommitted changes or is
+    an untracked file the target would overwrite. A branch without commits
+    can only be switched to while HEAD has none either, in which case only
+    HEAD is rewritten. The index is updated but not written.
+*/
+pub fn checkout_branch(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+    branch: &str,
+) -> io::Result<()> {
+    let target = refs.read_ref(&format!("refs/heads/{}", branch))?;
+    if target.is_empty() {
+        if !refs.read_head()?.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("invalid reference: {}", branch),
+            ));
+        }
+        return set_head(refs, branch);
+    }
+
+    let (_, data) = database.read_object(&target)?;
+    let target_files = tree::flatten_tree(database, &commit::parse_commit(&data)?.tree)?;
+    let head_files = tree::flatten_head_tree(database, refs)?;
+    let changes = diff::diff_file_maps(&head_files, &target_files);
+
+    check_overwrites(
+        workspace,
+        database,
+        index,
+        refs,
+        &changes,
+        "checkout",
+        "switch branches",
+    )?;
+    apply_changes(workspace, database, index, changes)?;
+    set_head(refs, branch)
+}
+
+/*
+    Fails when applying changes would lose work, because a changed path
+    has uncommitted changes or is an untracked file in the way. The error
+    lists the paths, naming the operation that was refused.
+*/
+pub fn check_overwrites(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+    changes: &[diff::TreeChange],
+    operation: &str,
+    before: &str,
+) -> io::Result<()> {
+    let report = status::status(workspace, database, index, refs)?;
+    let dirty = report
+        .modified
+        .iter()
+        .chain(&report.deleted)
+        .chain(report.staged.iter().map(|(path, _)| path))
+        .chain(&report.intent_to_add)
+        .chain(&report.type_changed)
+        .collect::<BTreeSet<_>>();
+    let untracked = report.untracked.iter().collect::<BTreeSet<_>>();
+    let mut local_changes = Vec::new();
+    let mut overwritten = Vec::new();
+    for change in changes {
+        if dirty.contains(&change.path) {
+            local_changes.push(change.path.as_str());
+        } else if untracked.contains(&change.path) {
+            overwritten.push(change.path.as_str());
+        }
+    }
+    if !local_changes.is_empty() {
+        return Err(would_overwrite(
+            "Your local changes to the following files",
+            &local_changes,
+            operation,
+            before,
+        ));
+    }
+    if !overwritten.is_empty() {
+        return Err(would_overwrite(
+            "The following untracked working tree files",
+            &overwritten,
+            operation,
+            before,
+        ));
+    }
+    Ok(())
+}
+
+/*
+    Writes each change into the workspace and the index, removing the
+    files a change deletes and writing the others from their blobs. Every
+    deletion, and with it any directory left empty, happens before the
+    first write, so a directory can be replaced by a file of the same name
+    and the other way around. The index is updated but not written.
+*/
+pub fn apply_changes(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    changes: Vec<diff::TreeChange>,
+) -> io::Result<()> {
+    let (deletions, writes): (Vec<_>, Vec<_>) =
+        changes.into_iter().partition(|change| change.new.is_none());
+    for change in deletions {
+        workspace.remove_file(Path::new(&change.path))?;
+        index.remove(&change.path);
+    }
+    for (name, file) in writes
+        .into_iter()
+        .filter_map(|change| Some((change.path, change.new?)))
+    {
+        let path = Path::new(&name);
+        let mode = u32::from_str_radix(&file.mode, 8).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad mode {} for {}", file.mode, name),
+            )
+        })?;
+        let (_, data) = database.read_object(&file.object_id)?;
+        workspace.write_blob(path, data, mode)?;
+        let object_id = utils::hex_to_u8(&file.object_id).unwrap();
+        index.add(
+            path,
+            hash::raw_oid(&object_id)?,
+            workspace.symlink_metadata(path)?,
+        )?;
+    }
+    Ok(())
+}
+
+fn set_head(refs: &Refs, branch: &str) -> io::Result<()> {
+    refs.set_head_branch(branch)
+        .map_err(|err| io::Error::other(err.to_string()))
+}
+
+fn would_overwrite(what: &str, paths: &[&str], operation: &str, before: &str) -> io::Error {
+    let mut message = format!("{} would be overwritten by {}:\n", what, operation);
+    for path in paths {
+        message.push_str(&format!("\t{}\n", path));
+    }
+    message.push_str(&format!(
+        "Please commit your changes or stash them before you {}.",
+        before
+    ));
+    io::Error::other(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::test_support::TestRepo;
+
+    fn checkout(repo: &TestRepo, branch: &str) -> io::Result<()> {
+        let mut index = repo.index();
+        checkout_branch(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+            branch,
+        )?;
+        assert!(index.write_updates().unwrap());
+        Ok(())
+    }
+
+    fn read(repo: &TestRepo, path: &str) -> String {
+        fs::read_to_string(repo.root().join(path)).unwrap()
+    }
+
+    // Commits files on a new branch forked from the current commit.
+    fn fork(repo: &TestRepo, branch: &str) {
+        let head = repo.refs().read_head().unwrap();
+        repo.refs().create_branch(branch, &head).unwrap();
+        checkout(repo, branch).unwrap();
+    }
+
+    #[test]
+    fn switching_branches_updates_files_index_and_head() {
+        let repo = TestRepo::new();
+        repo.write("shared.txt", "shared\n");
+        repo.write("main.txt", "main\n");
+        repo.stage(&["."]);
+        repo.commit("main");
+
+        fork(&repo, "topic");
+        fs::remove_file(repo.root().join("main.txt")).unwrap();
+        let mut index = repo.index();
+        index.remove("main.txt");
+        assert!(index.write_updates().unwrap());
+        repo.write("shared.txt", "changed\n");
+        repo.write("topic.txt", "topic\n");
+        repo.stage(&["."]);
+        repo.commit("topic");
+
+        checkout(&repo, "main").unwrap();
+        assert_eq!(read(&repo, "shared.txt"), "shared\n");
+        assert_eq!(read(&repo, "main.txt"), "main\n");
+        assert!(!repo.root().join("topic.txt").exists());
+        assert_eq!(
+            repo.refs().current_branch().unwrap().as_deref(),
+            Some("main")
+        );
+        assert!(repo.is_clean());
+
+        checkout(&repo, "topic").unwrap();
+        assert_eq!(read(&repo, "shared.txt"), "changed\n");
+        assert_eq!(read(&repo, "topic.txt"), "topic\n");
+        assert!(!repo.root().join("main.txt").exists());
+        assert!(repo.is_clean());
+    }
+
+    #[test]
+    fn directory_and_file_of_the_same_name_swap_places() {
+        let repo = TestRepo::new();
+        repo.write("item/inner.txt", "inner\n");
+        repo.write("other/deep/x.txt", "x\n");
+        repo.stage(&["."]);
+        repo.commit("directories");
+
+        fork(&repo, "files");
+        for dir in ["item", "other"] {
+            fs::remove_dir_all(repo.root().join(dir)).unwrap();
+        }
+        let mut index = repo.index();
+        index.clear();
+        assert!(index.write_updates().unwrap());
+        repo.write("item", "now a file\n");
+        repo.write("other", "also a file\n");
+        repo.stage(&["."]);
+        repo.commit("files");
+
+        checkout(&repo, "main").unwrap();
+        assert_eq!(read(&repo, "item/inner.txt"), "inner\n");
+        assert_eq!(read(&repo, "other/deep/x.txt"), "x\n");
+        assert!(repo.is_clean());
+
+        checkout(&repo, "files").unwrap();
+        assert_eq!(read(&repo, "item"), "now a file\n");
+        assert_eq!(read(&repo, "other"), "also a file\n");
+        assert!(repo.is_clean());
+    }
+
+    #[test]
+    fn uncommitted_changes_block_the_switch() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "one\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("one");
+        fork(&repo, "topic");
+        repo.write("a.txt", "two\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("two");
+
+        repo.write("a.txt", "local\n");
+        let err = checkout(&repo, "main").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Your local changes to the following files would be overwritten by checkout:\n\ta.txt\n"));
+        assert_eq!(read(&repo, "a.txt"), "local\n");
+        assert_eq!(
+            repo.refs().current_branch().unwrap().as_deref(),
+            Some("topic")
+        );
+    }
+
+    #[test]
+    fn unborn_branch_only_rewrites_head() {
+        let repo = TestRepo::new();
+        checkout(&repo, "fresh").unwrap();
+        assert_eq!(
+            repo.refs().current_branch().unwrap().as_deref(),
+            Some("fresh")
+        );
+
+        repo.write("a.txt", "a\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("first");
+        let err = checkout(&repo, "missing").unwrap_err();
+        assert_eq!(err.to_string(), "invalid reference: missing");
+    }
+}
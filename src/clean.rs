@@ -0,0 +1,138 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::index::Index;
+use crate::status;
+use crate::workspace::Workspace;
+
+#[derive(Debug, Default)]
+pub struct CleanOptions {
+    // Only list what would be removed.
+    pub dry_run: bool,
+    // Also remove files that .gitignore excludes.
+    pub ignored: bool,
+    // Also remove untracked directories, not just untracked files in
+    // directories that hold tracked ones.
+    pub directories: bool,
+}
+
+/*
+    Removes the untracked files of the workspace, or with dry_run only
+    lists them, returning the removed paths sorted. Tracked files are never
+    touched, nor .git or anything inside a nested repository. Directories
+    left empty are removed along with their last file.
+*/
+pub fn clean(
+    workspace: &Workspace,
+    index: &mut Index,
+    options: &CleanOptions,
+) -> io::Result<Vec<String>> {
+    let mut untracked = if options.ignored {
+        let files = workspace.list_pathspecs(&[PathBuf::new()], true, None);
+        status::untracked_paths(files, index)
+    } else {
+        status::untracked_files(workspace, index)
+    };
+
+    // Every directory holding a tracked file, and so not untracked itself.
+    let mut tracked_dirs = HashSet::new();
+    for entry in index.each_entry() {
+        tracked_dirs.extend(
+            Path::new(entry.path())
+                .ancestors()
+                .skip(1)
+                .map(Path::to_path_buf),
+        );
+    }
+    untracked.retain(|path| {
+        let parents = Path::new(path).ancestors().skip(1).collect::<Vec<_>>();
+        let nested_repository = parents.iter().any(|dir| {
+            !dir.as_os_str().is_empty() && workspace.root().join(dir).join(".git").exists()
+        });
+        // The root is never an untracked directory, even with nothing tracked.
+        let in_untracked_dir = parents
+            .first()
+            .is_some_and(|dir| !dir.as_os_str().is_empty() && !tracked_dirs.contains(*dir));
+        !nested_repository && (options.directories || !in_untracked_dir)
+    });
+
+    if !options.dry_run {
+        for path in &untracked {
+            workspace.remove_file(Path::new(path))?;
+        }
+    }
+    Ok(untracked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::TestRepo;
+
+    // A repository with a tracked file, an untracked file next to it, an
+    // ignored file and an untracked directory.
+    fn repo() -> TestRepo {
+        let repo = TestRepo::new();
+        repo.write(".gitignore", "*.log\n");
+        repo.write("lib/tracked.txt", "tracked\n");
+        repo.stage(&["lib/tracked.txt"]);
+        repo.write("lib/untracked.txt", "untracked\n");
+        repo.write("debug.log", "ignored\n");
+        repo.write("scratch/notes.txt", "notes\n");
+        repo
+    }
+
+    fn clean_with(repo: &TestRepo, options: CleanOptions) -> Vec<String> {
+        clean(&repo.workspace(), &mut repo.index(), &options).unwrap()
+    }
+
+    #[test]
+    fn dry_run_lists_without_removing() {
+        let repo = repo();
+        let options = CleanOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        assert_eq!(clean_with(&repo, options), ["lib/untracked.txt"]);
+        assert!(repo.root().join("lib/untracked.txt").exists());
+    }
+
+    #[test]
+    fn removes_untracked_files_but_not_tracked_or_ignored_ones() {
+        let repo = repo();
+        assert_eq!(
+            clean_with(&repo, CleanOptions::default()),
+            ["lib/untracked.txt"]
+        );
+        assert!(!repo.root().join("lib/untracked.txt").exists());
+        assert!(repo.root().join("lib/tracked.txt").exists());
+        assert!(repo.root().join("debug.log").exists());
+        assert!(repo.root().join("scratch/notes.txt").exists());
+        assert!(repo.git_dir().join("HEAD").exists());
+    }
+
+    #[test]
+    fn ignored_files_and_directories_are_removed_on_request() {
+        let repo = repo();
+        let nested = repo.root().join("vendor/dep");
+        std::fs::create_dir_all(nested.join(".git")).unwrap();
+        repo.write("vendor/dep/file.txt", "nested\n");
+        let options = CleanOptions {
+            ignored: true,
+            directories: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            clean_with(&repo, options),
+            ["debug.log", "lib/untracked.txt", "scratch/notes.txt"]
+        );
+        // The emptied directory goes with its last file.
+        assert!(!repo.root().join("scratch").exists());
+        assert!(repo.root().join("lib/tracked.txt").exists());
+        assert!(nested.join("file.txt").exists());
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, IsTerminal};
+
+use crate::config::Config;
+
+pub const RESET: &str = "\x1b[m";
+pub const BOLD: &str = "\x1b[1m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const CYAN: &str = "\x1b[36m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Always,
+    Never,
+    // Color only when writing to a terminal.
+    Auto,
+}
+
+impl ColorMode {
+    /*
+        Parses a --color value or the color.ui setting, where true means
+        auto and false means never as in git.
+    */
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "always" => Some(ColorMode::Always),
+            "never" | "false" => Some(ColorMode::Never),
+            "auto" | "true" => Some(ColorMode::Auto),
+            _ => None,
+        }
+    }
+
+    /*
+        The mode chosen by the last --color flag in args, then by color.ui,
+        defaulting to auto. A bare --color means always.
+    */
+    pub fn from_args(args: &[String], config: &Config) -> Self {
+        let flag = args.iter().rev().find_map(|arg| match arg.as_str() {
+            "--color" => Some(ColorMode::Always),
+            "--no-color" => Some(ColorMode::Never),
+            _ => arg.strip_prefix("--color=").and_then(Self::parse),
+        });
+        flag.or_else(|| config.get("color.ui").and_then(Self::parse))
+            .unwrap_or(ColorMode::Auto)
+    }
+
+    // Whether output written to stdout should be colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => io::stdout().is_terminal(),
+        }
+    }
+}
+
+fn paint(color: &str, text: &str) -> String {
+    format!("{}{}{}", color, text, RESET)
+}
+
+/*
+    Colors a patch as git diff does: file headers bold, hunk ranges cyan,
+    added lines green and removed lines red. The text after a hunk range
+    and context lines stay plain.
+*/
+pub fn diff(patch: &str) -> String {
+    let mut out = String::new();
+    let mut in_header = false;
+    for line in patch.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        if text.starts_with("diff --git ") {
+            in_header = true;
+        }
+        let colored = if let Some(after) = text.strip_prefix("@@") {
+            in_header = false;
+            match after.find("@@") {
+                Some(end) => {
+                    let (range, rest) = text.split_at(end + 4);
+                    format!("{}{}", paint(CYAN, range), rest)
+                }
+                None => paint(CYAN, text),
+            }
+        } else if in_header {
+            paint(BOLD, text)
+        } else if text.starts_with('+') {
+            paint(GREEN, text)
+        } else if text.starts_with('-') {
+            paint(RED, text)
+        } else {
+            text.to_string()
+        };
+        out.push_str(&colored);
+        out.push_str(newline);
+    }
+    out
+}
+
+/*
+    Colors the long status format: the paths to be committed green, and
+    unstaged and untracked paths red. Section headings stay plain.
+*/
+pub fn status_long(status: &str) -> String {
+    let mut out = String::new();
+    let mut color = None;
+    for line in status.split_inclusive('\n') {
+        match line.trim_end() {
+            "Changes to be committed:" => color = Some(GREEN),
+            "Changes not staged for commit:" | "Untracked files:" => color = Some(RED),
+            _ => {}
+        }
+        match (line.strip_prefix('\t'), color) {
+            (Some(entry), Some(color)) => {
+                let text = entry.trim_end_matches('\n');
+                out.push_str(&format!("\t{}\n", paint(color, text)));
+            }
+            _ => out.push_str(line),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn last_flag_wins_over_config() {
+        let config = Config::default();
+        assert_eq!(
+            ColorMode::from_args(&args(&["diff"]), &config),
+            ColorMode::Auto
+        );
+        assert_eq!(
+            ColorMode::from_args(&args(&["diff", "--color=never", "--color"]), &config),
+            ColorMode::Always
+        );
+        assert_eq!(
+            ColorMode::from_args(&args(&["diff", "--color=always", "--no-color"]), &config),
+            ColorMode::Never
+        );
+        assert!(ColorMode::Always.enabled() && !ColorMode::Never.enabled());
+        assert_eq!(ColorMode::parse("bogus"), None);
+    }
+
+    #[test]
+    fn coloring_only_wraps_lines_in_escapes() {
+        let patch = "diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@ fn main\n same\n-old\n+new\n";
+        assert_eq!(
+            diff(patch),
+            format!(
+                "{BOLD}diff --git a/a.txt b/a.txt{RESET}\n{BOLD}--- a/a.txt{RESET}\n\
+                 {BOLD}+++ b/a.txt{RESET}\n{CYAN}@@ -1,2 +1,2 @@{RESET} fn main\n same\n\
+                 {RED}-old{RESET}\n{GREEN}+new{RESET}\n"
+            )
+        );
+        let status = "Changes to be committed:\n\tnew file:   a.txt\n\nUntracked files:\n\tb.txt\n";
+        assert_eq!(
+            status_long(status),
+            format!(
+                "Changes to be committed:\n\t{GREEN}new file:   a.txt{RESET}\n\n\
+                 Untracked files:\n\t{RED}b.txt{RESET}\n"
+            )
+        );
+    }
+}
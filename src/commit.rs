@@ -1,23 +1,161 @@
+use std::io;
+
 use crate::author::Author;
 use crate::traits::Object;
 use crate::utils;
 
-#[derive(Debug)]
+/*
+    Reads the hex tree object id from the "tree" header of a stored commit body.
+*/
+pub fn parse_tree_oid(data: &[u8]) -> Option<String> {
+    let first_line = data.split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(first_line).ok()?;
+    line.strip_prefix("tree ").map(String::from)
+}
+
+/*
+    Splits a stored commit body into its header fields, in order, and the
+    message that follows the first blank line. Header lines beginning with
+    a space continue the value of the previous header.
+*/
+pub fn parse_headers(data: &[u8]) -> (Vec<(String, String)>, String) {
+    let text = String::from_utf8_lossy(data);
+    let (header_text, message) = match text.split_once("\n\n") {
+        Some((headers, message)) => (headers, message),
+        None => (text.as_ref(), ""),
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in header_text.split('\n') {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push('\n');
+                value.push_str(continuation);
+            }
+        } else if let Some((key, value)) = line.split_once(' ') {
+            headers.push((key.to_string(), value.to_string()));
+        }
+    }
+    (headers, message.to_string())
+}
+
+/*
+    Parses a stored commit body. Headers other than tree, parent, author
+    and committer, such as a multiline gpgsig, are kept in order in
+    extra_headers. The object id is left empty since it isn't part of the
+    body.
+*/
+pub fn parse_commit(data: &[u8]) -> io::Result<Commit> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupt commit: {}", what),
+        )
+    };
+    let (headers, message) = parse_headers(data);
+    let header = |key: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let tree = header("tree").ok_or_else(|| invalid("missing tree"))?;
+    let author = header("author")
+        .and_then(Author::parse)
+        .ok_or_else(|| invalid("missing or malformed author"))?;
+    // Old commits may lack a committer, fall back to the author.
+    let committer = match header("committer") {
+        Some(committer) => {
+            Author::parse(committer).ok_or_else(|| invalid("malformed committer"))?
+        }
+        None => author.clone(),
+    };
+    let parents = headers
+        .iter()
+        .filter(|(key, _)| key == "parent")
+        .map(|(_, value)| value.clone())
+        .collect();
+    let extra_headers = headers
+        .iter()
+        .filter(|(key, _)| !["tree", "parent", "author", "committer"].contains(&key.as_str()))
+        .cloned()
+        .collect();
+
+    Ok(Commit {
+        tree: tree.to_string(),
+        parents,
+        author,
+        committer,
+        extra_headers,
+        message,
+        object_id: String::new(),
+    })
+}
+
+/*
+    Tidies a commit message the way git does: trailing whitespace is
+    removed from each line, runs of blank lines are collapsed, and leading
+    and trailing blank lines are dropped. Lines starting with "#" are
+    removed too when strip_comments is set, as for messages written in an
+    editor. A message left empty is returned as an empty string, otherwise
+    it ends with a newline.
+*/
+pub fn cleanup_message(message: &str, strip_comments: bool) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in message.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !out.is_empty() {
+            out.push('\n');
+        }
+        blank = false;
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
 pub struct Commit {
-    pub parent: String,
+    // Hex object ids of the tree and of every parent, in order.
+    pub tree: String,
+    pub parents: Vec<String>,
     pub author: Author,
+    pub committer: Author,
+    // Headers written after committer, such as gpgsig, kept verbatim so a
+    // parsed commit is stored back as the same bytes. The lines of a
+    // multiline value are joined with "\n".
+    pub extra_headers: Vec<(String, String)>,
     pub message: String,
     pub object_id: String,
-    pub tree_object_id: String,
 }
 
 impl Commit {
+    /*
+        Creates a commit of the tree with the given raw object id. parent is
+        a hex object id, or empty for a root commit.
+    */
     pub fn new(parent: String, tree_object_id: String, author: Author, message: String) -> Self {
+        let parents = if parent.is_empty() {
+            Vec::new()
+        } else {
+            vec![parent]
+        };
         Commit {
-            parent,
+            tree: utils::u8_to_hex_str(tree_object_id.into_bytes()),
+            parents,
+            committer: author.clone(),
             author,
+            extra_headers: Vec::new(),
             message,
-            tree_object_id,
             object_id: String::from(""),
         }
     }
@@ -25,20 +163,19 @@ impl Commit {
 
 impl Object for Commit {
     fn to_string(&self) -> String {
-        let u8 = self.tree_object_id.as_bytes();
-        let mut parent = String::from("");
-        if !self.parent.is_empty() {
-            parent = format!("parent {}\n", self.parent.clone());
+        let mut parents = String::new();
+        for parent in &self.parents {
+            parents.push_str(&format!("parent {}\n", parent));
+        }
+        let mut extra = String::new();
+        for (key, value) in &self.extra_headers {
+            extra.push_str(&format!("{} {}\n", key, value.replace('\n', "\n ")));
         }
         let content_str = format!(
-            "tree {}\n{}author {}\ncommitter {}\n{}",
-            utils::u8_to_hex_str(u8.to_vec()),
-            parent,
-            self.author,
-            self.author,
-            self.message
+            "tree {}\n{}author {}\ncommitter {}\n{}\n{}",
+            self.tree, parents, self.author, self.committer, extra, self.message
         );
-        format!("commit {}\0{}", content_str.bytes().len(), content_str)
+        format!("commit {}\0{}", content_str.len(), content_str)
     }
 
     fn get_object_id(&self) -> String {
@@ -49,3 +186,103 @@ impl Object for Commit {
         self.object_id = object_id;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::database::Database;
+    use crate::hash;
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    const PARENT: &str = "1111111111111111111111111111111111111111";
+    const OTHER: &str = "2222222222222222222222222222222222222222";
+    const IDENT: &str = "A U Thor <author@example.com> 1700000000 +0000";
+
+    #[test]
+    fn parses_root_commit() {
+        let data = format!(
+            "tree {}\nauthor {}\ncommitter C O Mitter <c@example.com> 1700000001 +0100\n\nfirst\n",
+            TREE, IDENT
+        );
+        let commit = parse_commit(data.as_bytes()).unwrap();
+        assert_eq!(commit.tree, TREE);
+        assert!(commit.parents.is_empty());
+        assert_eq!(commit.author.to_string(), IDENT);
+        assert_eq!(commit.committer.name, "C O Mitter");
+        assert_eq!(commit.committer.time, "1700000001 +0100");
+        assert_eq!(commit.message, "first\n");
+        assert!(commit.extra_headers.is_empty());
+    }
+
+    #[test]
+    fn parses_merge_commit_parents_in_order() {
+        let data = format!(
+            "tree {}\nparent {}\nparent {}\nauthor {}\ncommitter {}\n\nmerge\n",
+            TREE, PARENT, OTHER, IDENT, IDENT
+        );
+        let commit = parse_commit(data.as_bytes()).unwrap();
+        assert_eq!(commit.parents, [PARENT, OTHER]);
+    }
+
+    #[test]
+    fn multiline_signature_is_kept_aside_and_round_trips() {
+        let data = format!(
+            "tree {}\nparent {}\nauthor {}\ncommitter {}\ngpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEzBAAB\n -----END PGP SIGNATURE-----\n\nsigned\n",
+            TREE, PARENT, IDENT, IDENT
+        );
+        let commit = parse_commit(data.as_bytes()).unwrap();
+        assert_eq!(commit.parents, [PARENT]);
+        assert_eq!(commit.message, "signed\n");
+        assert_eq!(
+            commit.extra_headers,
+            [(
+                String::from("gpgsig"),
+                String::from(
+                    "-----BEGIN PGP SIGNATURE-----\n\niQEzBAAB\n-----END PGP SIGNATURE-----"
+                )
+            )]
+        );
+        assert_eq!(
+            commit.to_string(),
+            format!("commit {}\0{}", data.len(), data)
+        );
+    }
+
+    #[test]
+    fn signed_commit_is_stored_under_its_original_id() {
+        let dir = TempDir::new().unwrap();
+        let database = Database::new(dir.path().join("objects"));
+        let data = format!(
+            "tree {}\nauthor {}\ncommitter {}\ngpgsig -----BEGIN SSH SIGNATURE-----\n U1NIU0lH\n -----END SSH SIGNATURE-----\nmergetag object {}\n type commit\n\nsigned\n",
+            TREE, IDENT, IDENT, PARENT
+        );
+        let raw = format!("commit {}\0{}", data.len(), data);
+        let oid = utils::u8_to_hex_str(hash::oid_bytes(raw.as_bytes()).to_vec());
+
+        let mut commit = parse_commit(data.as_bytes()).unwrap();
+        assert_eq!(commit.extra_headers.len(), 2);
+        database.store(&mut commit).unwrap();
+        assert_eq!(utils::u8_to_hex_str(commit.object_id.into_bytes()), oid);
+        let (kind, stored) = database.read_object(&oid).unwrap();
+        assert_eq!((kind.as_str(), stored), ("commit", data.into_bytes()));
+    }
+
+    #[test]
+    fn empty_message_and_missing_committer() {
+        let data = format!("tree {}\nauthor {}\n", TREE, IDENT);
+        let commit = parse_commit(data.as_bytes()).unwrap();
+        assert_eq!(commit.message, "");
+        assert_eq!(commit.committer.to_string(), IDENT);
+    }
+
+    #[test]
+    fn commit_without_tree_is_corrupt() {
+        let data = format!("author {}\n\nmessage\n", IDENT);
+        let err = parse_commit(data.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "corrupt commit: missing tree");
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::HashMap, env, fs, io, path::Path};
+
+/*
+    A minimal reader for git config files. Keys are stored as
+    "section.key" or "section.subsection.key", with the section and key
+    lowercased since they are case insensitive.
+*/
+#[derive(Debug, Default)]
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    /*
+        Reads the global config from $HOME/.gitconfig and then the repository
+        config from .git/config, so repository values override global ones.
+    */
+    pub fn load(git_path: &Path) -> io::Result<Self> {
+        let mut config = Self::load_global()?;
+        config.read_file(&git_path.join("config"))?;
+        Ok(config)
+    }
+
+    /*
+        Loads only the global config, for use before a repository exists.
+    */
+    pub fn load_global() -> io::Result<Self> {
+        let mut config = Self::default();
+        if let Some(home) = env::var_os("HOME") {
+            config.read_file(&Path::new(&home).join(".gitconfig"))?;
+        }
+        Ok(config)
+    }
+
+    /*
+        Merges the values from the file at path into this config.
+        A missing file is ignored.
+    */
+    pub fn read_file(&mut self, path: &Path) -> io::Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        self.parse(&contents);
+        Ok(())
+    }
+
+    fn parse(&mut self, contents: &str) {
+        let mut section = String::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                // [section "subsection"] keeps the subsection case.
+                section = match header.split_once(' ') {
+                    Some((name, sub)) => {
+                        format!("{}.{}", name.to_lowercase(), sub.trim().trim_matches('"'))
+                    }
+                    None => header.to_lowercase(),
+                };
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                // A key without a value is shorthand for true.
+                None => (line, "true"),
+            };
+            let value = value.trim_matches('"');
+            self.values.insert(
+                format!("{}.{}", section, key.to_lowercase()),
+                value.to_string(),
+            );
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let (name, rest) = key.split_once('.')?;
+        let (sub, var) = match rest.rsplit_once('.') {
+            Some((sub, var)) => (format!("{}.", sub), var),
+            None => (String::new(), rest),
+        };
+        let key = format!("{}.{}{}", name.to_lowercase(), sub, var.to_lowercase());
+        self.values.get(&key).map(String::as_str)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
+        }
+    }
+}
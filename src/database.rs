@@ -1,37 +1,346 @@
-use std::{fs, io, path::PathBuf};
+use std::{cell::OnceCell, fs, io, os::unix::fs::PermissionsExt, path::PathBuf};
 
 use deflate::write::ZlibEncoder;
 use deflate::Compression;
-use std::io::Write;
+use flate2::read::ZlibDecoder;
+use std::io::{BufRead, Read, Write};
 use tempfile::NamedTempFile;
 
+use crate::hash;
+use crate::pack::{self, PackIndex};
 use crate::traits::Object;
 use crate::utils;
 
+// Well known ids of the empty blob and empty tree. These can be read
+// even when they were never written to the database.
+pub const EMPTY_BLOB_OID: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+pub const EMPTY_TREE_OID: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
 pub struct Database {
     pub path_buf: PathBuf,
+    // Objects actually written, so tests can check that writes are skipped.
+    #[cfg(test)]
+    writes: std::cell::Cell<usize>,
+    // Indexes of the packs in every object directory, read on first use.
+    packs: OnceCell<Vec<PackIndex>>,
+    // Calls to store, so tests can check that whole objects are reused.
+    #[cfg(test)]
+    pub(crate) stores: std::cell::Cell<usize>,
 }
 
 impl Database {
     pub fn new(path_buf: PathBuf) -> Self {
-        Database { path_buf }
+        Database {
+            path_buf,
+            packs: OnceCell::new(),
+            #[cfg(test)]
+            writes: std::cell::Cell::new(0),
+            #[cfg(test)]
+            stores: std::cell::Cell::new(0),
+        }
     }
 
+    /*
+        Hashes and stores the object, skipping the write entirely when an
+        object with the same id is already present in the database.
+    */
     pub fn store(&self, object: &mut dyn Object) -> io::Result<()> {
+        #[cfg(test)]
+        self.stores.set(self.stores.get() + 1);
         let content_str = object.to_string();
-        let content_hash = utils::hash_content(&content_str);
+        let content_hash = hash::oid_bytes(content_str.as_bytes()).to_vec();
         let content_hash_hex = utils::u8_to_hex_str(content_hash.clone());
         unsafe {
             object.set_object_id(String::from_utf8_unchecked(content_hash));
         }
+        if self.exists(&content_hash_hex) {
+            return Ok(());
+        }
         self.write_object(&content_hash_hex, content_str.as_bytes())?;
         Ok(())
     }
 
+    pub fn exists(&self, content_hash_hex: &str) -> bool {
+        fs::metadata(self.object_path(content_hash_hex)).is_ok()
+            || self.find_packed(content_hash_hex).is_some()
+    }
+
+    /*
+        The pack indexes under pack/ in each object directory. An index
+        that can't be read is skipped, as if its pack wasn't there.
+    */
+    fn packs(&self) -> &[PackIndex] {
+        self.packs.get_or_init(|| {
+            let mut packs = Vec::new();
+            for objects in self.object_dirs() {
+                let Ok(files) = fs::read_dir(objects.join("pack")) else {
+                    continue;
+                };
+                let mut paths = files
+                    .filter_map(|file| file.ok().map(|file| file.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+                    .collect::<Vec<_>>();
+                paths.sort();
+                packs.extend(paths.iter().filter_map(|path| PackIndex::open(path).ok()));
+            }
+            packs
+        })
+    }
+
+    // The pack holding an object and the offset of its entry.
+    fn find_packed(&self, content_hash_hex: &str) -> Option<(&PackIndex, u64)> {
+        self.packs()
+            .iter()
+            .find_map(|pack| pack.lookup(content_hash_hex).map(|offset| (pack, offset)))
+    }
+
+    /*
+        The object directories to search, this database first followed by
+        any listed in info/alternates. Relative alternates are resolved
+        against this database's directory.
+    */
+    fn object_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.path_buf.clone()];
+        if let Ok(alternates) = fs::read_to_string(self.path_buf.join("info").join("alternates")) {
+            for line in alternates.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                dirs.push(self.path_buf.join(line));
+            }
+        }
+        dirs
+    }
+
+    /*
+        The path of the object file with the given hex id in the first object
+        directory that has it. When none do, the path it would have in this
+        database is returned so errors name the primary location.
+    */
+    fn object_path(&self, content_hash_hex: &str) -> PathBuf {
+        let (dir, file) = utils::hash_to_path(content_hash_hex);
+        let local = self.path_buf.join(dir).join(file);
+        if local.exists() {
+            return local;
+        }
+        self.object_dirs()
+            .into_iter()
+            .skip(1)
+            .map(|objects| objects.join(dir).join(file))
+            .find(|path| path.exists())
+            .unwrap_or(local)
+    }
+
+    /*
+        Reads and inflates the object with the given hex object id, from
+        this database or one of its alternates, loose or packed.
+        Returns the object type from the header along with the body bytes.
+    */
+    pub fn read_object(&self, content_hash_hex: &str) -> io::Result<(String, Vec<u8>)> {
+        let path = self.object_path(content_hash_hex);
+        if !path.exists() {
+            if let Some((pack, offset)) = self.find_packed(content_hash_hex) {
+                return pack::read_entry(pack.pack_path(), offset, &|base| self.read_object(base));
+            }
+        }
+        let mut data = Vec::new();
+        let kind = self.read_loose_to(content_hash_hex, &mut data)?;
+        Ok((kind, data))
+    }
+
+    /*
+        Like read_object, but streams the body into writer as it is
+        inflated instead of collecting it, returning the object type. Only
+        packed objects, which may need their delta bases, are read in full
+        first.
+    */
+    pub fn read_object_to(
+        &self,
+        content_hash_hex: &str,
+        writer: &mut dyn Write,
+    ) -> io::Result<String> {
+        if !self.object_path(content_hash_hex).exists()
+            && self.find_packed(content_hash_hex).is_some()
+        {
+            let (kind, data) = self.read_object(content_hash_hex)?;
+            writer.write_all(&data)?;
+            return Ok(kind);
+        }
+        self.read_loose_to(content_hash_hex, writer)
+    }
+
+    // Inflates a loose object into writer, checking its declared size.
+    fn read_loose_to(&self, content_hash_hex: &str, writer: &mut dyn Write) -> io::Result<String> {
+        let file = match fs::File::open(self.object_path(content_hash_hex)) {
+            Ok(file) => file,
+            Err(_) if content_hash_hex == EMPTY_BLOB_OID => return Ok(String::from("blob")),
+            Err(_) if content_hash_hex == EMPTY_TREE_OID => return Ok(String::from("tree")),
+            Err(err) => return Err(err),
+        };
+        let mut decoder = ZlibDecoder::new(io::BufReader::new(file));
+        let (kind, size) = parse_header(&mut decoder)?;
+
+        // Never inflate past the declared size, so a small object can't
+        // expand into an arbitrarily large body.
+        let written = io::copy(&mut decoder.take(size as u64 + 1), writer)? as usize;
+        if written != size {
+            let length = if written > size { "longer" } else { "shorter" };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "object {} is {} than its declared size of {} bytes",
+                    content_hash_hex, length, size
+                ),
+            ));
+        }
+        Ok(kind)
+    }
+
+    /*
+        Reads only the "type size" header of the object with the given hex
+        object id, inflating no more of the stream than the header needs.
+    */
+    pub fn read_header(&self, content_hash_hex: &str) -> io::Result<(String, usize)> {
+        let path = self.object_path(content_hash_hex);
+        // Pack entries don't record the type of deltified objects, so
+        // packed objects are read in full.
+        if !path.exists() && self.find_packed(content_hash_hex).is_some() {
+            let (kind, data) = self.read_object(content_hash_hex)?;
+            return Ok((kind, data.len()));
+        }
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(_) if content_hash_hex == EMPTY_BLOB_OID => return Ok((String::from("blob"), 0)),
+            Err(_) if content_hash_hex == EMPTY_TREE_OID => return Ok((String::from("tree"), 0)),
+            Err(err) => return Err(err),
+        };
+        parse_header(ZlibDecoder::new(io::BufReader::new(file)))
+    }
+
+    /*
+        The cat-file --batch protocol. Each line of input names an object by
+        its full or abbreviated id and is answered with "<oid> <type> <size>",
+        the content and a newline, or with "<name> missing" when no object
+        matches. Output is flushed after every object, so a caller can wait
+        for each answer before asking the next. Without contents only the
+        header line is written, as --batch-check does, and only the header of
+        loose objects is inflated.
+    */
+    pub fn cat_file_batch<R: BufRead, W: Write>(
+        &self,
+        input: R,
+        output: &mut W,
+        contents: bool,
+    ) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            let name = line.trim();
+            let oid = match self.expand_oid(name) {
+                Ok(oid) => oid,
+                Err(_) => {
+                    writeln!(output, "{} missing", name)?;
+                    output.flush()?;
+                    continue;
+                }
+            };
+            let (kind, size) = self.read_header(&oid)?;
+            writeln!(output, "{} {} {}", oid, kind, size)?;
+            if contents {
+                self.read_object_to(&oid, output)?;
+                writeln!(output)?;
+            }
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /*
+        Resolves an abbreviated hex object id to the full id of the single
+        stored object it prefixes, searching alternates as well. Errors if no object or several objects match.
+    */
+    pub fn expand_oid(&self, prefix: &str) -> io::Result<String> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < 4 || prefix.len() > 40 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a valid object name {}", prefix),
+            ));
+        }
+
+        let (dir, file_prefix) = utils::hash_to_path(&prefix);
+        let mut matches = Vec::new();
+        for pack in self.packs() {
+            for oid in pack.oids() {
+                if oid.starts_with(&prefix) && !matches.contains(&oid) {
+                    matches.push(oid);
+                }
+            }
+        }
+        for objects in self.object_dirs() {
+            if let Ok(files) = fs::read_dir(objects.join(dir)) {
+                for file in files {
+                    let name = file?.file_name();
+                    let name = name.to_string_lossy();
+                    let oid = format!("{}{}", dir, name);
+                    if name.starts_with(file_prefix) && !matches.contains(&oid) {
+                        matches.push(oid);
+                    }
+                }
+            }
+        }
+
+        match matches.len() {
+            0 => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no object matches {}", prefix),
+            )),
+            1 => Ok(matches.remove(0)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("short object id {} is ambiguous", prefix),
+            )),
+        }
+    }
+
+    /*
+        Lists the hex ids of every loose object in this database, not
+        including alternates, sorted.
+    */
+    pub fn list_objects(&self) -> io::Result<Vec<String>> {
+        let mut oids = Vec::new();
+        for dir in fs::read_dir(&self.path_buf)? {
+            let dir = dir?;
+            let prefix = dir.file_name().to_string_lossy().to_string();
+            if prefix.len() != 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let name = file?.file_name().to_string_lossy().to_string();
+                if name.len() == 38 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                    oids.push(format!("{}{}", prefix, name));
+                }
+            }
+        }
+        oids.sort();
+        Ok(oids)
+    }
+
+    /*
+        Removes the loose copy of an object, along with its fan-out
+        directory once that is empty.
+    */
+    pub fn remove_loose(&self, content_hash_hex: &str) -> io::Result<()> {
+        let (dir, file) = utils::hash_to_path(content_hash_hex);
+        fs::remove_file(self.path_buf.join(dir).join(file))?;
+        let _ = fs::remove_dir(self.path_buf.join(dir));
+        Ok(())
+    }
+
     pub fn write_object(&self, content_hash_hex: &str, content: &[u8]) -> io::Result<()> {
         let (dir, file) = utils::hash_to_path(content_hash_hex);
         let object_path = self.path_buf.join(dir);
-        if fs::metadata(object_path.join(file)).is_ok() {
+        if self.exists(content_hash_hex) {
             return Ok(());
         }
         let temp_file = NamedTempFile::new()?;
@@ -39,9 +348,395 @@ impl Database {
         encoder.write_all(content).expect("Write error!");
         let compressed_data = encoder.finish().expect("Failed to compress object");
         fs::write(&temp_file, compressed_data).expect("Unable to write object");
+        // Objects are immutable, so they are stored read-only like git does.
+        fs::set_permissions(temp_file.path(), fs::Permissions::from_mode(0o444))?;
         fs::create_dir_all(&object_path)?;
         fs::rename(temp_file.path(), object_path.join(file))?;
+        #[cfg(test)]
+        self.writes.set(self.writes.get() + 1);
 
         Ok(())
     }
 }
+
+// Longest header we accept, "commit " plus a 20 digit size and the NUL.
+const MAX_HEADER_SIZE: usize = 32;
+
+/*
+    Reads a "type size\0" object header from an inflated object stream,
+    leaving the reader positioned at the start of the body.
+*/
+pub fn parse_header<R: Read>(mut reader: R) -> io::Result<(String, usize)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt object header");
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == 0 {
+            break;
+        }
+        header.push(byte[0]);
+        if header.len() > MAX_HEADER_SIZE {
+            return Err(invalid());
+        }
+    }
+    let header = String::from_utf8(header).map_err(|_| invalid())?;
+    let (kind, size) = header.split_once(' ').ok_or_else(invalid)?;
+    let size = size.parse::<usize>().map_err(|_| invalid())?;
+    Ok((kind.to_string(), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha1::{Digest, Sha1};
+    use tempfile::TempDir;
+
+    use crate::blob::Blob;
+    use crate::entry::Entry;
+    use crate::tree::Tree;
+
+    fn database() -> (TempDir, Database) {
+        let dir = TempDir::new().unwrap();
+        let database = Database::new(dir.path().join("objects"));
+        (dir, database)
+    }
+
+    #[test]
+    fn expand_oid_resolves_unique_prefix() {
+        let (_dir, database) = database();
+        let oid = "abcd0123456789abcdef0123456789abcdef0123";
+        database.write_object(oid, b"blob 0\0").unwrap();
+        database
+            .write_object("abce0123456789abcdef0123456789abcdef0123", b"blob 0\0")
+            .unwrap();
+        assert_eq!(database.expand_oid("abcd").unwrap(), oid);
+        assert_eq!(database.expand_oid("ABCD01").unwrap(), oid);
+        assert_eq!(database.expand_oid(oid).unwrap(), oid);
+    }
+
+    #[test]
+    fn cat_file_batch_reports_missing_objects() {
+        let (_dir, database) = database();
+        for data in ["hello\n", "world\n"] {
+            database.store(&mut Blob::new(data)).unwrap();
+        }
+        let a = "ce013625030ba8dba906f756967f9e9ca394464a";
+        let b = "cc628ccd10742baea8241c5924df992b5c019f71";
+        let bogus = "0000000000000000000000000000000000000bad";
+        let input = format!("{}\n{}\n{}\n", a, bogus, &b[..7]);
+
+        let mut out = Vec::new();
+        database
+            .cat_file_batch(input.as_bytes(), &mut out, true)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{} blob 6\nhello\n\n{} missing\n{} blob 6\nworld\n\n",
+                a, bogus, b
+            )
+        );
+
+        let mut out = Vec::new();
+        database
+            .cat_file_batch(input.as_bytes(), &mut out, false)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{} blob 6\n{} missing\n{} blob 6\n", a, bogus, b)
+        );
+    }
+
+    #[test]
+    fn expand_oid_rejects_ambiguous_prefix() {
+        let (_dir, database) = database();
+        database
+            .write_object("abcd0123456789abcdef0123456789abcdef0123", b"blob 0\0")
+            .unwrap();
+        database
+            .write_object("abcd9876543210abcdef0123456789abcdef0123", b"blob 0\0")
+            .unwrap();
+        let err = database.expand_oid("abcd").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "short object id abcd is ambiguous");
+        assert!(database.expand_oid("abcd0").is_ok());
+    }
+
+    #[test]
+    fn expand_oid_reports_missing_prefix() {
+        let (_dir, database) = database();
+        database
+            .write_object("abcd0123456789abcdef0123456789abcdef0123", b"blob 0\0")
+            .unwrap();
+        let err = database.expand_oid("0123").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(database.expand_oid("abc").is_err());
+        assert!(database.expand_oid("abcz").is_err());
+    }
+
+    #[test]
+    fn identical_subtrees_are_stored_once() {
+        let (_dir, database) = database();
+        let mut blob = Blob::new("");
+        database.store(&mut blob).unwrap();
+        let entries = || {
+            ["a/file.txt", "b/file.txt"]
+                .into_iter()
+                .map(|path| {
+                    Entry::new(
+                        String::from("file.txt"),
+                        PathBuf::from(path),
+                        &blob.object_id,
+                        0o100644,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The blob, the root and one tree shared by a and b.
+        let mut tree = Tree::build(entries());
+        tree.store_tree(&database);
+        assert_eq!(database.writes.get(), 3);
+
+        let mut tree = Tree::build(entries());
+        tree.store_tree(&database);
+        assert_eq!(database.writes.get(), 3);
+    }
+
+    #[test]
+    fn empty_objects_have_well_known_ids() {
+        let (_dir, database) = database();
+        assert_eq!(
+            database.read_object(EMPTY_BLOB_OID).unwrap(),
+            (String::from("blob"), Vec::new())
+        );
+        assert_eq!(
+            database.read_object(EMPTY_TREE_OID).unwrap(),
+            (String::from("tree"), Vec::new())
+        );
+
+        let mut blob = Blob::new("");
+        database.store(&mut blob).unwrap();
+        assert_eq!(
+            utils::u8_to_hex_str(blob.object_id.into_bytes()),
+            EMPTY_BLOB_OID
+        );
+
+        let mut tree = Tree::new();
+        database.store(&mut tree).unwrap();
+        assert_eq!(
+            utils::u8_to_hex_str(tree.object_id.into_bytes()),
+            EMPTY_TREE_OID
+        );
+        assert_eq!(
+            database.read_header(EMPTY_TREE_OID).unwrap(),
+            (String::from("tree"), 0)
+        );
+    }
+
+    // Counts the bytes read from the wrapped reader.
+    struct CountingReader<R> {
+        inner: R,
+        consumed: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.consumed += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn header_is_read_without_inflating_the_body() {
+        // Noise, so the compressed object stays about as large as the blob.
+        let mut state = 1u32;
+        let body = (0..1 << 20)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect::<Vec<_>>();
+        let mut object = format!("blob {}\0", body.len()).into_bytes();
+        object.extend_from_slice(&body);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Fast);
+        encoder.write_all(&object).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut counting = CountingReader {
+            inner: compressed.as_slice(),
+            consumed: 0,
+        };
+        let header = parse_header(ZlibDecoder::new(&mut counting)).unwrap();
+        assert_eq!(header, (String::from("blob"), 1 << 20));
+        assert!(
+            counting.consumed < compressed.len() / 8,
+            "read {} of {} compressed bytes",
+            counting.consumed,
+            compressed.len()
+        );
+
+        let (_dir, database) = database();
+        let oid = utils::u8_to_hex_str(Sha1::digest(&object).to_vec());
+        database.write_object(&oid, &object).unwrap();
+        assert_eq!(database.read_header(&oid).unwrap(), header);
+    }
+
+    #[test]
+    fn objects_are_read_from_alternates() {
+        let dir = TempDir::new().unwrap();
+        let shared = Database::new(dir.path().join("shared"));
+        let mut blob = Blob::new("shared\n");
+        shared.store(&mut blob).unwrap();
+        let oid = utils::u8_to_hex_str(blob.object_id.into_bytes());
+
+        let primary = dir.path().join("objects");
+        fs::create_dir_all(primary.join("info")).unwrap();
+        fs::write(
+            primary.join("info").join("alternates"),
+            "# shared store\n../shared\n",
+        )
+        .unwrap();
+        let database = Database::new(primary.clone());
+        assert!(database.exists(&oid));
+        assert_eq!(
+            database.read_object(&oid).unwrap(),
+            (String::from("blob"), b"shared\n".to_vec())
+        );
+        assert_eq!(database.expand_oid(&oid[..7]).unwrap(), oid);
+
+        // New objects are written to the primary only.
+        let mut blob = Blob::new("local\n");
+        database.store(&mut blob).unwrap();
+        let oid = utils::u8_to_hex_str(blob.object_id.into_bytes());
+        let (subdir, file) = utils::hash_to_path(&oid);
+        assert!(primary.join(subdir).join(file).exists());
+        assert!(!shared.exists(&oid));
+    }
+
+    #[test]
+    fn stored_objects_are_read_only() {
+        let (dir, database) = database();
+        let mut blob = Blob::new("immutable\n");
+        database.store(&mut blob).unwrap();
+        let oid = utils::u8_to_hex_str(blob.object_id.clone().into_bytes());
+        let (subdir, file) = utils::hash_to_path(&oid);
+        let path = dir.path().join("objects").join(subdir).join(file);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o444);
+
+        // Storing it again is a no-op rather than a failed overwrite.
+        database.store(&mut blob).unwrap();
+        assert_eq!(database.writes.get(), 1);
+        assert_eq!(database.read_object(&oid).unwrap().1, b"immutable\n");
+    }
+
+    #[test]
+    fn object_longer_than_declared_is_rejected_without_inflating_it() {
+        let (_dir, database) = database();
+        let oid = "abcd0123456789abcdef0123456789abcdef0123";
+        let mut content = b"blob 3\0".to_vec();
+        content.extend(std::iter::repeat_n(b'x', 1 << 20));
+        database.write_object(oid, &content).unwrap();
+
+        let err = database.read_object(oid).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            format!("object {} is longer than its declared size of 3 bytes", oid)
+        );
+        // At most one byte past the declared size is ever inflated.
+        let mut body = Vec::new();
+        assert!(database.read_object_to(oid, &mut body).is_err());
+        assert_eq!(body.len(), 4);
+    }
+
+    #[test]
+    fn object_shorter_than_declared_is_rejected() {
+        let (_dir, database) = database();
+        let oid = "abcd0123456789abcdef0123456789abcdef0123";
+        database.write_object(oid, b"blob 10\0short").unwrap();
+        let err = database.read_object(oid).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "object {} is shorter than its declared size of 10 bytes",
+                oid
+            )
+        );
+    }
+
+    #[test]
+    fn object_only_in_a_pack_is_readable() {
+        let (dir, database) = database();
+        let mut blob = Blob::new("packed\n");
+        database.store(&mut blob).unwrap();
+        let oid = utils::u8_to_hex_str(blob.object_id.into_bytes());
+        pack::write_pack(
+            &database,
+            std::slice::from_ref(&oid),
+            &database.path_buf.join("pack"),
+        )
+        .unwrap();
+        database.remove_loose(&oid).unwrap();
+        assert!(!database.object_path(&oid).exists());
+
+        // The pack indexes are read on first use, so open a fresh database.
+        let database = Database::new(dir.path().join("objects"));
+        assert!(database.exists(&oid));
+        assert_eq!(
+            database.read_object(&oid).unwrap(),
+            (String::from("blob"), b"packed\n".to_vec())
+        );
+        assert_eq!(
+            database.read_header(&oid).unwrap(),
+            (String::from("blob"), 7)
+        );
+        let mut body = Vec::new();
+        assert_eq!(database.read_object_to(&oid, &mut body).unwrap(), "blob");
+        assert_eq!(body, b"packed\n");
+        assert_eq!(database.expand_oid(&oid[..8]).unwrap(), oid);
+    }
+
+    // Checks streamed bytes against the expected content as they arrive.
+    struct Expecting<'a> {
+        rest: &'a [u8],
+        largest_write: usize,
+    }
+
+    impl Write for Expecting<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            assert!(self.rest.starts_with(buf));
+            self.rest = &self.rest[buf.len()..];
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn large_blob_is_streamed_in_pieces() {
+        let (_dir, database) = database();
+        let content = (0..200_000)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let mut blob = Blob::new(&content);
+        database.store(&mut blob).unwrap();
+        let oid = utils::u8_to_hex_str(blob.object_id.into_bytes());
+
+        let mut writer = Expecting {
+            rest: content.as_bytes(),
+            largest_write: 0,
+        };
+        assert_eq!(database.read_object_to(&oid, &mut writer).unwrap(), "blob");
+        assert!(writer.rest.is_empty());
+        // The body never reached the writer as one buffer.
+        assert!(writer.largest_write < content.len() / 10);
+    }
+}
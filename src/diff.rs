@@ -0,0 +1,611 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::Path,
+};
+
+use crate::database::Database;
+use crate::hash;
+use crate::index::{self, Index};
+use crate::refs::Refs;
+use crate::tree::{self, TreeEntry};
+use crate::utils;
+use crate::workspace::Workspace;
+
+// Lines of unchanged context shown around each change.
+const CONTEXT: usize = 3;
+const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    // Indices into the old and new line lists.
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/*
+    Computes a shortest edit script turning a into b with Myers' algorithm.
+*/
+pub fn diff_lines(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; (2 * max + 3) as usize];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk the trace backwards to recover the path.
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal((x - 1) as usize, (y - 1) as usize));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert((y - 1) as usize));
+            } else {
+                edits.push(Edit::Delete((x - 1) as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/*
+    Groups an edit script into unified diff hunks, each beginning with its
+    "@@ -a,b +c,d @@" header. Lines keep their trailing newline, a final
+    line without one is followed by git's "No newline" marker.
+*/
+pub fn unified_hunks(a: &[&str], b: &[&str], edits: &[Edit]) -> Vec<String> {
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+
+    let mut hunks = Vec::new();
+    let mut c = 0;
+    while c < changes.len() {
+        // Extend the hunk while the next change is within reach of the context.
+        let start = changes[c].saturating_sub(CONTEXT);
+        let mut last = changes[c];
+        while c + 1 < changes.len() && changes[c + 1] - last - 1 <= 2 * CONTEXT {
+            c += 1;
+            last = changes[c];
+        }
+        let end = (last + CONTEXT + 1).min(edits.len());
+        c += 1;
+
+        let a_before = edits[..start]
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert(_)))
+            .count();
+        let b_before = edits[..start]
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Delete(_)))
+            .count();
+        let mut body = String::new();
+        let (mut a_len, mut b_len) = (0, 0);
+        for edit in &edits[start..end] {
+            let (prefix, line) = match *edit {
+                Edit::Equal(i, _) => {
+                    a_len += 1;
+                    b_len += 1;
+                    (' ', a[i])
+                }
+                Edit::Delete(i) => {
+                    a_len += 1;
+                    ('-', a[i])
+                }
+                Edit::Insert(j) => {
+                    b_len += 1;
+                    ('+', b[j])
+                }
+            };
+            body.push(prefix);
+            body.push_str(line);
+            if !line.ends_with('\n') {
+                body.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        hunks.push(format!(
+            "@@ -{} +{} @@\n{}",
+            hunk_range(a_before, a_len),
+            hunk_range(b_before, b_len),
+            body
+        ));
+    }
+    hunks
+}
+
+fn hunk_range(before: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", before),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{}", before + 1, len),
+    }
+}
+
+/*
+    One side of a file diff. Missing sides are represented by None.
+*/
+#[derive(Debug, Clone)]
+pub struct DiffTarget {
+    pub path: String,
+    pub mode: String,
+    pub oid: String,
+    pub data: Vec<u8>,
+}
+
+/*
+    Formats a git style patch between old and new versions of one file.
+*/
+pub fn format_diff(old: Option<&DiffTarget>, new: Option<&DiffTarget>) -> String {
+    let path = match (old, new) {
+        (_, Some(target)) | (Some(target), None) => target.path.as_str(),
+        (None, None) => return String::new(),
+    };
+    let mut out = format!("diff --git a/{} b/{}\n", path, path);
+
+    match (old, new) {
+        (None, Some(new)) => out.push_str(&format!("new file mode {}\n", new.mode)),
+        (Some(old), None) => out.push_str(&format!("deleted file mode {}\n", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            out.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode))
+        }
+        _ => {}
+    }
+
+    let old_oid = old.map_or(NULL_OID, |target| target.oid.as_str());
+    let new_oid = new.map_or(NULL_OID, |target| target.oid.as_str());
+    if old_oid == new_oid {
+        return out;
+    }
+    out.push_str(&format!(
+        "index {}..{}",
+        utils::oid_abbrev(old_oid, 7),
+        utils::oid_abbrev(new_oid, 7)
+    ));
+    match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => out.push_str(&format!(" {}\n", old.mode)),
+        _ => out.push('\n'),
+    }
+
+    let a_name = old.map_or(String::from("/dev/null"), |t| format!("a/{}", t.path));
+    let b_name = new.map_or(String::from("/dev/null"), |t| format!("b/{}", t.path));
+    let old_data = old.map_or(&[][..], |target| &target.data[..]);
+    let new_data = new.map_or(&[][..], |target| &target.data[..]);
+    if old_data.contains(&0) || new_data.contains(&0) {
+        out.push_str(&format!("Binary files {} and {} differ\n", a_name, b_name));
+        return out;
+    }
+
+    out.push_str(&format!("--- {}\n+++ {}\n", a_name, b_name));
+    let old_text = String::from_utf8_lossy(old_data);
+    let new_text = String::from_utf8_lossy(new_data);
+    let a = old_text.split_inclusive('\n').collect::<Vec<&str>>();
+    let b = new_text.split_inclusive('\n').collect::<Vec<&str>>();
+    for hunk in unified_hunks(&a, &b, &diff_lines(&a, &b)) {
+        out.push_str(&hunk);
+    }
+    out
+}
+
+/*
+    A path whose entry differs between two trees.
+*/
+#[derive(Debug, Clone)]
+pub struct TreeChange {
+    pub path: String,
+    pub old: Option<TreeEntry>,
+    pub new: Option<TreeEntry>,
+    // The path old was found at when the change is a rename to path.
+    pub old_path: Option<String>,
+}
+
+/*
+    Compares two trees by hex object id, either of which may be absent,
+    returning every changed file sorted by path.
+*/
+pub fn diff_trees(
+    database: &Database,
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+) -> io::Result<Vec<TreeChange>> {
+    let old_files = match old_tree {
+        Some(oid) => tree::flatten_tree(database, oid)?,
+        None => Default::default(),
+    };
+    let new_files = match new_tree {
+        Some(oid) => tree::flatten_tree(database, oid)?,
+        None => Default::default(),
+    };
+    Ok(diff_file_maps(&old_files, &new_files))
+}
+
+/*
+    Compares two sets of files keyed by path, as produced by flatten_tree.
+    Only the mode and object id of each file are compared.
+*/
+pub fn diff_file_maps(
+    old_files: &BTreeMap<String, TreeEntry>,
+    new_files: &BTreeMap<String, TreeEntry>,
+) -> Vec<TreeChange> {
+    let paths = old_files
+        .keys()
+        .chain(new_files.keys())
+        .collect::<BTreeSet<_>>();
+    let mut changes = Vec::new();
+    for path in paths {
+        let old = old_files.get(path);
+        let new = new_files.get(path);
+        let unchanged = matches!((old, new), (Some(o), Some(n)) if o.object_id == n.object_id && o.mode == n.mode);
+        if !unchanged {
+            changes.push(TreeChange {
+                path: path.clone(),
+                old: old.cloned(),
+                new: new.cloned(),
+                old_path: None,
+            });
+        }
+    }
+    changes
+}
+
+/*
+    Pairs deleted and added files with identical object ids as exact
+    renames, returning (old path, new path) pairs. Each side is a list of
+    (path, object id). A deleted file goes to at most one added file,
+    preferably one with the same file name, so a moved file is paired
+    with its move rather than with an unrelated copy of its content.
+*/
+pub fn pair_exact_renames(
+    deleted: &[(&str, &str)],
+    added: &[(&str, &str)],
+) -> Vec<(String, String)> {
+    let file_name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+    let mut unpaired = deleted.to_vec();
+    let mut pairs = Vec::new();
+    // Same file name first, then any path with the same content.
+    for same_name in [true, false] {
+        for (new_path, new_oid) in added {
+            if pairs
+                .iter()
+                .any(|(_, paired): &(String, String)| paired == new_path)
+            {
+                continue;
+            }
+            let found = unpaired.iter().position(|(old_path, old_oid)| {
+                old_oid == new_oid && (!same_name || file_name(old_path) == file_name(new_path))
+            });
+            if let Some(i) = found {
+                pairs.push((unpaired.remove(i).0.to_string(), new_path.to_string()));
+            }
+        }
+    }
+    pairs
+}
+
+/*
+    Replaces each deleted and added pair of identical files among changes
+    with a single rename change, keeping the result sorted by path.
+*/
+pub fn detect_renames(changes: Vec<TreeChange>) -> Vec<TreeChange> {
+    let side = |deleted: bool| {
+        changes
+            .iter()
+            .filter_map(|change| match (&change.old, &change.new) {
+                (Some(old), None) if deleted => {
+                    Some((change.path.as_str(), old.object_id.as_str()))
+                }
+                (None, Some(new)) if !deleted => {
+                    Some((change.path.as_str(), new.object_id.as_str()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let pairs = pair_exact_renames(&side(true), &side(false));
+    if pairs.is_empty() {
+        return changes;
+    }
+
+    let mut olds = BTreeMap::new();
+    let mut result = Vec::new();
+    for change in changes {
+        if pairs.iter().any(|(old_path, _)| *old_path == change.path) {
+            olds.insert(change.path.clone(), change.old);
+        } else {
+            result.push(change);
+        }
+    }
+    for change in &mut result {
+        if let Some((old_path, _)) = pairs.iter().find(|(_, new_path)| *new_path == change.path) {
+            change.old = olds.remove(old_path).flatten();
+            change.old_path = Some(old_path.clone());
+        }
+    }
+    result
+}
+
+/*
+    The staged changes: the patch from the tree of HEAD to the index, as
+    shown by diff --cached. Blobs are only read for paths whose object id
+    or mode differ.
+*/
+pub fn diff_head_index(database: &Database, refs: &Refs, index: &mut Index) -> io::Result<String> {
+    let head_files = tree::flatten_head_tree(database, refs)?;
+    let index_files = index
+        .each_entry()
+        .into_iter()
+        .filter(|entry| !entry.intent_to_add())
+        .map(|entry| {
+            let file = TreeEntry {
+                mode: format!("{:o}", entry.mode()),
+                name: entry.path().to_string(),
+                object_id: utils::u8_to_hex_str(entry.oid().to_vec()),
+            };
+            (entry.path().to_string(), file)
+        })
+        .collect::<BTreeMap<String, TreeEntry>>();
+    let changes = detect_renames(diff_file_maps(&head_files, &index_files));
+    format_tree_changes(database, &changes)
+}
+
+/*
+    The unstaged changes: the patch from the index to the working tree.
+    Files whose stat still matches the index are assumed unchanged, and
+    skip-worktree entries are never compared.
+*/
+pub fn diff_index_worktree(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+) -> io::Result<String> {
+    let mut out = String::new();
+    for entry in index.each_entry() {
+        if entry.skip_worktree() {
+            continue;
+        }
+        let old = DiffTarget {
+            path: entry.path().to_string(),
+            mode: format!("{:o}", entry.mode()),
+            oid: utils::u8_to_hex_str(entry.oid().to_vec()),
+            data: Vec::new(),
+        };
+        let path = Path::new(entry.path());
+        // A file replaced by a directory has no content left to compare.
+        let stat = match workspace.symlink_metadata(path) {
+            Ok(stat) if !(stat.is_dir() && entry.type_changed(&stat)) => stat,
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {
+                let old = with_blob(database, old)?;
+                out.push_str(&format_diff(Some(&old), None));
+                continue;
+            }
+        };
+        if entry.stat_matches(&stat) && !entry.intent_to_add() && !index.is_racily_clean(&entry) {
+            continue;
+        }
+
+        let data = workspace.read_blob(path)?;
+        let mut blob = format!("blob {}\0", data.len()).into_bytes();
+        blob.extend_from_slice(&data);
+        let new = DiffTarget {
+            path: entry.path().to_string(),
+            mode: format!("{:o}", index::mode_bits(&stat)),
+            oid: hash::oid_hex(&blob),
+            data,
+        };
+        if new.oid == old.oid && new.mode == old.mode {
+            continue;
+        }
+        // An intent-to-add entry shows up as a new file.
+        if entry.intent_to_add() {
+            out.push_str(&format_diff(None, Some(&new)));
+        } else {
+            let old = with_blob(database, old)?;
+            out.push_str(&format_diff(Some(&old), Some(&new)));
+        }
+    }
+    Ok(out)
+}
+
+// Fills in the content of a target from its blob.
+fn with_blob(database: &Database, mut target: DiffTarget) -> io::Result<DiffTarget> {
+    target.data = database.read_object(&target.oid)?.1;
+    Ok(target)
+}
+
+/*
+    Formats the patch for each tree change, reading blobs from the database.
+    Renames have identical content, so only their header is shown.
+*/
+pub fn format_tree_changes(database: &Database, changes: &[TreeChange]) -> io::Result<String> {
+    let target = |entry: &Option<TreeEntry>, path: &str| -> io::Result<Option<DiffTarget>> {
+        match entry {
+            Some(entry) => Ok(Some(DiffTarget {
+                path: path.to_string(),
+                mode: entry.mode.clone(),
+                oid: entry.object_id.clone(),
+                data: database.read_object(&entry.object_id)?.1,
+            })),
+            None => Ok(None),
+        }
+    };
+
+    let mut out = String::new();
+    for change in changes {
+        if let (Some(old_path), Some(old), Some(new)) = (&change.old_path, &change.old, &change.new)
+        {
+            out.push_str(&format!("diff --git a/{} b/{}\n", old_path, change.path));
+            if old.mode != new.mode {
+                out.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode));
+            }
+            out.push_str(&format!(
+                "similarity index 100%\nrename from {}\nrename to {}\n",
+                old_path, change.path
+            ));
+            continue;
+        }
+        let old = target(&change.old, &change.path)?;
+        let new = target(&change.new, &change.path)?;
+        out.push_str(&format_diff(old.as_ref(), new.as_ref()));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs, os::unix::fs::symlink};
+
+    use crate::test_support::TestRepo;
+
+    fn worktree_diff(repo: &TestRepo) -> String {
+        diff_index_worktree(&repo.workspace(), &repo.database(), &mut repo.index()).unwrap()
+    }
+
+    #[test]
+    fn symlink_diff_compares_target_paths() {
+        let repo = TestRepo::new();
+        repo.write("target.txt", "one\n");
+        symlink("target.txt", repo.root().join("link")).unwrap();
+        repo.stage(&["link"]);
+        repo.commit("initial");
+
+        // Only the link is tracked, so editing its target shows nothing.
+        repo.write("target.txt", "two\n");
+        assert_eq!(worktree_diff(&repo), "");
+
+        fs::remove_file(repo.root().join("link")).unwrap();
+        symlink("other.txt", repo.root().join("link")).unwrap();
+        let diff = worktree_diff(&repo);
+        assert!(diff.starts_with("diff --git a/link b/link\n"), "{}", diff);
+        assert!(
+            diff.contains("-target.txt\n\\ No newline at end of file\n"),
+            "{}",
+            diff
+        );
+        assert!(
+            diff.contains("+other.txt\n\\ No newline at end of file\n"),
+            "{}",
+            diff
+        );
+    }
+
+    #[test]
+    fn cached_diff_shows_staged_edits_only() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "one\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("initial");
+        repo.write("a.txt", "two\n");
+        repo.stage(&["a.txt"]);
+
+        let cached = diff_head_index(&repo.database(), &repo.refs(), &mut repo.index()).unwrap();
+        assert_eq!(
+            cached,
+            "diff --git a/a.txt b/a.txt\n\
+             index 5626abf..f719efd 100644\n\
+             --- a/a.txt\n\
+             +++ b/a.txt\n\
+             @@ -1 +1 @@\n\
+             -one\n\
+             +two\n"
+        );
+        assert_eq!(worktree_diff(&repo), "");
+    }
+
+    // Commits the staged working tree, returning the hex id of its tree.
+    fn commit_tree(repo: &TestRepo, message: &str) -> String {
+        let database = repo.database();
+        let commit = repo.commit(message);
+        let (_, data) = database.read_object(&commit).unwrap();
+        crate::commit::parse_tree_oid(&data).unwrap()
+    }
+
+    #[test]
+    fn moved_file_is_a_rename_not_an_add_and_delete() {
+        let repo = TestRepo::new();
+        repo.write("old.txt", "moved\n");
+        repo.write("kept.txt", "kept\n");
+        repo.stage(&["."]);
+        let before = commit_tree(&repo, "initial");
+        fs::remove_file(repo.root().join("old.txt")).unwrap();
+        repo.write("lib/new.txt", "moved\n");
+        let mut index = repo.index();
+        assert!(index.remove("old.txt"));
+        assert!(index.write_updates().unwrap());
+        repo.stage(&["lib/new.txt"]);
+        let after = commit_tree(&repo, "move");
+
+        let database = repo.database();
+        let changes = diff_trees(&database, Some(&before), Some(&after)).unwrap();
+        assert_eq!(changes.len(), 2);
+        let renames = detect_renames(changes);
+        assert_eq!(renames.len(), 1);
+        assert_eq!(renames[0].path, "lib/new.txt");
+        assert_eq!(renames[0].old_path.as_deref(), Some("old.txt"));
+        assert_eq!(
+            renames[0].old.as_ref().unwrap().object_id,
+            renames[0].new.as_ref().unwrap().object_id
+        );
+    }
+
+    #[test]
+    fn exact_renames_prefer_the_same_file_name() {
+        let pairs = pair_exact_renames(
+            &[("a/readme", "1"), ("b/other", "1"), ("c/gone", "2")],
+            &[("x/other", "1"), ("y/readme", "1"), ("z/new", "3")],
+        );
+        assert_eq!(
+            pairs,
+            [
+                (String::from("b/other"), String::from("x/other")),
+                (String::from("a/readme"), String::from("y/readme")),
+            ]
+        );
+        assert!(pair_exact_renames(&[("a", "1")], &[("b", "2")]).is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+use std::{env, io, path::Path, process::Command};
+
+use crate::config::Config;
+
+/*
+    The editor to launch for messages, looked up the way git does:
+    GIT_EDITOR, core.editor, VISUAL and EDITOR, falling back to vi.
+*/
+pub fn editor(config: &Config) -> String {
+    env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| env::var("EDITOR").ok())
+        .filter(|editor| !editor.is_empty())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+/*
+    Opens path in the editor and waits for it to exit. The editor is run
+    through the shell, so it may carry its own arguments.
+*/
+pub fn edit(path: &Path, config: &Config) -> io::Result<()> {
+    let editor = editor(config);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "there was a problem with the editor '{}'",
+            editor
+        )));
+    }
+    Ok(())
+}
@@ -1,37 +1,49 @@
-use std::{
-    fs::Metadata,
-    os::unix::fs::PermissionsExt,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
+
+use crate::index;
 
 #[derive(Debug, Clone)]
 pub struct Entry {
     pub filename: String,
     pub path: PathBuf,
     pub object_id: String,
-    pub stat: Metadata,
+    pub mode: u32,
 }
 
 impl Entry {
-    pub fn new(filename: String, path: PathBuf, object_id: &str, stat: Metadata) -> Self {
+    pub fn new(filename: String, path: PathBuf, object_id: &str, mode: u32) -> Self {
         Entry {
             filename,
             path,
             object_id: object_id.to_string(),
-            stat,
+            mode,
         }
     }
 
-    pub fn mode(&self) -> String {
-        let permissions = self.stat.permissions();
-        // Check if owner has executable permission set.
-        if permissions.mode() & 0o100 != 0 {
-            String::from("100755")
-        } else {
-            String::from("100644")
+    /*
+        Converts an index entry, whose object id is raw bytes, into a tree
+        entry keeping the same mode.
+    */
+    pub fn from_index(entry: &index::Entry) -> Self {
+        let path = PathBuf::from(entry.path());
+        let filename = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => entry.path().to_string(),
+        };
+        let object_id = unsafe { String::from_utf8_unchecked(entry.oid().to_vec()) };
+        Entry {
+            filename,
+            path,
+            object_id,
+            mode: entry.mode(),
         }
     }
 
+    // Octal mode as written in tree objects, e.g. "100644".
+    pub fn mode(&self) -> String {
+        format!("{:o}", self.mode)
+    }
+
     pub fn ancestor_directories(&self) -> Vec<PathBuf> {
         let ancestors = Path::new(self.path.as_path())
             .ancestors()
@@ -0,0 +1,372 @@
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+/*
+    The filesystem operations the workspace needs, so that traversal can
+    run against something other than the real disk.
+*/
+pub trait FileSystem {
+    type Metadata: FileMetadata;
+
+    // Lists the paths of the entries in a directory. Entries that can't be
+    // read are returned as errors so the rest of the listing still works.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<io::Result<PathBuf>>>;
+    fn metadata(&self, path: &Path) -> io::Result<Self::Metadata>;
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Self::Metadata>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    // The target a symlink points at, without following it.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+}
+
+pub trait FileMetadata {
+    fn is_dir(&self) -> bool;
+    fn is_file(&self) -> bool;
+    fn is_symlink(&self) -> bool;
+}
+
+impl FileMetadata for Metadata {
+    fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+}
+
+/*
+    The real filesystem, a thin wrapper around std::fs.
+*/
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    type Metadata = Metadata;
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<io::Result<PathBuf>>> {
+        Ok(fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+}
+
+// The mock filesystem is only built for tests.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::{
+        cell::RefCell,
+        collections::{BTreeMap, BTreeSet},
+        io,
+        path::{Component, Path, PathBuf},
+    };
+
+    use super::{FileMetadata, FileSystem};
+
+    #[derive(Debug, Clone)]
+    enum MockNode {
+        File(Vec<u8>),
+        Dir,
+        Symlink(PathBuf),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MockKind {
+        File,
+        Dir,
+        Symlink,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct MockMetadata {
+        pub kind: MockKind,
+        pub len: u64,
+    }
+
+    impl FileMetadata for MockMetadata {
+        fn is_dir(&self) -> bool {
+            self.kind == MockKind::Dir
+        }
+
+        fn is_file(&self) -> bool {
+            self.kind == MockKind::File
+        }
+
+        fn is_symlink(&self) -> bool {
+            self.kind == MockKind::Symlink
+        }
+    }
+
+    // Symlink hops followed before giving up, as the kernel does with ELOOP.
+    const MAX_SYMLINK_HOPS: usize = 40;
+
+    /*
+        An in-memory filesystem for exercising workspace logic without disk I/O.
+        Paths are absolute, parent directories are created as entries are added,
+        and the number of operations performed is counted.
+    */
+    #[derive(Debug, Default)]
+    pub struct MockFs {
+        nodes: BTreeMap<PathBuf, MockNode>,
+        // Paths whose content or listing can't be read, like a file without
+        // read permission.
+        unreadable: BTreeSet<PathBuf>,
+        pub operations: RefCell<usize>,
+    }
+
+    impl MockFs {
+        pub fn new() -> Self {
+            let mut mock = MockFs::default();
+            mock.nodes.insert(PathBuf::from("/"), MockNode::Dir);
+            mock
+        }
+
+        pub fn add_file(&mut self, path: &Path, data: &[u8]) {
+            self.add_node(path, MockNode::File(data.to_vec()));
+        }
+
+        pub fn add_dir(&mut self, path: &Path) {
+            self.add_node(path, MockNode::Dir);
+        }
+
+        pub fn add_symlink(&mut self, path: &Path, target: &Path) {
+            self.add_node(path, MockNode::Symlink(target.to_path_buf()));
+        }
+
+        // Makes reading path, or listing it when it is a directory, fail.
+        pub fn deny_read(&mut self, path: &Path) {
+            self.unreadable.insert(normalize(path));
+        }
+
+        fn check_readable(&self, path: &Path) -> io::Result<()> {
+            if self.unreadable.contains(path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{}: permission denied", path.display()),
+                ));
+            }
+            Ok(())
+        }
+
+        fn add_node(&mut self, path: &Path, node: MockNode) {
+            for ancestor in path.ancestors().skip(1) {
+                self.nodes
+                    .entry(ancestor.to_path_buf())
+                    .or_insert(MockNode::Dir);
+            }
+            self.nodes.insert(path.to_path_buf(), node);
+        }
+
+        // Follows symlinks in every component of the path, including the last.
+        fn resolve(&self, path: &Path) -> io::Result<(PathBuf, &MockNode)> {
+            let mut hops = 0;
+            let mut resolved = PathBuf::from("/");
+            let mut remaining = path_parts(path);
+            remaining.reverse();
+            while let Some(component) = remaining.pop() {
+                let candidate = resolved.join(component);
+                if let MockNode::Symlink(target) = self.lookup(&candidate)? {
+                    hops += 1;
+                    if hops > MAX_SYMLINK_HOPS {
+                        return Err(io::Error::other(format!(
+                            "{}: too many levels of symbolic links",
+                            path.display()
+                        )));
+                    }
+                    // Splice the target in place of the link and start again.
+                    remaining.extend(path_parts(&resolved.join(target)).into_iter().rev());
+                    resolved = PathBuf::from("/");
+                } else {
+                    resolved = candidate;
+                }
+            }
+            let node = self.lookup(&resolved)?;
+            Ok((resolved, node))
+        }
+
+        fn lookup(&self, path: &Path) -> io::Result<&MockNode> {
+            *self.operations.borrow_mut() += 1;
+            self.nodes.get(&normalize(path)).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: no such file or directory", path.display()),
+                )
+            })
+        }
+
+        fn node_metadata(node: &MockNode) -> MockMetadata {
+            match node {
+                MockNode::File(data) => MockMetadata {
+                    kind: MockKind::File,
+                    len: data.len() as u64,
+                },
+                MockNode::Dir => MockMetadata {
+                    kind: MockKind::Dir,
+                    len: 0,
+                },
+                MockNode::Symlink(target) => MockMetadata {
+                    kind: MockKind::Symlink,
+                    len: target.as_os_str().len() as u64,
+                },
+            }
+        }
+    }
+
+    impl FileSystem for MockFs {
+        type Metadata = MockMetadata;
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<io::Result<PathBuf>>> {
+            let (dir, node) = self.resolve(path)?;
+            self.check_readable(&dir)?;
+            if !matches!(node, MockNode::Dir) {
+                return Err(io::Error::other(format!(
+                    "{}: not a directory",
+                    path.display()
+                )));
+            }
+            // Report children under the path as given, not the resolved one.
+            let base = normalize(path);
+            Ok(self
+                .nodes
+                .keys()
+                .filter(|child| child.parent() == Some(dir.as_path()))
+                .map(|child| Ok(base.join(child.file_name().unwrap())))
+                .collect())
+        }
+
+        fn metadata(&self, path: &Path) -> io::Result<MockMetadata> {
+            let (_, node) = self.resolve(path)?;
+            Ok(Self::node_metadata(node))
+        }
+
+        fn symlink_metadata(&self, path: &Path) -> io::Result<MockMetadata> {
+            Ok(Self::node_metadata(self.lookup(path)?))
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            let (resolved, node) = self.resolve(path)?;
+            self.check_readable(&resolved)?;
+            match node {
+                MockNode::File(data) => Ok(data.clone()),
+                _ => Err(io::Error::other(format!(
+                    "{}: is a directory",
+                    path.display()
+                ))),
+            }
+        }
+
+        fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+            match self.lookup(path)? {
+                MockNode::Symlink(target) => Ok(target.clone()),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: not a symbolic link", path.display()),
+                )),
+            }
+        }
+    }
+
+    // Resolves "." and ".." components lexically.
+    fn normalize(path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+        normalized
+    }
+
+    // The names below the root of an absolute path, after normalizing.
+    fn path_parts(path: &Path) -> Vec<PathBuf> {
+        normalize(path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .map(|component| PathBuf::from(component.as_os_str()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::Path};
+
+    use super::mock::{MockFs, MockKind};
+    use super::FileSystem;
+
+    #[test]
+    fn symlink_cycle_is_reported_as_an_error() {
+        let mut mock = MockFs::new();
+        mock.add_symlink(Path::new("/repo/ping"), Path::new("pong"));
+        mock.add_symlink(Path::new("/repo/pong"), Path::new("/repo/ping"));
+
+        for result in [
+            mock.metadata(Path::new("/repo/ping")).map(|_| ()),
+            mock.read(Path::new("/repo/pong/file")).map(|_| ()),
+            mock.read_dir(Path::new("/repo/ping")).map(|_| ()),
+        ] {
+            let err = result.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            assert!(err
+                .to_string()
+                .ends_with("too many levels of symbolic links"));
+        }
+        // The link itself can still be described without following it.
+        let link = mock.symlink_metadata(Path::new("/repo/ping")).unwrap();
+        assert_eq!(link.kind, MockKind::Symlink);
+        assert_eq!(link.len, 4);
+        // Each lookup gave up after a bounded number of hops.
+        assert!(*mock.operations.borrow() < 1_000);
+    }
+
+    #[test]
+    fn symlinks_resolve_through_directories() {
+        let mut mock = MockFs::new();
+        mock.add_file(Path::new("/repo/real/file.txt"), b"content");
+        mock.add_symlink(Path::new("/repo/alias"), Path::new("real"));
+        mock.add_dir(Path::new("/repo/real/empty"));
+        assert_eq!(
+            mock.read(Path::new("/repo/alias/file.txt")).unwrap(),
+            b"content"
+        );
+        assert_eq!(
+            mock.read_dir(Path::new("/repo/alias"))
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+            [
+                Path::new("/repo/alias/empty"),
+                Path::new("/repo/alias/file.txt")
+            ]
+        );
+        let empty = mock.metadata(Path::new("/repo/alias/empty")).unwrap();
+        assert_eq!((empty.kind, empty.len), (MockKind::Dir, 0));
+    }
+}
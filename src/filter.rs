@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use crate::attributes::{AttributeStack, State};
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AutoCrlf {
+    #[default]
+    False,
+    // Convert CRLF to LF when storing and LF to CRLF when checking out.
+    True,
+    // Only convert CRLF to LF when storing.
+    Input,
+}
+
+/*
+    The line ending conversion between the working tree and the object
+    database set by core.autocrlf, which the text and eol attributes of a
+    path override.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    autocrlf: AutoCrlf,
+}
+
+// How the content of one path is treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Content {
+    // Never converted.
+    Binary,
+    // Always converted.
+    Text,
+    // Converted unless the content looks binary.
+    Auto,
+}
+
+impl Filter {
+    pub fn new(config: &Config) -> Self {
+        let autocrlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => AutoCrlf::Input,
+            Some(_) if config.get_bool("core.autocrlf") == Some(true) => AutoCrlf::True,
+            _ => AutoCrlf::False,
+        };
+        Filter { autocrlf }
+    }
+
+    /*
+        Converts working tree content of path into what is stored, turning
+        CRLF into LF for text. The text and eol attributes of path decide
+        what is text, falling back to autocrlf when neither is given.
+    */
+    pub fn clean(&self, attributes: &AttributeStack, path: &Path, data: Vec<u8>) -> Vec<u8> {
+        let (text, _) = self.conversion(attributes, path);
+        if text == Content::Binary
+            || (text == Content::Auto && is_binary(&data))
+            || !data.contains(&b'\r')
+        {
+            return data;
+        }
+        let mut out = Vec::with_capacity(data.len());
+        for (i, &b) in data.iter().enumerate() {
+            if b == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            out.push(b);
+        }
+        out
+    }
+
+    /*
+        Converts stored content of path into what is written to the working
+        tree, turning LF into CRLF for text checked out with CRLF endings:
+        those with eol=crlf, or with no eol attribute when autocrlf is true.
+        Content that looks binary or already holds a CR is left alone
+        unless the path is forced to be text.
+    */
+    pub fn smudge(&self, attributes: &AttributeStack, path: &Path, data: Vec<u8>) -> Vec<u8> {
+        let (text, crlf) = self.conversion(attributes, path);
+        if text == Content::Binary
+            || !crlf
+            || (text == Content::Auto && (is_binary(&data) || data.contains(&b'\r')))
+        {
+            return data;
+        }
+        let mut out = Vec::with_capacity(data.len() + data.len() / 32);
+        for (i, &b) in data.iter().enumerate() {
+            if b == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+                out.push(b'\r');
+            }
+            out.push(b);
+        }
+        out
+    }
+
+    // Whether path is text, and whether it is checked out with CRLF.
+    fn conversion(&self, attributes: &AttributeStack, path: &Path) -> (Content, bool) {
+        let eol = attributes.get(path, "eol");
+        let text = match attributes.get(path, "text") {
+            State::Unset => Content::Binary,
+            State::Set => Content::Text,
+            State::Value(value) if value == "auto" => Content::Auto,
+            // An eol attribute makes a path text on its own.
+            _ if matches!(eol, State::Value(_)) => Content::Text,
+            _ if self.autocrlf != AutoCrlf::False => Content::Auto,
+            _ => Content::Binary,
+        };
+        let crlf = match eol {
+            State::Value(value) if value == "crlf" => true,
+            State::Value(value) if value == "lf" => false,
+            _ => self.autocrlf == AutoCrlf::True,
+        };
+        (text, crlf)
+    }
+}
+
+/*
+    Guesses whether content is binary the way git does: it holds a NUL, a
+    CR not followed by LF, or more than one nonprintable byte in every 128
+    printable ones.
+*/
+pub fn is_binary(data: &[u8]) -> bool {
+    let mut printable = 0;
+    let mut nonprintable = 0;
+    for (i, &b) in data.iter().enumerate() {
+        match b {
+            0 => return true,
+            b'\r' if data.get(i + 1) != Some(&b'\n') => return true,
+            b'\r' | b'\n' => {}
+            // Backspace, tab, escape and form feed are common in text.
+            8 | b'\t' | 27 | 12 => printable += 1,
+            127 => nonprintable += 1,
+            b if b < 32 => nonprintable += 1,
+            _ => printable += 1,
+        }
+    }
+    (printable >> 7) < nonprintable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRLF: &[u8] = b"one\r\ntwo\r\n";
+    const LF: &[u8] = b"one\ntwo\n";
+
+    fn autocrlf() -> Filter {
+        Filter {
+            autocrlf: AutoCrlf::True,
+        }
+    }
+
+    fn attributes(contents: &str) -> AttributeStack {
+        let mut attributes = AttributeStack::new();
+        attributes.push(contents, Path::new(""));
+        attributes
+    }
+
+    #[test]
+    fn binary_paths_are_never_converted() {
+        let attributes = attributes("*.dat binary\n");
+        let path = Path::new("lib/data.dat");
+        assert_eq!(autocrlf().clean(&attributes, path, CRLF.to_vec()), CRLF);
+        assert_eq!(autocrlf().smudge(&attributes, path, LF.to_vec()), LF);
+        // Other paths still follow autocrlf.
+        let path = Path::new("notes.txt");
+        assert_eq!(autocrlf().clean(&attributes, path, CRLF.to_vec()), LF);
+        assert_eq!(autocrlf().smudge(&attributes, path, LF.to_vec()), CRLF);
+    }
+
+    #[test]
+    fn eol_lf_overrides_autocrlf_on_checkout() {
+        let attributes = attributes("*.sh eol=lf\n");
+        let path = Path::new("build.sh");
+        assert_eq!(autocrlf().clean(&attributes, path, CRLF.to_vec()), LF);
+        assert_eq!(autocrlf().smudge(&attributes, path, LF.to_vec()), LF);
+        // eol alone makes a path text, even without autocrlf.
+        let filter = Filter::default();
+        assert_eq!(filter.clean(&attributes, path, CRLF.to_vec()), LF);
+        assert_eq!(
+            filter.clean(&attributes, Path::new("a.txt"), CRLF.to_vec()),
+            CRLF
+        );
+    }
+
+    #[test]
+    fn auto_text_leaves_binary_looking_content_alone() {
+        let attributes = AttributeStack::new();
+        let path = Path::new("a.bin");
+        assert_eq!(
+            autocrlf().clean(&attributes, path, b"a\0\r\n".to_vec()),
+            b"a\0\r\n"
+        );
+        assert_eq!(
+            autocrlf().smudge(&attributes, path, b"a\0\n".to_vec()),
+            b"a\0\n"
+        );
+        // Text forced on converts it anyway.
+        let attributes = self::attributes("a.bin text\n");
+        assert_eq!(
+            autocrlf().clean(&attributes, path, b"a\0\r\n".to_vec()),
+            b"a\0\n"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt, io,
+};
+
+use crate::commit;
+use crate::database::Database;
+use crate::refs::Refs;
+use crate::tree;
+
+// Mode of a submodule entry, whose commit lives in another repository.
+const GITLINK_MODE: &str = "160000";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    // A reachable object that is not in the database: (expected type, oid).
+    Missing(&'static str, String),
+    // An object stored with another type than the one referring to it expects.
+    WrongType {
+        oid: String,
+        expected: &'static str,
+        actual: String,
+    },
+    // An object that could not be inflated or parsed: (oid, reason).
+    Corrupt(String, String),
+    // A commit no ref or other commit leads to.
+    Dangling(String),
+}
+
+impl Problem {
+    /*
+        Dangling commits are only reported, everything else is damage.
+    */
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Problem::Dangling(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing(kind, oid) => write!(f, "missing {} {}", kind, oid),
+            Problem::WrongType {
+                oid,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "error: object {} is a {}, not a {}",
+                oid, actual, expected
+            ),
+            Problem::Corrupt(oid, reason) => {
+                write!(f, "error: object {} is corrupt: {}", oid, reason)
+            }
+            Problem::Dangling(oid) => write!(f, "dangling commit {}", oid),
+        }
+    }
+}
+
+/*
+    Walks every commit, tree and blob reachable from HEAD and the refs,
+    reporting objects that are missing, corrupt or of the wrong type, then
+    reports loose commits that nothing reaches as dangling.
+*/
+pub fn fsck(database: &Database, refs: &Refs) -> io::Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let mut reachable = HashSet::new();
+
+    let mut pending = Vec::new();
+    let head = refs.read_head()?;
+    if !head.is_empty() {
+        pending.push(("commit", head));
+    }
+    for (_, oid) in refs.list_refs()? {
+        pending.push(("commit", oid));
+    }
+
+    while let Some((kind, oid)) = pending.pop() {
+        if !reachable.insert(oid.clone()) {
+            continue;
+        }
+        if let Err(problem) = check_object(database, kind, &oid, &mut pending) {
+            problems.push(problem);
+        }
+    }
+
+    // A dangling commit may have unreachable parents, only the tips count.
+    let mut unreachable = BTreeSet::new();
+    let mut parents = HashSet::new();
+    for oid in database.list_objects()? {
+        if reachable.contains(&oid) {
+            continue;
+        }
+        if let Ok((kind, data)) = database.read_object(&oid) {
+            if kind == "commit" {
+                if let Ok(commit) = commit::parse_commit(&data) {
+                    parents.extend(commit.parents);
+                }
+                unreachable.insert(oid);
+            }
+        }
+    }
+    for oid in unreachable {
+        if !parents.contains(&oid) {
+            problems.push(Problem::Dangling(oid));
+        }
+    }
+    Ok(problems)
+}
+
+// Verifies one object, queueing the objects it refers to.
+fn check_object(
+    database: &Database,
+    kind: &'static str,
+    oid: &str,
+    pending: &mut Vec<(&'static str, String)>,
+) -> Result<(), Problem> {
+    let corrupt = |err: io::Error| Problem::Corrupt(oid.to_string(), err.to_string());
+    // Blob contents are never needed, so only their header is read.
+    let (actual, data) = if kind == "blob" {
+        let (actual, _) = database.read_header(oid).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Problem::Missing(kind, oid.to_string()),
+            _ => corrupt(err),
+        })?;
+        (actual, Vec::new())
+    } else {
+        database.read_object(oid).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Problem::Missing(kind, oid.to_string()),
+            _ => corrupt(err),
+        })?
+    };
+    if actual != kind {
+        return Err(Problem::WrongType {
+            oid: oid.to_string(),
+            expected: kind,
+            actual,
+        });
+    }
+
+    match kind {
+        "commit" => {
+            let commit = commit::parse_commit(&data).map_err(corrupt)?;
+            pending.push(("tree", commit.tree));
+            pending.extend(commit.parents.into_iter().map(|parent| ("commit", parent)));
+        }
+        "tree" => {
+            for entry in tree::parse_tree(&data).map_err(corrupt)? {
+                if entry.mode == GITLINK_MODE {
+                    continue;
+                }
+                let kind = if entry.is_tree() { "tree" } else { "blob" };
+                pending.push((kind, entry.object_id));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::test_support::TestRepo;
+    use crate::utils;
+
+    fn committed_blob(repo: &TestRepo) -> String {
+        repo.write("a.txt", "a\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("initial");
+        utils::u8_to_hex_str(repo.index().get("a.txt").unwrap().oid().to_vec())
+    }
+
+    fn object_file(repo: &TestRepo, oid: &str) -> std::path::PathBuf {
+        let (dir, file) = utils::hash_to_path(oid);
+        repo.git_dir().join("objects").join(dir).join(file)
+    }
+
+    #[test]
+    fn intact_repository_has_no_problems() {
+        let repo = TestRepo::new();
+        committed_blob(&repo);
+        assert_eq!(fsck(&repo.database(), &repo.refs()).unwrap(), []);
+    }
+
+    #[test]
+    fn deleted_blob_is_reported_missing() {
+        let repo = TestRepo::new();
+        let blob = committed_blob(&repo);
+        fs::remove_file(object_file(&repo, &blob)).unwrap();
+
+        let problems = fsck(&repo.database(), &repo.refs()).unwrap();
+        assert_eq!(problems, [Problem::Missing("blob", blob.clone())]);
+        assert!(problems[0].is_error());
+        assert_eq!(problems[0].to_string(), format!("missing blob {}", blob));
+    }
+
+    #[test]
+    fn object_of_the_wrong_type_is_reported() {
+        let repo = TestRepo::new();
+        let blob = committed_blob(&repo);
+        fs::remove_file(object_file(&repo, &blob)).unwrap();
+        repo.database().write_object(&blob, b"tree 0\0").unwrap();
+
+        let problems = fsck(&repo.database(), &repo.refs()).unwrap();
+        assert_eq!(
+            problems,
+            [Problem::WrongType {
+                oid: blob,
+                expected: "blob",
+                actual: String::from("tree"),
+            }]
+        );
+    }
+
+    #[test]
+    fn unreferenced_commit_tip_is_dangling() {
+        let repo = TestRepo::new();
+        committed_blob(&repo);
+        let first = repo.refs().read_head().unwrap();
+        repo.write("a.txt", "b\n");
+        repo.stage(&["a.txt"]);
+        let second = repo.commit("second");
+        repo.write("a.txt", "c\n");
+        repo.stage(&["a.txt"]);
+        let third = repo.commit("third");
+        // Rewind the branch, leaving second and third unreachable.
+        repo.refs()
+            .compare_and_swap("refs/heads/main", Some(&third), &first)
+            .unwrap();
+
+        let problems = fsck(&repo.database(), &repo.refs()).unwrap();
+        assert_eq!(problems, [Problem::Dangling(third)]);
+        assert!(!problems[0].is_error());
+        assert!(!problems.contains(&Problem::Dangling(second)));
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+
+use sha1::{Digest, Sha1};
+
+// Length in bytes of a raw SHA-1 object id.
+pub const OID_SIZE: usize = 20;
+
+/*
+    Hashes data into a raw object id. This is the one place objects and
+    the index are hashed, so another hash function only has to be added here.
+*/
+pub fn oid_bytes(data: &[u8]) -> [u8; OID_SIZE] {
+    Sha1::digest(data).into()
+}
+
+/*
+    Checks that bytes hold a raw object id, as opposed to its hex form or
+    an id of some other hash function.
+*/
+pub fn raw_oid(bytes: &[u8]) -> io::Result<&[u8; OID_SIZE]> {
+    bytes.try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "object id must be {} raw bytes, got {}",
+                OID_SIZE,
+                bytes.len()
+            ),
+        )
+    })
+}
+
+/*
+    Hashes data into a lowercase hex object id.
+*/
+pub fn oid_hex(data: &[u8]) -> String {
+    oid_bytes(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_sha1_vectors() {
+        assert_eq!(oid_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(oid_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(oid_bytes(b"abc")[..4], [0xa9, 0x99, 0x3e, 0x36]);
+    }
+
+    #[test]
+    fn empty_blob_has_gits_id() {
+        assert_eq!(
+            oid_hex(b"blob 0\0"),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/*
+    Runs .git/hooks/<name> from the root of the workspace if it exists and
+    is executable. Returns whether the hook passed, a missing or
+    non-executable hook counts as passing.
+*/
+pub fn run_hook(git_path: &Path, root_path: &Path, name: &str) -> io::Result<bool> {
+    let hook_path = git_path.join("hooks").join(name);
+    let metadata = match fs::metadata(&hook_path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err),
+    };
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Ok(true);
+    }
+
+    let status = Command::new(&hook_path)
+        .current_dir(root_path)
+        .stdin(Stdio::null())
+        .status()?;
+    Ok(status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn write_hook(dir: &Path, script: &str, mode: u32) {
+        let hooks = dir.join(".git").join("hooks");
+        fs::create_dir_all(&hooks).unwrap();
+        let path = hooks.join("pre-commit");
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn run_pre_commit(dir: &Path) -> bool {
+        run_hook(&dir.join(".git"), dir, "pre-commit").unwrap()
+    }
+
+    #[test]
+    fn missing_hook_passes() {
+        let dir = TempDir::new().unwrap();
+        assert!(run_pre_commit(dir.path()));
+    }
+
+    #[test]
+    fn hook_exit_status_decides() {
+        let dir = TempDir::new().unwrap();
+        write_hook(dir.path(), "#!/bin/sh\nexit 1\n", 0o755);
+        assert!(!run_pre_commit(dir.path()));
+        write_hook(dir.path(), "#!/bin/sh\nexit 0\n", 0o755);
+        assert!(run_pre_commit(dir.path()));
+    }
+
+    #[test]
+    fn non_executable_hook_is_skipped() {
+        let dir = TempDir::new().unwrap();
+        write_hook(dir.path(), "#!/bin/sh\nexit 1\n", 0o644);
+        assert!(run_pre_commit(dir.path()));
+    }
+
+    #[test]
+    fn hook_runs_from_the_workspace_root() {
+        let dir = TempDir::new().unwrap();
+        write_hook(dir.path(), "#!/bin/sh\ntouch ran\n", 0o755);
+        assert!(run_pre_commit(dir.path()));
+        assert!(dir.path().join("ran").exists());
+    }
+}
@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+/*
+    A single pattern from a .gitignore file.
+*/
+#[derive(Debug, Clone)]
+pub struct IgnorePattern {
+    pattern: String,
+    // A leading "!" re-includes paths an earlier pattern excluded.
+    negated: bool,
+    // A trailing "/" only matches directories.
+    dir_only: bool,
+    // A pattern containing a "/" matches the path relative to base,
+    // otherwise it matches the final name at any depth below base.
+    anchored: bool,
+    // Directory of the .gitignore file, relative to the workspace root.
+    base: PathBuf,
+}
+
+impl IgnorePattern {
+    /*
+        Parses one line of a .gitignore in the directory base. Blank lines
+        and comments yield None.
+    */
+    pub fn parse(line: &str, base: &Path) -> Option<Self> {
+        let mut line = line.trim_end_matches(['\n', '\r']);
+        if !line.ends_with("\\ ") {
+            line = line.trim_end_matches(' ');
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // A backslash escapes a leading "!" or "#".
+        let negated = line.starts_with('!');
+        if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(IgnorePattern {
+            pattern: line.to_string(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_path_buf(),
+        })
+    }
+
+    /*
+        Whether path, relative to the workspace root, matches the pattern.
+        Negation is left to the caller.
+    */
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        let text = if self.anchored {
+            relative.to_string_lossy()
+        } else {
+            match relative.file_name() {
+                Some(name) => name.to_string_lossy(),
+                None => return false,
+            }
+        };
+        wildmatch(self.pattern.as_bytes(), text.as_bytes())
+    }
+}
+
+/*
+    The patterns in effect for a directory: those of every .gitignore from
+    the workspace root down to it, in that order, so deeper files and later
+    lines take precedence.
+*/
+#[derive(Debug, Default, Clone)]
+pub struct IgnoreStack {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+        Adds the patterns of a .gitignore found in base, returning the
+        previous depth of the stack for use with truncate.
+    */
+    pub fn push(&mut self, contents: &str, base: &Path) -> usize {
+        let depth = self.patterns.len();
+        self.patterns.extend(
+            contents
+                .lines()
+                .filter_map(|line| IgnorePattern::parse(line, base)),
+        );
+        depth
+    }
+
+    // Drops the patterns pushed after the stack had the given depth.
+    pub fn truncate(&mut self, depth: usize) {
+        self.patterns.truncate(depth);
+    }
+
+    /*
+        Whether path, relative to the workspace root, is ignored. The last
+        matching pattern decides.
+    */
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/*
+    Matches text against a gitignore glob. "*" and "?" never match "/",
+    "**" matches across directories, and "[...]" matches a class of bytes.
+*/
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            match rest.strip_prefix(b"/") {
+                // "**/" matches zero or more leading directories.
+                Some(rest) => {
+                    wildmatch(rest, text)
+                        || (0..text.len())
+                            .any(|i| text[i] == b'/' && wildmatch(rest, &text[i + 1..]))
+                }
+                None => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+            }
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if wildmatch(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(&c) if c != b'/' => wildmatch(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => match (match_class(&pattern[1..], text.first()), text.first()) {
+            (Some((true, len)), Some(_)) => wildmatch(&pattern[1 + len..], &text[1..]),
+            (Some(_), _) => false,
+            // An unterminated class is a literal "[".
+            (None, Some(b'[')) => wildmatch(&pattern[1..], &text[1..]),
+            (None, _) => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && wildmatch(&pattern[2..], &text[1..])
+        }
+        Some(&c) => text.first() == Some(&c) && wildmatch(&pattern[1..], &text[1..]),
+    }
+}
+
+/*
+    Matches c against the class whose body starts at pattern, just after
+    the "[". Returns whether it matched and the length of the class
+    including the closing "]", or None if the class is unterminated.
+*/
+fn match_class(pattern: &[u8], c: Option<&u8>) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(i)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&end| end != b']') {
+            let end = pattern[i + 2];
+            matched |= c.is_some_and(|&c| start <= c && c <= end);
+            i += 3;
+        } else {
+            matched |= c == Some(&start);
+            i += 1;
+        }
+    }
+    // Like "?", a class never matches the directory separator.
+    let hit = matched != negated && c.is_some_and(|&c| c != b'/');
+    Some((hit, i + 1))
+}
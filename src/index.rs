@@ -1,99 +1,394 @@
-use core::panic;
 use std::{
     cmp,
     collections::HashMap,
-    fs::Metadata,
+    error, fmt,
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, Read},
     os::unix::fs::{MetadataExt, PermissionsExt},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use sha1::{digest::core_api::CoreWrapper, Digest, Sha1, Sha1Core};
 
-use crate::lockfile::LockFile;
+use crate::cache_tree::CacheTree;
+use crate::database::{Database, EMPTY_BLOB_OID};
+use crate::entry;
+use crate::hash::{self, OID_SIZE};
+use crate::lockfile::{LockFile, LockfileError};
+use crate::resolve_undo::ResolveUndo;
+use crate::tree;
+use crate::utils;
+use crate::workspace::Workspace;
+
+// Header: "DIRC" signature, 4 byte version, 4 byte entry count.
+const HEADER_SIZE: usize = 12;
+// Fixed size portion of an entry: ten 4 byte stat fields, 20 byte oid, 2 byte flags.
+const ENTRY_FIXED_SIZE: usize = 62;
+// The fixed fields plus a one byte path and its terminating NUL.
+const ENTRY_MIN_SIZE: usize = ENTRY_FIXED_SIZE + 2;
+const CHECKSUM_SIZE: usize = 20;
+// Version 2 entries have fixed flags, version 3 adds extended flags and
+// version 4 prefix compresses paths.
+const SUPPORTED_VERSIONS: [u32; 3] = [2, 3, 4];
+
+// Set in the flags word when a v3 extended flags word follows it.
+const FLAG_EXTENDED: u16 = 0x4000;
+// Extended flag bits, only present in version 3 and later.
+const EXT_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const EXT_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+/*
+    The entry count as stored in the 4 byte header field, which can't
+    represent more than u32::MAX entries.
+*/
+fn header_count(len: usize) -> Result<u32, IndexError> {
+    u32::try_from(len).map_err(|_| IndexError::TooManyEntries(len))
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    Io(io::Error),
+    CorruptHeader,
+    BadSignature,
+    BadVersion(u32),
+    BadChecksum,
+    TruncatedEntry(usize),
+    DuplicateEntry(String),
+    UnsortedEntries(String, String),
+    TooManyEntries(usize),
+    // Taking, writing or committing the index lock failed.
+    Lock(LockfileError),
+    // The header declares more entries than the file could hold.
+    ImpossibleEntryCount(u32, usize),
+    CorruptExtension(String),
+    // A required extension, such as the "link" of a split index, whose
+    // entries would be lost without it.
+    UnsupportedExtension(String),
+}
+
+impl error::Error for IndexError {}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexError::Io(err) => write!(f, "unable to read index: {}", err),
+            IndexError::CorruptHeader => write!(f, "index file smaller than expected"),
+            IndexError::BadSignature => write!(f, "bad index file signature"),
+            IndexError::BadVersion(version) => write!(f, "bad index file version {}", version),
+            IndexError::BadChecksum => write!(f, "index file checksum mismatch"),
+            IndexError::TruncatedEntry(n) => write!(f, "index entry {} is truncated", n),
+            IndexError::DuplicateEntry(path) => write!(f, "duplicate index entry '{}'", path),
+            IndexError::UnsortedEntries(prev, next) => {
+                write!(
+                    f,
+                    "index entries '{}' and '{}' are out of order",
+                    prev, next
+                )
+            }
+            IndexError::TooManyEntries(n) => {
+                write!(f, "{} entries do not fit in the index header", n)
+            }
+            IndexError::Lock(err) => write!(f, "unable to write index: {}", err),
+            IndexError::ImpossibleEntryCount(declared, max) => write!(
+                f,
+                "index header declares {} entries but the file holds at most {}",
+                declared, max
+            ),
+            IndexError::CorruptExtension(name) => write!(f, "corrupt index extension '{}'", name),
+            IndexError::UnsupportedExtension(name) => {
+                write!(f, "index uses unsupported extension '{}'", name)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for IndexError {
+    fn from(err: io::Error) -> Self {
+        IndexError::Io(err)
+    }
+}
+
+// Object types stored in the high bits of an entry mode.
+const MODE_REGULAR: u32 = 0x8000;
+const MODE_SYMLINK: u32 = 0xA000;
+const MODE_GITLINK: u32 = 0xE000;
+const MODE_TYPE_MASK: u32 = 0xF000;
+
+/*
+    Computes the full entry mode from the file type, adding permission bits
+    for regular files. Only the owner executable bit is considered, so
+    regular files are always 0644 or 0755.
+*/
+pub fn mode_bits(stat: &Metadata) -> u32 {
+    let file_type = stat.file_type();
+    if file_type.is_symlink() {
+        MODE_SYMLINK
+    } else if file_type.is_dir() {
+        MODE_GITLINK
+    } else if stat.permissions().mode() & 0o100 != 0 {
+        MODE_REGULAR | 0o755
+    } else {
+        MODE_REGULAR | 0o644
+    }
+}
+
+/*
+    Encodes a stat value as a 32 bit big-endian index field. The index only
+    has room for the low 32 bits, so larger values such as 64 bit inode
+    numbers or sizes over 4GiB are truncated, the same way git does. The
+    truncated value is only ever compared against another truncated stat.
+*/
+fn u32_be(v: u64) -> [u8; 4] {
+    (v as u32).to_be_bytes()
+}
 
 #[derive(Debug, Clone)]
 pub struct Entry {
-    pub ctime: [u8; 4],
-    pub ctime_nsec: [u8; 4],
-    pub mtime: [u8; 4],
-    pub mtime_nsec: [u8; 4],
-    pub dev: [u8; 4],
-    pub ino: [u8; 4],
-    pub mode: [u8; 4],
-    pub uid: [u8; 4],
-    pub gid: [u8; 4],
-    pub size: [u8; 4],
-    pub oid: Vec<u8>,
-    pub flags: [u8; 2],
-    pub path: String,
+    ctime: [u8; 4],
+    ctime_nsec: [u8; 4],
+    mtime: [u8; 4],
+    mtime_nsec: [u8; 4],
+    dev: [u8; 4],
+    ino: [u8; 4],
+    mode: [u8; 4],
+    uid: [u8; 4],
+    gid: [u8; 4],
+    size: [u8; 4],
+    oid: [u8; OID_SIZE],
+    flags: [u8; 2],
+    extended_flags: [u8; 2],
+    path: String,
 }
 
 impl Entry {
-    pub fn new(path: PathBuf, object_id: &str, stat: Metadata) -> Self {
-        let mut pathname = String::new();
-        match path.to_str() {
-            Some(s) => {
-                pathname = String::from(s);
-            }
-            None => {
-                eprintln!("Error reading pathname.");
-                panic!();
-            }
+    /*
+        object_id is the raw object id as stored in the index, not the hex
+        form. Index paths are UTF-8, so any other path is an error.
+    */
+    pub fn new(path: PathBuf, object_id: &[u8; OID_SIZE], stat: Metadata) -> io::Result<Self> {
+        let pathname = path.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("path '{}' is not valid UTF-8", path.display()),
+            )
+        })?;
+        Ok(Self::from_stat(pathname, object_id, &stat))
+    }
+
+    /*
+        An entry for a file in the working tree. The mode comes from stat
+        and the stat fields are cached, so an unchanged stat later means
+        unchanged content.
+    */
+    pub fn from_stat(path: &str, object_id: &[u8; OID_SIZE], stat: &Metadata) -> Self {
+        let mut entry = Self::from_tree(path, mode_bits(stat), object_id);
+        entry.ctime = u32_be(stat.ctime() as u64);
+        entry.ctime_nsec = u32_be(stat.ctime_nsec() as u64);
+        entry.mtime = u32_be(stat.mtime() as u64);
+        entry.mtime_nsec = u32_be(stat.mtime_nsec() as u64);
+        entry.dev = u32_be(stat.dev());
+        entry.ino = u32_be(stat.ino());
+        entry.uid = stat.uid().to_be_bytes();
+        entry.gid = stat.gid().to_be_bytes();
+        entry.size = u32_be(stat.size());
+        entry
+    }
+
+    /*
+        An entry for a path read from a tree rather than the working tree.
+        The stat fields are zeroed, so the entry never matches a stat of
+        the file and its content is compared instead.
+    */
+    pub fn from_tree(path: &str, mode: u32, object_id: &[u8; OID_SIZE]) -> Self {
+        let flag = cmp::min(0xFFF, path.len()) as u16;
+        Entry {
+            ctime: [0; 4],
+            ctime_nsec: [0; 4],
+            mtime: [0; 4],
+            mtime_nsec: [0; 4],
+            dev: [0; 4],
+            ino: [0; 4],
+            mode: mode.to_be_bytes(),
+            uid: [0; 4],
+            gid: [0; 4],
+            size: [0; 4],
+            oid: *object_id,
+            flags: flag.to_be_bytes(),
+            extended_flags: [0, 0],
+            path: path.to_string(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // The raw object id, not its hex form.
+    pub fn oid(&self) -> &[u8] {
+        &self.oid
+    }
+
+    pub fn mode(&self) -> u32 {
+        u32::from_be_bytes(self.mode)
+    }
+
+    pub fn size(&self) -> u32 {
+        u32::from_be_bytes(self.size)
+    }
+
+    /*
+        The flags word: the path length capped at 0xFFF, the stage and the
+        extended bit. It only changes through the setters below, which keep
+        it consistent with the path and the extended flags.
+    */
+    pub fn flags(&self) -> u16 {
+        u16::from_be_bytes(self.flags)
+    }
+
+    pub fn extended_flags(&self) -> u16 {
+        u16::from_be_bytes(self.extended_flags)
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.extended_flags != [0, 0]
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        u16::from_be_bytes(self.extended_flags) & EXT_FLAG_INTENT_TO_ADD != 0
+    }
+
+    pub fn set_intent_to_add(&mut self) {
+        self.set_extended_flag(EXT_FLAG_INTENT_TO_ADD);
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        u16::from_be_bytes(self.extended_flags) & EXT_FLAG_SKIP_WORKTREE != 0
+    }
+
+    pub fn set_skip_worktree(&mut self, skip: bool) {
+        if skip {
+            self.set_extended_flag(EXT_FLAG_SKIP_WORKTREE);
+        } else {
+            self.clear_extended_flag(EXT_FLAG_SKIP_WORKTREE);
+        }
+    }
+
+    // Keeps the extended bit of the flags word in sync with the extended flags.
+    fn set_extended_flag(&mut self, flag: u16) {
+        let extended = u16::from_be_bytes(self.extended_flags) | flag;
+        self.extended_flags = extended.to_be_bytes();
+        let flags = u16::from_be_bytes(self.flags) | FLAG_EXTENDED;
+        self.flags = flags.to_be_bytes();
+    }
+
+    fn clear_extended_flag(&mut self, flag: u16) {
+        let extended = u16::from_be_bytes(self.extended_flags) & !flag;
+        self.extended_flags = extended.to_be_bytes();
+        if extended == 0 {
+            let flags = u16::from_be_bytes(self.flags) & !FLAG_EXTENDED;
+            self.flags = flags.to_be_bytes();
+        }
+    }
+
+    /*
+        Checks the cached size and mtime against a fresh stat of the working file.
+    */
+    pub fn stat_matches(&self, stat: &Metadata) -> bool {
+        let size = u32_be(stat.size());
+        let mtime = u32_be(stat.mtime() as u64);
+        let mtime_nsec = u32_be(stat.mtime_nsec() as u64);
+        self.size == size && self.mtime == mtime && self.mtime_nsec == mtime_nsec
+    }
+
+    /*
+        Whether the working file is no longer the kind of object the entry
+        records, such as a regular file replaced by a symlink or a
+        directory. Its content can't be compared then.
+    */
+    pub fn type_changed(&self, stat: &Metadata) -> bool {
+        self.mode() & MODE_TYPE_MASK != mode_bits(stat) & MODE_TYPE_MASK
+    }
+
+    /*
+        Replaces the cached stat fields with a fresh stat of the working
+        file, keeping the object id, mode and flags.
+    */
+    pub fn refresh_stat(&mut self, stat: Metadata) {
+        let fresh = Entry::from_stat(&self.path, &self.oid, &stat);
+        *self = Entry {
+            mode: self.mode,
+            flags: self.flags,
+            extended_flags: self.extended_flags,
+            oid: self.oid,
+            path: std::mem::take(&mut self.path),
+            ..fresh
+        };
+    }
+
+    /*
+        Parses a single entry starting at the beginning of data.
+        Returns the entry along with the number of bytes it occupied,
+        including the trailing NUL padding. Version 4 paths are stored
+        relative to prev_path, the path of the previous entry.
+    */
+    fn parse(data: &[u8], version: u32, prev_path: &str) -> Option<(Self, usize)> {
+        if data.len() < ENTRY_FIXED_SIZE {
+            return None;
         }
+        let word = |i: usize| -> [u8; 4] { data[i * 4..i * 4 + 4].try_into().unwrap() };
+        let flags: [u8; 2] = data[60..62].try_into().unwrap();
 
-        // Just check executable bit for mode.
-        let mode: [u8; 4] = if stat.permissions().mode() & 0o100 != 0 {
-            // 0o100755 -> 0x81ED -> Big-endian padded with zero's.
-            [0x00, 0x00, 0x81, 0xED]
+        // Version 3 entries may carry a second flags word before the path.
+        let mut path_start = ENTRY_FIXED_SIZE;
+        let mut extended_flags = [0, 0];
+        if version >= 3 && u16::from_be_bytes(flags) & FLAG_EXTENDED != 0 {
+            extended_flags = data.get(62..64)?.try_into().unwrap();
+            path_start += 2;
+        }
+        // Version 4 prefixes the path with how many trailing bytes of the
+        // previous path to drop before appending the rest.
+        let mut prefix = "";
+        if version >= 4 {
+            let (strip, len) = decode_varint(data.get(path_start..)?)?;
+            prefix = prev_path.get(..prev_path.len().checked_sub(strip)?)?;
+            path_start += len;
+        }
+        let path_end = data.get(path_start..)?.iter().position(|&b| b == 0)? + path_start;
+        let suffix = std::str::from_utf8(&data[path_start..path_end]).ok()?;
+        let path = format!("{}{}", prefix, suffix);
+
+        // Entries are padded with 1-8 NUL bytes to a multiple of 8, except
+        // in version 4 where only the terminating NUL follows the path.
+        let size = if version >= 4 {
+            path_end + 1
         } else {
-            // 0o100644 -> 0x81A4 -> Big-endian padded with zero's.
-            [0x00, 0x00, 0x81, 0xA4]
+            (path_end + 8) & !7
         };
+        if size > data.len() {
+            return None;
+        }
 
-        let flag = cmp::min(0xFFF, pathname.len());
+        let entry = Entry {
+            ctime: word(0),
+            ctime_nsec: word(1),
+            mtime: word(2),
+            mtime_nsec: word(3),
+            dev: word(4),
+            ino: word(5),
+            mode: word(6),
+            uid: word(7),
+            gid: word(8),
+            size: word(9),
+            oid: data[40..60].try_into().ok()?,
+            flags,
+            extended_flags,
+            path,
+        };
+        Some((entry, size))
+    }
 
-        Entry {
-            ctime: stat.ctime().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting ctime."),
-            ctime_nsec: stat.ctime_nsec().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting ctime_nsec."),
-            mtime: stat.mtime().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting ctime."),
-            mtime_nsec: stat.mtime_nsec().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting mtime_nsec."),
-            dev: stat.dev().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting dev."),
-            ino: stat.ino().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting ino."),
-            mode: mode,
-            uid: stat
-                .uid()
-                .to_be_bytes()
-                .try_into()
-                .expect("failure getting uid."),
-            gid: stat
-                .gid()
-                .to_be_bytes()
-                .try_into()
-                .expect("failure getting gid."),
-            size: stat.size().to_be_bytes()[4..8]
-                .try_into()
-                .expect("failure getting size."),
-            oid: Vec::from(object_id),
-            flags: flag.to_be_bytes()[6..8]
-                .try_into()
-                .expect("failure setting file size flag."),
-            path: pathname,
-        }
-    }
-
-    fn to_string(&self) -> String {
+    fn to_bytes(&self) -> Vec<u8> {
         let mut res: Vec<u8> = Vec::new();
         res.extend_from_slice(&self.ctime);
         res.extend_from_slice(&self.ctime_nsec);
@@ -107,26 +402,305 @@ impl Entry {
         res.extend_from_slice(&self.size);
         res.extend_from_slice(&self.oid);
         res.extend_from_slice(&self.flags);
-        res.extend_from_slice(&self.path.as_bytes());
-        if res.len() % 8 == 0 {
+        if self.is_extended() {
+            res.extend_from_slice(&self.extended_flags);
+        }
+        res.extend_from_slice(self.path.as_bytes());
+        if res.len().is_multiple_of(8) {
             res.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
         } else {
-            while res.len() % 8 != 0 {
+            while !res.len().is_multiple_of(8) {
                 res.push(0);
             }
         }
-        let mut s = String::new();
-        unsafe {
-            s = String::from_utf8_unchecked(res);
+        res
+    }
+}
+
+/*
+    The 12 byte header that starts every index file.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexHeader {
+    pub signature: [u8; 4],
+    pub version: u32,
+    // The number of entries the header declares, which a damaged index
+    // may not actually hold.
+    pub entry_count: u32,
+}
+
+impl IndexHeader {
+    fn parse(data: &[u8; HEADER_SIZE]) -> Result<Self, IndexError> {
+        let word = |i: usize| -> [u8; 4] { data[i * 4..i * 4 + 4].try_into().unwrap() };
+        let header = IndexHeader {
+            signature: word(0),
+            version: u32::from_be_bytes(word(1)),
+            entry_count: u32::from_be_bytes(word(2)),
+        };
+        if &header.signature != b"DIRC" {
+            return Err(IndexError::BadSignature);
+        }
+        if !SUPPORTED_VERSIONS.contains(&header.version) {
+            return Err(IndexError::BadVersion(header.version));
+        }
+        Ok(header)
+    }
+}
+
+// The extensions of an index file this index understands.
+#[derive(Default)]
+struct Extensions {
+    tree_cache: Option<CacheTree>,
+    resolve_undo: Vec<ResolveUndo>,
+}
+
+/*
+    Parses raw index file contents, checking the header and trailing checksum.
+    Entries are returned in the order they appear in the file, followed by
+    the extensions that were understood.
+*/
+fn parse_index(data: &[u8]) -> Result<(IndexHeader, Vec<Entry>, Extensions), IndexError> {
+    if data.len() < HEADER_SIZE + CHECKSUM_SIZE {
+        return Err(IndexError::CorruptHeader);
+    }
+    let header = IndexHeader::parse(data[..HEADER_SIZE].try_into().unwrap())?;
+    let version = header.version;
+    let count = header.entry_count as usize;
+
+    let (content, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+    if hash::oid_bytes(content) != checksum {
+        return Err(IndexError::BadChecksum);
+    }
+
+    // A corrupt count is rejected before any entry is read.
+    let max = (content.len() - HEADER_SIZE) / ENTRY_MIN_SIZE;
+    if count > max {
+        return Err(IndexError::ImpossibleEntryCount(header.entry_count, max));
+    }
+
+    let mut entries: Vec<Entry> = Vec::with_capacity(count);
+    let mut offset = HEADER_SIZE;
+    for n in 0..count {
+        let prev_path = entries.last().map_or("", |entry| entry.path.as_str());
+        match Entry::parse(&content[offset..], version, prev_path) {
+            Some((entry, size)) => {
+                entries.push(entry);
+                offset += size;
+            }
+            None => return Err(IndexError::TruncatedEntry(n)),
+        }
+    }
+
+    // Extensions follow the entries as a signature, a size and the data.
+    // Only TREE and REUC are understood and written back. Optional
+    // extensions, whose names start with an uppercase letter, are dropped.
+    // Others are required to read the index correctly, like the "link" of
+    // a split index sharing entries with a base index we don't maintain.
+    let mut extensions = Extensions::default();
+    while offset < content.len() {
+        let signature = content.get(offset..offset + 4);
+        let size = content
+            .get(offset + 4..offset + 8)
+            .map(|size| u32::from_be_bytes(size.try_into().unwrap()) as usize);
+        let name = String::from_utf8_lossy(signature.unwrap_or(b"")).to_string();
+        let (Some(signature), Some(size)) = (signature, size) else {
+            return Err(IndexError::CorruptExtension(name));
+        };
+        let start = offset + 8;
+        let data = content
+            .get(start..start.saturating_add(size))
+            .ok_or_else(|| IndexError::CorruptExtension(name.clone()))?;
+        match signature {
+            b"TREE" => {
+                extensions.tree_cache =
+                    Some(CacheTree::parse(data).ok_or(IndexError::CorruptExtension(name))?);
+            }
+            b"REUC" => {
+                extensions.resolve_undo =
+                    ResolveUndo::parse(data).ok_or(IndexError::CorruptExtension(name))?;
+            }
+            // The untracked cache records directory stats this index never
+            // refreshes, so it is skipped rather than kept stale.
+            b"UNTR" => {}
+            _ if signature[0].is_ascii_uppercase() => {}
+            _ => return Err(IndexError::UnsupportedExtension(name)),
         }
-        return s;
+        offset = start + size;
     }
+    Ok((header, entries, extensions))
+}
+
+/*
+    Decodes the variable length integer used by version 4 paths, returning
+    the value and the number of bytes read. Each continuation adds one
+    before shifting, so every value has exactly one encoding.
+*/
+fn decode_varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut bytes = data.iter();
+    let mut byte = *bytes.next()?;
+    let mut value = (byte & 0x7f) as usize;
+    let mut len = 1;
+    while byte & 0x80 != 0 {
+        byte = *bytes.next()?;
+        value = (value + 1).checked_mul(128)? + (byte & 0x7f) as usize;
+        len += 1;
+    }
+    Some((value, len))
+}
+
+/*
+    Scans an index file entry by entry without building an Index.
+    Only the path of each entry is decoded, the rest is skipped.
+    The trailing checksum is not verified since reading may stop early.
+*/
+pub struct IndexReader<R: BufRead> {
+    reader: R,
+    version: u32,
+    count: usize,
+    position: usize,
+    // Needed to expand version 4 prefix compressed paths.
+    last_path: Vec<u8>,
+}
+
+impl IndexReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, IndexError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> IndexReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, IndexError> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| IndexError::CorruptHeader)?;
+        let header = IndexHeader::parse(&header)?;
+        Ok(Self {
+            reader,
+            version: header.version,
+            count: header.entry_count as usize,
+            position: 0,
+            last_path: Vec::new(),
+        })
+    }
+
+    /*
+        Checks whether path is tracked. Entries are sorted, so scanning
+        stops at the first entry that is equal to or sorts after path.
+    */
+    pub fn contains(&mut self, path: &str) -> Result<bool, IndexError> {
+        while let Some(next) = self.next_path()? {
+            match next.as_str().cmp(path) {
+                cmp::Ordering::Less => continue,
+                cmp::Ordering::Equal => return Ok(true),
+                cmp::Ordering::Greater => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+
+    fn next_path(&mut self) -> Result<Option<String>, IndexError> {
+        if self.position == self.count {
+            return Ok(None);
+        }
+        let position = self.position;
+        let truncated = |_| IndexError::TruncatedEntry(position);
+
+        let mut fixed = [0u8; ENTRY_FIXED_SIZE];
+        self.reader.read_exact(&mut fixed).map_err(truncated)?;
+        let flags = u16::from_be_bytes(fixed[60..62].try_into().unwrap());
+        let mut path_start = ENTRY_FIXED_SIZE;
+        if self.version >= 3 && flags & FLAG_EXTENDED != 0 {
+            self.reader.read_exact(&mut [0u8; 2]).map_err(truncated)?;
+            path_start += 2;
+        }
+
+        let mut path = Vec::new();
+        if self.version >= 4 {
+            let strip = self
+                .read_varint()
+                .ok_or(IndexError::TruncatedEntry(position))?;
+            let keep = self
+                .last_path
+                .len()
+                .checked_sub(strip)
+                .ok_or(IndexError::TruncatedEntry(position))?;
+            path.extend_from_slice(&self.last_path[..keep]);
+        }
+        let prefix_len = path.len();
+        self.reader.read_until(0, &mut path).map_err(truncated)?;
+        if path.pop() != Some(0) {
+            return Err(IndexError::TruncatedEntry(position));
+        }
+        if self.version < 4 {
+            // Skip the rest of the NUL padding, one NUL was consumed with the path.
+            let stored = path.len() - prefix_len;
+            let size = (path_start + stored + 8) & !7;
+            let padding = size - (path_start + stored + 1);
+            self.reader
+                .read_exact(&mut vec![0u8; padding])
+                .map_err(truncated)?;
+        }
+
+        self.position += 1;
+        self.last_path = path.clone();
+        String::from_utf8(path)
+            .map(Some)
+            .map_err(|_| IndexError::TruncatedEntry(position))
+    }
+
+    fn read_varint(&mut self) -> Option<usize> {
+        let mut byte = [0u8; 1];
+        self.reader.read_exact(&mut byte).ok()?;
+        let mut value = (byte[0] & 0x7f) as usize;
+        while byte[0] & 0x80 != 0 {
+            self.reader.read_exact(&mut byte).ok()?;
+            value = (value + 1).checked_mul(128)? + (byte[0] & 0x7f) as usize;
+        }
+        Some(value)
+    }
+}
+
+/*
+    Loads the index at path and checks that entries are strictly sorted
+    with no duplicates, returning the parsed header on success.
+    The first anomaly found is reported as an error.
+*/
+pub fn verify_index(path: &Path) -> Result<IndexHeader, IndexError> {
+    let data = fs::read(path)?;
+    let (header, entries, _) = parse_index(&data)?;
+    for pair in entries.windows(2) {
+        let (prev, next) = (&pair[0].path, &pair[1].path);
+        if prev == next {
+            return Err(IndexError::DuplicateEntry(next.clone()));
+        }
+        if prev > next {
+            return Err(IndexError::UnsortedEntries(prev.clone(), next.clone()));
+        }
+    }
+    Ok(header)
 }
 
 pub struct Index {
     keys: Vec<String>,
     entries: HashMap<String, Entry>,
+    // Tracked paths by their lowercased form, to find paths differing only
+    // in case without scanning every entry.
+    folded: HashMap<String, Vec<String>>,
     lockfile: LockFile,
+    lock_timeout: Duration,
+    // The header of the file this index was loaded from, if any.
+    header: Option<IndexHeader>,
+    // Set when entries change after loading, so callers know to write.
+    changed: bool,
+    // The modification time of the index file when it was last loaded or
+    // written. Entries modified at or after it may be racily clean.
+    mtime: Option<SystemTime>,
+    // Tree ids from the TREE extension, invalidated as entries change.
+    tree_cache: Option<CacheTree>,
+    // Conflicts resolved in this index, from the REUC extension, sorted
+    // by path.
+    resolve_undo: Vec<ResolveUndo>,
     digest: CoreWrapper<Sha1Core>,
 }
 
@@ -135,11 +709,356 @@ impl Index {
         Self {
             keys: Vec::new(),
             entries: HashMap::new(),
+            folded: HashMap::new(),
             lockfile: LockFile::new(path),
+            lock_timeout: Duration::ZERO,
+            header: None,
+            changed: false,
+            mtime: None,
+            tree_cache: None,
+            resolve_undo: Vec::new(),
             digest: Sha1::new(),
         }
     }
 
+    /*
+        Reads the index file at path. The header and checksum are validated,
+        and any anomaly is returned as an IndexError. A missing index file
+        is not an error, it is treated as an empty index.
+
+        Writers only ever replace the index by renaming a complete
+        index.lock over it, so reading never sees a partial write. The data
+        and mtime both come from the one open file, even if a writer
+        replaces it meanwhile.
+    */
+    pub fn load(path: PathBuf) -> Result<Self, IndexError> {
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::new(path)),
+            Err(err) => return Err(IndexError::Io(err)),
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut index = Self::new(path);
+        index.mtime = file.metadata().and_then(|stat| stat.modified()).ok();
+        let (header, entries, extensions) = parse_index(&data)?;
+        for entry in entries {
+            index.insert(entry);
+        }
+        index.header = Some(header);
+        index.tree_cache = extensions.tree_cache;
+        index.resolve_undo = extensions.resolve_undo;
+        Ok(index)
+    }
+
+    /*
+        Takes the index lock, then reads the index at path the way load
+        does. A writer that loads first and locks later can overwrite
+        entries another process wrote meanwhile; holding the lock across
+        the read and the write_updates that follows makes the update
+        atomic. Returns None when the lock is still held after timeout.
+        The lock is released by write_updates or when the index is dropped.
+    */
+    pub fn load_for_update(path: PathBuf, timeout: Duration) -> Result<Option<Self>, IndexError> {
+        let mut lockfile = LockFile::new(path.clone());
+        match lockfile.hold_for_update_with_timeout(timeout) {
+            Ok(()) => {}
+            Err(LockfileError::LockHeld) => return Ok(None),
+            Err(err) => return Err(IndexError::Lock(err)),
+        }
+        let mut index = Self::load(path)?;
+        index.lockfile = lockfile;
+        index.lock_timeout = timeout;
+        Ok(Some(index))
+    }
+
+    /*
+        The header as read by load, or None when there was no index file.
+        Its entry count is the declared one, compare it with len to spot
+        duplicate entries that collapsed on load.
+    */
+    pub fn header(&self) -> Option<&IndexHeader> {
+        self.header.as_ref()
+    }
+
+    /*
+        The modification time of the index file as of the last load or
+        write_updates, or None when it has never existed.
+    */
+    pub fn mtime(&self) -> Option<SystemTime> {
+        self.mtime
+    }
+
+    /*
+        Whether entry was modified no earlier than the index file was
+        written. Its file may then have changed again within the same
+        timestamp after being staged, so a matching stat proves nothing
+        and the content has to be compared.
+    */
+    pub fn is_racily_clean(&self, entry: &Entry) -> bool {
+        let Some(index_mtime) = self.mtime else {
+            return false;
+        };
+        let mtime = UNIX_EPOCH
+            + Duration::new(
+                u32::from_be_bytes(entry.mtime).into(),
+                u32::from_be_bytes(entry.mtime_nsec),
+            );
+        mtime >= index_mtime
+    }
+
+    /*
+        Removes every entry while keeping the lockfile for this index path,
+        so the next write_updates produces a valid empty index.
+    */
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.entries.clear();
+        self.folded.clear();
+        self.tree_cache = None;
+        self.resolve_undo.clear();
+        self.digest = Sha1::new();
+        self.changed = true;
+    }
+
+    /*
+        How long write_updates waits for another process to release the
+        index lock before giving up. Defaults to not waiting.
+    */
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+
+    /*
+        Replaces the contents of the index with the files of the tree with
+        the given hex object id. The index is not written.
+    */
+    pub fn read_tree(&mut self, database: &Database, tree_oid: &str) -> io::Result<()> {
+        let files = tree::flatten_tree(database, tree_oid)?;
+        self.clear();
+        for (path, file) in files {
+            let mode = u32::from_str_radix(&file.mode, 8).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad mode {} for {}", file.mode, path),
+                )
+            })?;
+            let object_id = utils::hex_to_u8(&file.object_id).unwrap();
+            self.insert(Entry::from_tree(&path, mode, hash::raw_oid(&object_id)?));
+        }
+        Ok(())
+    }
+
+    /*
+        An index for committing only paths: the files of the tree with hex
+        id tree_oid, or nothing for an unborn HEAD, overlaid with the
+        entries of this index at or under each path. A path this index no
+        longer tracks is removed. The partial index is never written and
+        this index is left unchanged. Errors when a path matches nothing in
+        either.
+    */
+    pub fn partial(
+        &self,
+        database: &Database,
+        tree_oid: Option<&str>,
+        paths: &[String],
+    ) -> io::Result<Index> {
+        let mut partial = Index::new(self.lockfile.file_path.with_extension("partial"));
+        if let Some(tree_oid) = tree_oid {
+            partial.read_tree(database, tree_oid)?;
+        }
+        let under = |key: &str, path: &str| {
+            path == "."
+                || key == path
+                || key
+                    .strip_prefix(path)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        };
+        for path in paths {
+            let staged = self
+                .keys
+                .iter()
+                .filter(|key| under(key, path))
+                .filter_map(|key| self.entries.get(key))
+                .filter(|entry| !entry.intent_to_add())
+                .cloned()
+                .collect::<Vec<_>>();
+            let removed = partial
+                .keys
+                .iter()
+                .filter(|key| under(key, path) && !self.entries.contains_key(key.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+            if staged.is_empty() && removed.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("pathspec '{}' did not match any file(s) known to git", path),
+                ));
+            }
+            for entry in staged {
+                partial.insert(entry);
+            }
+            for key in removed {
+                partial.remove(&key);
+            }
+        }
+        Ok(partial)
+    }
+
+    /*
+        The hex object id of the root tree write-tree would produce for this
+        index, computed in memory without storing any objects.
+    */
+    pub fn tree_oid_dry(&self) -> String {
+        let mut entries = self
+            .entries
+            .values()
+            .filter(|entry| !entry.intent_to_add())
+            .collect::<Vec<&Entry>>();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let entries = entries.into_iter().map(entry::Entry::from_index).collect();
+        let mut tree = tree::Tree::build(entries);
+        tree.compute_object_ids();
+        utils::u8_to_hex_str(tree.object_id.into_bytes())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Entry> {
+        self.entries.get(path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /*
+        Removes the entry for path, returning whether it was tracked.
+    */
+    pub fn remove(&mut self, path: &str) -> bool {
+        if self.entries.remove(path).is_none() {
+            return false;
+        }
+        if let Some(cache) = &mut self.tree_cache {
+            cache.invalidate(path);
+        }
+        self.keys.retain(|key| key != path);
+        let folded = path.to_lowercase();
+        if let Some(paths) = self.folded.get_mut(&folded) {
+            paths.retain(|key| key != path);
+            if paths.is_empty() {
+                self.folded.remove(&folded);
+            }
+        }
+        true
+    }
+
+    /*
+        Finds a tracked path that differs from path only in letter case.
+    */
+    pub fn find_case_collision(&self, path: &str) -> Option<&str> {
+        self.folded
+            .get(&path.to_lowercase())?
+            .iter()
+            .find(|key| key.as_str() != path)
+            .map(String::as_str)
+    }
+
+    /*
+        Sets or clears the skip-worktree flag on a tracked path.
+        Returns false if the path is not in the index.
+    */
+    pub fn set_skip_worktree(&mut self, path: &str, skip: bool) -> bool {
+        match self.entries.get_mut(path) {
+            Some(entry) => {
+                entry.set_skip_worktree(skip);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /*
+        Refreshes the cached stat of a tracked path whose content is known
+        to match the index. Returns false if the path is not in the index.
+    */
+    pub fn refresh_stat(&mut self, path: &str, stat: Metadata) -> bool {
+        match self.entries.get_mut(path) {
+            Some(entry) => {
+                entry.refresh_stat(stat);
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /*
+        Whether any cached stat or the tree cache was updated since the
+        index was loaded.
+    */
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /*
+        The cached tree ids, valid for every directory whose entries haven't
+        changed since the cache was set or loaded.
+    */
+    pub fn tree_cache(&self) -> Option<&CacheTree> {
+        self.tree_cache.as_ref()
+    }
+
+    /*
+        Replaces the tree cache with the one for a tree just written from
+        this index. Directories holding intent-to-add entries are left
+        invalid, since their trees leave those entries out.
+    */
+    pub fn set_tree_cache(&mut self, mut cache: CacheTree) {
+        for entry in self.entries.values().filter(|entry| entry.intent_to_add()) {
+            cache.invalidate(&entry.path);
+        }
+        if self.tree_cache.as_ref() != Some(&cache) {
+            self.tree_cache = Some(cache);
+            self.changed = true;
+        }
+    }
+
+    pub fn resolve_undo(&self) -> &[ResolveUndo] {
+        &self.resolve_undo
+    }
+
+    /*
+        Records the stages of a conflict that was resolved, replacing any
+        record for the same path.
+    */
+    pub fn record_resolve_undo(&mut self, record: ResolveUndo) {
+        match self
+            .resolve_undo
+            .binary_search_by(|existing| existing.path.cmp(&record.path))
+        {
+            Ok(i) => self.resolve_undo[i] = record,
+            Err(i) => self.resolve_undo.insert(i, record),
+        }
+        self.changed = true;
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        if let Some(cache) = &mut self.tree_cache {
+            cache.invalidate(&entry.path);
+        }
+        if !self.entries.contains_key(&entry.path) {
+            self.keys.push(entry.path.clone());
+            self.folded
+                .entry(entry.path.to_lowercase())
+                .or_default()
+                .push(entry.path.clone());
+        }
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
     pub fn each_entry(&mut self) -> Vec<Entry> {
         self.keys.sort();
         let mut entries = Vec::new();
@@ -150,71 +1069,1214 @@ impl Index {
         entries
     }
 
-    pub fn add(&mut self, path: &PathBuf, object_id: &str, stat: Metadata) {
-        let entry = Entry::new(path.clone(), object_id, stat);
-        let mut pathname = String::new();
-        match path.to_str() {
-            Some(s) => {
-                pathname = String::from(s);
-            }
-            None => {
-                eprintln!("Error reading pathname.");
-                panic!();
+    pub fn add(
+        &mut self,
+        path: &Path,
+        object_id: &[u8; OID_SIZE],
+        stat: Metadata,
+    ) -> io::Result<()> {
+        let entry = Entry::new(path.to_path_buf(), object_id, stat)?;
+        self.insert(entry);
+        Ok(())
+    }
+
+    /*
+        Records an entry built elsewhere, such as from a tree, replacing
+        any entry at its path.
+    */
+    pub fn add_entry(&mut self, entry: Entry) {
+        self.insert(entry);
+    }
+
+    /*
+        Records path as intended to be added later. The entry points at the
+        empty blob and carries the intent-to-add extended flag. A path that
+        is already tracked keeps its entry.
+    */
+    pub fn add_intent_to_add(&mut self, path: &Path, stat: Metadata) -> io::Result<()> {
+        let empty_blob = utils::hex_to_u8(EMPTY_BLOB_OID).unwrap();
+        let mut entry = Entry::new(path.to_path_buf(), hash::raw_oid(&empty_blob)?, stat)?;
+        if self.entries.contains_key(entry.path()) {
+            return Ok(());
+        }
+        entry.set_intent_to_add();
+        self.insert(entry);
+        Ok(())
+    }
+
+    /*
+        Writes the blob of every entry into the workspace, creating parent
+        directories and applying the recorded mode. Existing files are only
+        overwritten when force is set, the paths that were left alone are
+        returned.
+    */
+    pub fn checkout_index(
+        &mut self,
+        workspace: &Workspace,
+        database: &Database,
+        force: bool,
+    ) -> io::Result<Vec<String>> {
+        let mut skipped = Vec::new();
+        for entry in self.each_entry() {
+            let path = Path::new(&entry.path);
+            if !force && workspace.symlink_metadata(path).is_ok() {
+                skipped.push(entry.path.clone());
+                continue;
             }
+            let (_, data) = database.read_object(&utils::u8_to_hex_str(entry.oid.to_vec()))?;
+            workspace.write_blob(path, data, u32::from_be_bytes(entry.mode))?;
         }
-        self.entries.insert(pathname.clone(), entry);
-        self.keys.push(pathname);
+        Ok(skipped)
     }
 
-    pub fn write_updates(&mut self) -> bool {
-        let lock_res = self.lockfile.hold_for_update();
-        let bool = match lock_res {
-            Ok(_) => true,
-            Err(_) => false,
-        };
-        if !bool {
-            return bool;
+    /*
+        Writes the index through its lockfile. Returns false when another
+        process holds the lock, and an error when the index can't be
+        represented or written. A failed write releases the lock and leaves
+        the old index in place.
+    */
+    pub fn write_updates(&mut self) -> Result<bool, IndexError> {
+        // Check the count before taking the lock so an oversized index
+        // never leaves a half written lockfile behind.
+        let count = header_count(self.entries.len())?;
+        match self
+            .lockfile
+            .hold_for_update_with_timeout(self.lock_timeout)
+        {
+            Ok(()) => {}
+            Err(LockfileError::LockHeld) => return Ok(false),
+            Err(err) => return Err(IndexError::Lock(err)),
+        }
+        let result = self.write_contents(count);
+        if result.is_err() && self.lockfile.is_held() {
+            let _ = self.lockfile.rollback();
         }
+        result.map(|_| true)
+    }
 
+    fn write_contents(&mut self, count: u32) -> Result<(), IndexError> {
         // hash index header
         let mut header: Vec<u8> = Vec::new();
         header.extend_from_slice(String::from("DIRC").as_bytes());
-        let size: [u8; 4] = self.entries.len().to_be_bytes()[4..8]
-            .try_into()
-            .expect("failure getting ino.");
-        header.extend_from_slice(&[0x00, 0x00, 0x00, 0x02]);
-        header.extend_from_slice(&size);
-        self.write(header);
-
-        let mut data_vec = Vec::new();
-        for entry in &mut self.each_entry() {
-            let data = Vec::from(entry.clone().to_string().as_bytes());
-            data_vec.push(data);
-        }
-        for data in data_vec {
-            self.write(data);
-        }
-        self.finish_write();
-        true
+        // Extended flags need version 3, otherwise stay on version 2.
+        let version: u32 = if self.entries.values().any(Entry::is_extended) {
+            3
+        } else {
+            2
+        };
+        header.extend_from_slice(&version.to_be_bytes());
+        header.extend_from_slice(&count.to_be_bytes());
+        self.write(header)?;
+
+        // Readers binary search the entries, so an out of order index is
+        // corrupt. Catch any iteration that bypasses the sorted keys.
+        let entries = self.each_entry();
+        debug_assert!(
+            entries.windows(2).all(|pair| pair[0].path < pair[1].path),
+            "index entries must be serialized in strictly ascending path order"
+        );
+        for entry in &entries {
+            self.write(entry.to_bytes())?;
+        }
+        if let Some(cache) = &self.tree_cache {
+            self.write_extension(b"TREE", cache.to_bytes())?;
+        }
+        if !self.resolve_undo.is_empty() {
+            self.write_extension(b"REUC", ResolveUndo::to_bytes(&self.resolve_undo))?;
+        }
+        self.finish_write()
     }
 
-    pub fn write(&mut self, data: Vec<u8>) {
+    // Writes an extension as its signature, its size and its data.
+    fn write_extension(&mut self, signature: &[u8; 4], data: Vec<u8>) -> Result<(), IndexError> {
+        let mut extension = signature.to_vec();
+        extension.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        extension.extend_from_slice(&data);
+        self.write(extension)
+    }
+
+    pub fn write(&mut self, data: Vec<u8>) -> Result<(), IndexError> {
         unsafe {
-            let _ = self
-                .lockfile
-                .write(String::from_utf8_unchecked(data.clone()));
+            self.lockfile
+                .write(String::from_utf8_unchecked(data.clone()))
+                .map_err(IndexError::Lock)?;
         }
         self.digest.update(&data);
+        Ok(())
     }
 
-    pub fn finish_write(&mut self) {
-        let hash_result = &self.digest.clone().finalize();
+    /*
+        Appends the checksum of everything written and commits the lockfile.
+        The digest is swapped for a fresh one, so the next write_updates
+        hashes only its own output.
+    */
+    pub fn finish_write(&mut self) -> Result<(), IndexError> {
+        let hash_result = std::mem::replace(&mut self.digest, Sha1::new()).finalize();
         let hash_result = hash_result.as_slice().to_vec();
         unsafe {
-            let _ = self
-                .lockfile
-                .write(String::from_utf8_unchecked(hash_result.clone()));
+            self.lockfile
+                .write(String::from_utf8_unchecked(hash_result.clone()))
+                .map_err(IndexError::Lock)?;
+        }
+        self.lockfile.commit().map_err(IndexError::Lock)?;
+        self.mtime = fs::metadata(&self.lockfile.file_path)
+            .and_then(|stat| stat.modified())
+            .ok();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use tempfile::TempDir;
+
+    use crate::commit;
+    use crate::test_support::TestRepo;
+
+    const OID: [u8; 20] = [0xab; 20];
+
+    // Serializes entries for paths, in the order given, into a version 2
+    // index file with a valid checksum.
+    fn raw_index(paths: &[&str]) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&(paths.len() as u32).to_be_bytes());
+        for path in paths {
+            data.extend_from_slice(&Entry::from_tree(path, 0o100644, &OID).to_bytes());
+        }
+        with_checksum(data)
+    }
+
+    fn with_checksum(mut data: Vec<u8>) -> Vec<u8> {
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    fn verify(data: &[u8]) -> Result<IndexHeader, IndexError> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        fs::write(&path, data).unwrap();
+        verify_index(&path)
+    }
+
+    #[test]
+    fn verify_accepts_sorted_index() {
+        let header = verify(&raw_index(&["a.txt", "b/c.txt", "d.txt"])).unwrap();
+        assert_eq!(header.entry_count, 3);
+        assert_eq!(header.version, 2);
+    }
+
+    #[test]
+    fn verify_reports_duplicate_entry() {
+        let err = verify(&raw_index(&["a.txt", "b.txt", "b.txt"])).unwrap_err();
+        assert!(matches!(err, IndexError::DuplicateEntry(ref path) if path == "b.txt"));
+        assert_eq!(err.to_string(), "duplicate index entry 'b.txt'");
+    }
+
+    #[test]
+    fn verify_reports_out_of_order_entries() {
+        let err = verify(&raw_index(&["a.txt", "c.txt", "b.txt"])).unwrap_err();
+        assert!(matches!(
+            err,
+            IndexError::UnsortedEntries(ref prev, ref next) if prev == "c.txt" && next == "b.txt"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "index entries 'c.txt' and 'b.txt' are out of order"
+        );
+    }
+
+    #[test]
+    fn verify_reports_bad_checksum() {
+        let mut data = raw_index(&["a.txt"]);
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let err = verify(&data).unwrap_err();
+        assert!(matches!(err, IndexError::BadChecksum));
+        assert_eq!(err.to_string(), "index file checksum mismatch");
+    }
+
+    #[test]
+    fn verify_reports_bad_signature() {
+        let mut data = raw_index(&["a.txt"]);
+        data[0..4].copy_from_slice(b"CRID");
+        let len = data.len() - CHECKSUM_SIZE;
+        let checksum = Sha1::digest(&data[..len]);
+        data[len..].copy_from_slice(&checksum);
+        assert!(matches!(verify(&data), Err(IndexError::BadSignature)));
+    }
+
+    #[test]
+    fn intent_to_add_records_placeholder_entry() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("new.txt");
+        fs::write(&file, "content\n").unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        index
+            .add_intent_to_add(Path::new("new.txt"), fs::metadata(&file).unwrap())
+            .unwrap();
+
+        let entry = index.get("new.txt").unwrap();
+        assert!(entry.intent_to_add() && entry.is_extended());
+        assert_eq!(
+            utils::u8_to_hex_str(entry.oid.to_vec()),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+        assert!(index.write_updates().unwrap());
+        let index = Index::load(dir.path().join("index")).unwrap();
+        assert!(index.get("new.txt").unwrap().intent_to_add());
+    }
+
+    #[test]
+    fn intent_to_add_leaves_tracked_entry_alone() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "content\n").unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        index
+            .add(Path::new("a.txt"), &OID, fs::metadata(&file).unwrap())
+            .unwrap();
+        index
+            .add_intent_to_add(Path::new("a.txt"), fs::metadata(&file).unwrap())
+            .unwrap();
+
+        let entry = index.get("a.txt").unwrap();
+        assert!(!entry.intent_to_add());
+        assert_eq!(entry.oid, OID);
+    }
+
+    #[test]
+    fn load_treats_missing_index_as_empty() {
+        let dir = TempDir::new().unwrap();
+        let mut index = Index::load(dir.path().join("index")).unwrap();
+        assert!(index.each_entry().is_empty());
+        assert!(index.header().is_none());
+    }
+
+    #[test]
+    fn load_rejects_empty_index_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        fs::write(&path, b"").unwrap();
+        assert!(matches!(Index::load(path), Err(IndexError::CorruptHeader)));
+    }
+
+    // A smoke version of the write_updates benchmark in benches/index.rs.
+    // The bound is far above the linear cost and only catches staging or
+    // writing going quadratic.
+    #[test]
+    fn write_updates_smoke() {
+        let n = 50_000;
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "bench").unwrap();
+        let stat = fs::metadata(&file).unwrap();
+        let start = Instant::now();
+        let mut index = Index::new(dir.path().join("index"));
+        for i in 0..n {
+            let path = PathBuf::from(format!("dir{}/file{}.txt", i % 100, i));
+            index.add(&path, &OID, stat.clone()).unwrap();
+        }
+        assert!(index.write_updates().unwrap());
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < Duration::from_secs(5),
+            "staging and writing {} entries took {:?}",
+            n,
+            elapsed
+        );
+        let mut index = Index::load(dir.path().join("index")).unwrap();
+        assert_eq!(index.each_entry().len(), n);
+    }
+
+    // Counts the bytes taken from the wrapped reader.
+    struct CountingReader<'a> {
+        data: &'a [u8],
+        consumed: usize,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.data.read(buf)?;
+            self.consumed += n;
+            Ok(n)
+        }
+    }
+
+    impl BufRead for CountingReader<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            Ok(self.data)
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.data.consume(amt);
+            self.consumed += amt;
         }
-        let _ = self.lockfile.commit();
+    }
+
+    #[test]
+    fn reader_stops_after_early_path() {
+        let paths = (0..1000)
+            .map(|i| format!("file{:04}", i))
+            .collect::<Vec<_>>();
+        let data = raw_index(&paths.iter().map(String::as_str).collect::<Vec<_>>());
+
+        let mut counting = CountingReader {
+            data: &data,
+            consumed: 0,
+        };
+        {
+            let mut reader = IndexReader::new(&mut counting).unwrap();
+            assert!(reader.contains("file0001").unwrap());
+        }
+        // The header and the first two entries, each 72 bytes.
+        assert_eq!(counting.consumed, HEADER_SIZE + 2 * 72);
+    }
+
+    #[test]
+    fn reader_reports_untracked_path() {
+        let data = raw_index(&["a.txt", "c.txt", "d.txt"]);
+        let mut reader = IndexReader::new(data.as_slice()).unwrap();
+        assert!(!reader.contains("b.txt").unwrap());
+        let mut reader = IndexReader::new(data.as_slice()).unwrap();
+        assert!(reader.contains("d.txt").unwrap());
+        let mut reader = IndexReader::new(data.as_slice()).unwrap();
+        assert!(!reader.contains("e.txt").unwrap());
+    }
+
+    #[test]
+    fn mode_bits_follow_file_type() {
+        let dir = TempDir::new().unwrap();
+        let regular = dir.path().join("regular");
+        let executable = dir.path().join("executable");
+        let link = dir.path().join("link");
+        let gitlink = dir.path().join("submodule");
+        fs::write(&regular, "").unwrap();
+        fs::set_permissions(&regular, fs::Permissions::from_mode(0o664)).unwrap();
+        fs::write(&executable, "").unwrap();
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o744)).unwrap();
+        std::os::unix::fs::symlink("regular", &link).unwrap();
+        fs::create_dir(&gitlink).unwrap();
+
+        let mode = |path: &Path| {
+            let stat = fs::symlink_metadata(path).unwrap();
+            Entry::new(PathBuf::from("path"), &OID, stat)
+                .unwrap()
+                .to_bytes()[24..28]
+                .to_vec()
+        };
+        assert_eq!(mode(&regular), [0x00, 0x00, 0x81, 0xa4]);
+        assert_eq!(mode(&executable), [0x00, 0x00, 0x81, 0xed]);
+        assert_eq!(mode(&link), [0x00, 0x00, 0xa0, 0x00]);
+        assert_eq!(mode(&gitlink), [0x00, 0x00, 0xe0, 0x00]);
+    }
+
+    #[test]
+    fn checkout_index_restores_deleted_file() {
+        let repo = TestRepo::new();
+        repo.write("bin/run.sh", "#!/bin/sh\n");
+        let script = repo.root().join("bin/run.sh");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        repo.write("kept.txt", "local\n");
+        repo.stage(&["."]);
+        repo.write("kept.txt", "changed locally\n");
+        fs::remove_dir_all(repo.root().join("bin")).unwrap();
+
+        let mut index = repo.index();
+        let skipped = index
+            .checkout_index(&repo.workspace(), &repo.database(), false)
+            .unwrap();
+        assert_eq!(skipped, vec![String::from("kept.txt")]);
+        assert_eq!(fs::read_to_string(&script).unwrap(), "#!/bin/sh\n");
+        let mode = fs::metadata(&script).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_to_string(repo.root().join("kept.txt")).unwrap(),
+            "changed locally\n"
+        );
+
+        let skipped = index
+            .checkout_index(&repo.workspace(), &repo.database(), true)
+            .unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(
+            fs::read_to_string(repo.root().join("kept.txt")).unwrap(),
+            "local\n"
+        );
+    }
+
+    #[test]
+    fn case_collisions_follow_entries() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let stat = fs::metadata(&file).unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        index
+            .add(Path::new("docs/Readme"), &OID, stat.clone())
+            .unwrap();
+        index.add(Path::new("docs/README"), &OID, stat).unwrap();
+        assert_eq!(
+            index.find_case_collision("docs/readme"),
+            Some("docs/Readme")
+        );
+        assert_eq!(
+            index.find_case_collision("docs/Readme"),
+            Some("docs/README")
+        );
+        assert_eq!(index.find_case_collision("docs/other"), None);
+
+        index.remove("docs/README");
+        assert_eq!(index.find_case_collision("docs/Readme"), None);
+        assert_eq!(
+            index.find_case_collision("DOCS/README"),
+            Some("docs/Readme")
+        );
+        index.clear();
+        assert_eq!(index.find_case_collision("DOCS/README"), None);
+    }
+
+    #[test]
+    fn clear_writes_valid_empty_index() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let stat = fs::metadata(&file).unwrap();
+        let path = dir.path().join("index");
+        let mut index = Index::new(path.clone());
+        index.add(Path::new("a.txt"), &OID, stat.clone()).unwrap();
+        index.add(Path::new("b.txt"), &OID, stat).unwrap();
+        assert!(index.write_updates().unwrap());
+
+        let mut index = Index::load(path.clone()).unwrap();
+        assert_eq!(index.each_entry().len(), 2);
+        assert!(!index.is_changed());
+        index.clear();
+        assert!(index.is_changed());
+        assert!(index.each_entry().is_empty());
+        assert!(index.find_case_collision("A.TXT").is_none());
+        assert!(index.write_updates().unwrap());
+
+        assert_eq!(verify_index(&path).unwrap().entry_count, 0);
+        assert!(Index::load(path).unwrap().each_entry().is_empty());
+    }
+
+    #[test]
+    fn oversized_index_is_an_error() {
+        let err = header_count(u32::MAX as usize + 1).unwrap_err();
+        assert!(matches!(err, IndexError::TooManyEntries(n) if n == u32::MAX as usize + 1));
+        assert_eq!(header_count(3).unwrap(), 3);
+    }
+
+    #[test]
+    fn held_lock_is_not_an_error() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("index.lock"), "").unwrap();
+        let stat = fs::metadata(dir.path().join("index.lock")).unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        index.add(Path::new("a.txt"), &OID, stat).unwrap();
+        assert!(!index.write_updates().unwrap());
+        assert!(!dir.path().join("index").exists());
+    }
+
+    #[test]
+    fn failed_commit_is_propagated_and_releases_the_lock() {
+        let dir = TempDir::new().unwrap();
+        // The lock can't be renamed over a non-empty directory.
+        let path = dir.path().join("index");
+        fs::create_dir_all(path.join("occupied")).unwrap();
+        let stat = fs::metadata(&path).unwrap();
+        let mut index = Index::new(path.clone());
+        index.add(Path::new("a.txt"), &OID, stat).unwrap();
+        let err = index.write_updates().unwrap_err();
+        assert!(matches!(err, IndexError::Lock(LockfileError::Io(_))));
+        assert!(!dir.path().join("index.lock").exists());
+        assert!(path.join("occupied").is_dir());
+    }
+
+    #[test]
+    fn load_for_update_keeps_concurrent_updates() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        thread::scope(|scope| {
+            for writer in 0..4 {
+                let path = &path;
+                scope.spawn(move || {
+                    for round in 0..25 {
+                        let mut index =
+                            Index::load_for_update(path.clone(), Duration::from_secs(5))
+                                .unwrap()
+                                .unwrap();
+                        let name = format!("w{}/{:02}.txt", writer, round);
+                        index.add_entry(Entry::from_tree(&name, 0o100644, &OID));
+                        assert!(index.write_updates().unwrap());
+                    }
+                });
+            }
+        });
+        assert_eq!(Index::load(path).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn load_for_update_gives_up_on_a_held_lock() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("index.lock"), "").unwrap();
+        let index = Index::load_for_update(dir.path().join("index"), Duration::ZERO).unwrap();
+        assert!(index.is_none());
+        assert!(dir.path().join("index.lock").exists());
+    }
+
+    #[test]
+    fn dropping_an_unwritten_index_releases_its_lock() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        let index = Index::load_for_update(path.clone(), Duration::ZERO)
+            .unwrap()
+            .unwrap();
+        assert!(dir.path().join("index.lock").exists());
+        drop(index);
+        assert!(!dir.path().join("index.lock").exists());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn len_and_is_empty_follow_entries() {
+        let dir = TempDir::new().unwrap();
+        let stat = fs::metadata(dir.path()).unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        assert_eq!((index.len(), index.is_empty()), (0, true));
+
+        index.add(Path::new("a.txt"), &OID, stat.clone()).unwrap();
+        assert_eq!((index.len(), index.is_empty()), (1, false));
+        // Re-adding a path replaces its entry rather than duplicating it.
+        index.add(Path::new("a.txt"), &OID, stat).unwrap();
+        assert_eq!(index.len(), 1);
+
+        assert!(index.remove("a.txt"));
+        assert!(!index.remove("a.txt"));
+        assert_eq!((index.len(), index.is_empty()), (0, true));
+        assert!(index.each_entry().is_empty());
+    }
+
+    #[test]
+    fn entry_stores_raw_oid_bytes() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "a").unwrap();
+        let stat = fs::metadata(&file).unwrap();
+        let hex = utils::hex_to_u8("2e65efe2a145dda7ee51d1741299f848e5bf752e").unwrap();
+        let oid = hash::raw_oid(&hex).unwrap();
+
+        let entry = Entry::new(PathBuf::from("a.txt"), oid, stat.clone()).unwrap();
+        assert_eq!(entry.oid.len(), 20);
+        assert_eq!(&entry.oid, oid);
+
+        let mut index = Index::new(dir.path().join("index"));
+        index.add(Path::new("a.txt"), oid, stat).unwrap();
+        assert!(index.write_updates().unwrap());
+        let index = Index::load(dir.path().join("index")).unwrap();
+        assert_eq!(&index.get("a.txt").unwrap().oid, oid);
+    }
+
+    #[test]
+    fn entry_rejects_hex_oid() {
+        let hex = "2e65efe2a145dda7ee51d1741299f848e5bf752e";
+        let err = hash::raw_oid(hex.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "object id must be 20 raw bytes, got 40");
+    }
+
+    #[test]
+    fn entry_rejects_non_utf8_path() {
+        let dir = TempDir::new().unwrap();
+        let stat = fs::metadata(dir.path()).unwrap();
+        let path = PathBuf::from(OsStr::from_bytes(b"bad\xff.txt"));
+        let err = Entry::new(path.clone(), &OID, stat.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut index = Index::new(dir.path().join("index"));
+        assert!(index.add(&path, &OID, stat).is_err());
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn read_tree_replaces_entries_with_tree_files() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        repo.write("lib/b.txt", "b\n");
+        repo.write("lib/deep/c.txt", "c\n");
+        repo.stage(&["."]);
+        let staged = repo.index().each_entry();
+        let mut tree = tree::Tree::from_index(&mut repo.index());
+        tree.store_tree(&repo.database());
+        let tree_oid = utils::u8_to_hex_str(tree.object_id.into_bytes());
+
+        let mut index = Index::new(repo.git_dir().join("other-index"));
+        index.insert(Entry::from_tree("stale.txt", 0o100644, &OID));
+        index.read_tree(&repo.database(), &tree_oid).unwrap();
+
+        let entries = index.each_entry();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect::<Vec<_>>(),
+            ["a.txt", "lib/b.txt", "lib/deep/c.txt"]
+        );
+        for (entry, staged) in entries.iter().zip(&staged) {
+            assert_eq!(entry.oid, staged.oid);
+            assert_eq!(u32::from_be_bytes(entry.mode), 0o100644);
+            // There is no working file to take stat data from yet.
+            assert_eq!(
+                (entry.mtime, entry.size, entry.ino),
+                ([0; 4], [0; 4], [0; 4])
+            );
+        }
+        assert!(index.write_updates().unwrap());
+        let reloaded = Index::load(repo.git_dir().join("other-index")).unwrap();
+        assert_eq!(reloaded.len(), 3);
+    }
+
+    // Every loose object file under the objects directory.
+    fn loose_objects(repo: &TestRepo) -> usize {
+        fs::read_dir(repo.git_dir().join("objects"))
+            .unwrap()
+            .map(|dir| fs::read_dir(dir.unwrap().path()).unwrap().count())
+            .sum()
+    }
+
+    #[test]
+    fn tree_oid_dry_matches_stored_tree_without_writing() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "hello\n");
+        repo.write("lib/b.txt", "world\n");
+        repo.stage(&["."]);
+        let index = repo.index();
+        let blobs = loose_objects(&repo);
+
+        let dry = index.tree_oid_dry();
+        assert_eq!(loose_objects(&repo), blobs);
+        // The id stock git write-tree prints for this layout.
+        assert_eq!(dry, "8522d47075ad7804031c0d8aca3d89f4fa480edd");
+
+        let mut tree = tree::Tree::from_index(&mut repo.index());
+        tree.store_tree(&repo.database());
+        assert_eq!(utils::u8_to_hex_str(tree.object_id.into_bytes()), dry);
+        assert_eq!(loose_objects(&repo), blobs + 2);
+    }
+
+    fn load_raw(data: &[u8]) -> Result<Index, IndexError> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        fs::write(&path, data).unwrap();
+        Index::load(path)
+    }
+
+    fn paths(index: &mut Index) -> Vec<String> {
+        index
+            .each_entry()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    #[test]
+    fn load_reads_version_2() {
+        let mut index = load_raw(&raw_index(&["a.txt", "b/c.txt"])).unwrap();
+        assert_eq!(paths(&mut index), ["a.txt", "b/c.txt"]);
+        assert_eq!(index.get("b/c.txt").unwrap().oid, OID);
+    }
+
+    #[test]
+    fn load_reads_version_3_extended_flags() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        let mut index = Index::new(path.clone());
+        index.insert(Entry::from_tree("a.txt", 0o100644, &OID));
+        index.insert(Entry::from_tree("b.txt", 0o100644, &OID));
+        assert!(index.set_skip_worktree("a.txt", true));
+        assert!(index.write_updates().unwrap());
+        assert_eq!(fs::read(&path).unwrap()[4..8], 3u32.to_be_bytes());
+
+        let mut index = Index::load(path).unwrap();
+        assert_eq!(paths(&mut index), ["a.txt", "b.txt"]);
+        assert!(index.get("a.txt").unwrap().skip_worktree());
+        assert!(!index.get("b.txt").unwrap().skip_worktree());
+    }
+
+    #[test]
+    fn load_reads_version_4_prefix_compressed_paths() {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&4u32.to_be_bytes());
+        data.extend_from_slice(&2u32.to_be_bytes());
+        // Each path strips bytes from the end of the previous one and
+        // appends a NUL terminated suffix.
+        for (path, strip, suffix) in [("lib/a.txt", 0, "lib/a.txt"), ("lib/b.rs", 5, "b.rs")] {
+            let fixed = Entry::from_tree(path, 0o100644, &OID).to_bytes();
+            data.extend_from_slice(&fixed[..62]);
+            data.push(strip);
+            data.extend_from_slice(suffix.as_bytes());
+            data.push(0);
+        }
+        let mut index = load_raw(&with_checksum(data)).unwrap();
+        assert_eq!(paths(&mut index), ["lib/a.txt", "lib/b.rs"]);
+    }
+
+    #[test]
+    fn load_rejects_unsupported_version() {
+        let mut data = raw_index(&["a.txt"]);
+        data[4..8].copy_from_slice(&99u32.to_be_bytes());
+        let data = with_checksum(data[..data.len() - 20].to_vec());
+        let err = load_raw(&data).err().unwrap();
+        assert!(matches!(err, IndexError::BadVersion(99)));
+        assert_eq!(err.to_string(), "bad index file version 99");
+    }
+
+    #[test]
+    fn load_rejects_impossible_entry_count() {
+        let mut data = raw_index(&["a.txt"]);
+        data[8..12].copy_from_slice(&5_000_000u32.to_be_bytes());
+        let data = with_checksum(data[..data.len() - 20].to_vec());
+        let err = load_raw(&data).err().unwrap();
+        assert!(matches!(
+            err,
+            IndexError::ImpossibleEntryCount(5_000_000, 1)
+        ));
+        assert_eq!(
+            err.to_string(),
+            "index header declares 5000000 entries but the file holds at most 1"
+        );
+        // The check happens before entries are read, so a header alone fails too.
+        let data = with_checksum(b"DIRC\0\0\0\x02\xff\xff\xff\xff".to_vec());
+        assert!(matches!(
+            load_raw(&data).err().unwrap(),
+            IndexError::ImpossibleEntryCount(u32::MAX, 0)
+        ));
+    }
+
+    #[test]
+    fn written_index_mtime_is_not_before_its_entries() {
+        let dir = TempDir::new().unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        assert_eq!(index.mtime(), None);
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let file = dir.path().join(name);
+            fs::write(&file, name).unwrap();
+            index
+                .add(Path::new(name), &OID, fs::metadata(&file).unwrap())
+                .unwrap();
+        }
+        assert!(index.write_updates().unwrap());
+
+        let written = index.mtime().unwrap();
+        assert_eq!(
+            written,
+            fs::metadata(dir.path().join("index"))
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let modified = fs::metadata(dir.path().join(name))
+                .unwrap()
+                .modified()
+                .unwrap();
+            assert!(written >= modified, "{} is newer than the index", name);
+        }
+        let loaded = Index::load(dir.path().join("index")).unwrap();
+        assert_eq!(loaded.mtime(), Some(written));
+    }
+
+    #[test]
+    fn entries_modified_with_the_index_are_racily_clean() {
+        let dir = TempDir::new().unwrap();
+        let old = dir.path().join("old.txt");
+        let new = dir.path().join("new.txt");
+        fs::write(&old, "old").unwrap();
+        fs::write(&new, "new").unwrap();
+        let now = SystemTime::now();
+        File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(60))
+            .unwrap();
+
+        let mut index = Index::new(dir.path().join("index"));
+        index
+            .add(Path::new("old.txt"), &OID, fs::metadata(&old).unwrap())
+            .unwrap();
+        assert!(index.write_updates().unwrap());
+        // Stamp new.txt with the index's own mtime, as a write within the
+        // same timestamp would.
+        File::options()
+            .write(true)
+            .open(&new)
+            .unwrap()
+            .set_modified(index.mtime().unwrap())
+            .unwrap();
+
+        let old_entry = Entry::from_stat("old.txt", &OID, &fs::metadata(&old).unwrap());
+        let new_entry = Entry::from_stat("new.txt", &OID, &fs::metadata(&new).unwrap());
+        assert!(!index.is_racily_clean(&old_entry));
+        assert!(index.is_racily_clean(&new_entry));
+        assert!(!Index::new(dir.path().join("missing")).is_racily_clean(&new_entry));
+    }
+
+    #[test]
+    fn partial_index_overlays_paths_on_head() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a1\n");
+        repo.write("b.txt", "b1\n");
+        repo.stage(&["."]);
+        let head = repo.commit("first");
+        repo.write("a.txt", "a2\n");
+        repo.write("b.txt", "b2\n");
+        repo.stage(&["."]);
+
+        let database = repo.database();
+        let (_, data) = database.read_object(&head).unwrap();
+        let head_tree = commit::parse_tree_oid(&data).unwrap();
+        let index = repo.index();
+        let mut partial = index
+            .partial(&database, Some(&head_tree), &[String::from("a.txt")])
+            .unwrap();
+        let mut tree = tree::Tree::from_index(&mut partial);
+        tree.store_tree(&database);
+        let files = tree::flatten_tree(
+            &database,
+            &utils::u8_to_hex_str(tree.object_id.into_bytes()),
+        )
+        .unwrap();
+        let content = |path: &str| database.read_object(&files[path].object_id).unwrap().1;
+        assert_eq!(content("a.txt"), b"a2\n");
+        assert_eq!(content("b.txt"), b"b1\n");
+
+        // The real index keeps both staged changes.
+        let staged = repo.index().get("b.txt").unwrap().oid().to_vec();
+        let (_, b2) = database.read_object(&utils::u8_to_hex_str(staged)).unwrap();
+        assert_eq!(b2, b"b2\n");
+
+        let err = index
+            .partial(&database, Some(&head_tree), &[String::from("missing")])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'missing' did not match any file(s) known to git"
+        );
+    }
+
+    #[test]
+    fn each_write_checksums_only_its_own_contents() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        let mut index = Index::new(path.clone());
+        index.insert(Entry::from_tree("a.txt", 0o100644, &OID));
+        assert!(index.write_updates().unwrap());
+        let first = fs::read(&path).unwrap();
+        let (content, checksum) = first.split_at(first.len() - 20);
+        assert_eq!(checksum, hash::oid_bytes(content));
+
+        // A second write of the same entries hashes from a fresh digest,
+        // so it produces the same file.
+        index.insert(Entry::from_tree("a.txt", 0o100644, &OID));
+        assert!(index.write_updates().unwrap());
+        assert_eq!(fs::read(&path).unwrap(), first);
+
+        index.insert(Entry::from_tree("b.txt", 0o100644, &OID));
+        assert!(index.write_updates().unwrap());
+        assert_eq!(verify_index(&path).unwrap().entry_count, 2);
+        assert_eq!(fs::read(&path).unwrap(), raw_index(&["a.txt", "b.txt"]));
+    }
+
+    #[test]
+    fn untracked_cache_is_skipped_on_load_and_dropped_on_write() {
+        let mut data = raw_index(&["a.txt"]);
+        data.truncate(data.len() - 20);
+        let untracked = b"\0\0\0\0stale directory stats";
+        data.extend_from_slice(b"UNTR");
+        data.extend_from_slice(&(untracked.len() as u32).to_be_bytes());
+        data.extend_from_slice(untracked);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        fs::write(&path, with_checksum(data)).unwrap();
+
+        let mut index = Index::load(path.clone()).unwrap();
+        assert_eq!(paths(&mut index), ["a.txt"]);
+        index.insert(Entry::from_tree("b.txt", 0o100644, &OID));
+        assert!(index.write_updates().unwrap());
+        let written = fs::read(&path).unwrap();
+        assert!(!written.windows(4).any(|window| window == b"UNTR"));
+        assert_eq!(written, raw_index(&["a.txt", "b.txt"]));
+
+        // A required extension still can't be skipped.
+        let mut data = raw_index(&["a.txt"]);
+        data.truncate(data.len() - 20);
+        data.extend_from_slice(b"link\0\0\0\0");
+        let err = load_raw(&with_checksum(data)).err().unwrap();
+        assert!(matches!(err, IndexError::UnsupportedExtension(ref name) if name == "link"));
+    }
+
+    #[test]
+    fn split_index_is_refused_and_never_written() {
+        // A link extension naming a shared base index, with empty bitmaps.
+        let mut data = raw_index(&["a.txt"]);
+        data.truncate(data.len() - 20);
+        let mut link = vec![0x5a; 20];
+        link.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(b"link");
+        data.extend_from_slice(&(link.len() as u32).to_be_bytes());
+        data.extend_from_slice(&link);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        fs::write(&path, with_checksum(data)).unwrap();
+        let err = Index::load(path.clone()).err().unwrap();
+        assert!(matches!(err, IndexError::UnsupportedExtension(ref name) if name == "link"));
+        assert_eq!(err.to_string(), "index uses unsupported extension 'link'");
+
+        // Every extension written is one this index can read back.
+        let mut index = Index::new(path.clone());
+        index.add_entry(Entry::from_tree("a.txt", 0o100644, &OID));
+        index.record_resolve_undo(ResolveUndo {
+            path: String::from("a.txt"),
+            modes: [0, 0o100644, 0o100644],
+            oids: [Vec::new(), vec![1; 20], vec![2; 20]],
+        });
+        assert!(index.write_updates().unwrap());
+        let written = fs::read(&path).unwrap();
+        assert!(!written.windows(4).any(|window| window == b"link"));
+        assert_eq!(Index::load(path).unwrap().resolve_undo().len(), 1);
+    }
+
+    #[test]
+    fn readers_never_see_a_partial_write() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        let mut index = Index::new(path.clone());
+        index.insert(Entry::from_tree("seed.txt", 0o100644, &OID));
+        assert!(index.write_updates().unwrap());
+
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let writers = (1..=4)
+                .map(|writer| {
+                    let path = &path;
+                    scope.spawn(move || {
+                        let mut written = 0;
+                        for round in 0..50 {
+                            // Each writer writes a different number of entries.
+                            let mut index = Index::new(path.clone());
+                            for i in 0..writer * 100 {
+                                let name = format!("w{}/r{}/{:04}.txt", writer, round, i);
+                                index.insert(Entry::from_tree(&name, 0o100644, &OID));
+                            }
+                            // Losing the lock to another writer is fine.
+                            if index.write_updates().unwrap() {
+                                written += 1;
+                            }
+                        }
+                        written
+                    })
+                })
+                .collect::<Vec<_>>();
+            scope.spawn(|| {
+                let mut reads = 0;
+                while !done.load(Ordering::Relaxed) || reads == 0 {
+                    let mut index = Index::load(path.clone()).unwrap();
+                    let count = index.len();
+                    assert!(
+                        count == 1 || count.is_multiple_of(100),
+                        "read {} entries",
+                        count
+                    );
+                    assert_eq!(paths(&mut index).len(), count);
+                    verify_index(&path).unwrap();
+                    reads += 1;
+                }
+            });
+            let written: usize = writers.into_iter().map(|w| w.join().unwrap()).sum();
+            done.store(true, Ordering::Relaxed);
+            assert!(written > 0);
+        });
+        assert!(!dir.path().join("index.lock").exists());
+    }
+
+    #[test]
+    fn tree_entries_serialize_with_zeroed_stat() {
+        let entry = Entry::from_tree("lib/a.txt", 0o100755, &OID);
+        let bytes = entry.to_bytes();
+        assert_eq!(&bytes[..24], &[0; 24]);
+        assert_eq!(&bytes[24..28], &0o100755u32.to_be_bytes());
+        assert_eq!(&bytes[28..40], &[0; 12]);
+        assert_eq!(&bytes[40..60], &OID);
+        assert_eq!(&bytes[60..62], &9u16.to_be_bytes());
+        assert_eq!(&bytes[62..71], b"lib/a.txt");
+        assert!(bytes.len().is_multiple_of(8) && bytes[71..].iter().all(|&b| b == 0));
+
+        let (parsed, used) = Entry::parse(&bytes, 2, "").unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(parsed.to_bytes(), bytes);
+        assert_eq!((parsed.path(), parsed.mode()), ("lib/a.txt", 0o100755));
+    }
+
+    #[test]
+    fn stat_entries_serialize_the_working_file_stat() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "content").unwrap();
+        let stat = fs::metadata(&file).unwrap();
+        let entry = Entry::from_stat("a.txt", &OID, &stat);
+        assert!(entry.stat_matches(&stat));
+        assert_eq!((entry.mode(), entry.size()), (0o100644, 7));
+
+        let bytes = entry.to_bytes();
+        assert_eq!(&bytes[8..12], &u32_be(stat.mtime() as u64));
+        assert_eq!(&bytes[20..24], &u32_be(stat.ino()));
+        let (parsed, _) = Entry::parse(&bytes, 2, "").unwrap();
+        assert!(parsed.stat_matches(&stat));
+        assert_eq!(parsed.to_bytes(), bytes);
+        // The same path and oid from a tree differ only in the stat fields.
+        let from_tree = Entry::from_tree("a.txt", 0o100644, &OID).to_bytes();
+        assert_eq!(bytes[40..], from_tree[40..]);
+        assert!(!Entry::from_tree("a.txt", 0o100644, &OID).stat_matches(&stat));
+    }
+
+    #[test]
+    fn u32_be_keeps_the_low_32_bits() {
+        assert_eq!(u32_be(0x0102_0304), [1, 2, 3, 4]);
+        assert_eq!(u32_be(0x1_0000_0002), [0, 0, 0, 2]);
+        assert_eq!(u32_be(u64::MAX), [0xff; 4]);
+    }
+
+    #[test]
+    fn entry_truncates_sizes_over_4gib() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("big.bin");
+        // A sparse file, so no space is actually used.
+        let file = fs::File::create(&path).unwrap();
+        file.set_len((5 << 30) + 7).unwrap();
+        let stat = fs::metadata(&path).unwrap();
+
+        let entry = Entry::from_stat("big.bin", &OID, &stat);
+        assert_eq!(entry.size, u32_be(stat.len()));
+        assert_eq!(u32::from_be_bytes(entry.size), (1 << 30) + 7);
+        assert!(entry.stat_matches(&stat));
+    }
+
+    #[test]
+    fn entries_are_written_in_ascending_order() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        let mut index = Index::new(path.clone());
+        for name in ["z.txt", "lib/b.txt", "a.txt", "lib-c.txt", "lib/a/c.txt"] {
+            index.insert(Entry::from_tree(name, 0o100644, &OID));
+        }
+        assert!(index.write_updates().unwrap());
+        assert_eq!(verify_index(&path).unwrap().entry_count, 5);
+        let mut index = Index::load(path).unwrap();
+        assert_eq!(
+            paths(&mut index),
+            ["a.txt", "lib-c.txt", "lib/a/c.txt", "lib/b.txt", "z.txt"]
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "strictly ascending path order")]
+    fn unsorted_output_trips_the_debug_check() {
+        let dir = TempDir::new().unwrap();
+        let mut index = Index::new(dir.path().join("index"));
+        index.insert(Entry::from_tree("a.txt", 0o100644, &OID));
+        // A key listed twice would serialize the entry twice.
+        index.keys.push(String::from("a.txt"));
+        let _ = index.write_updates();
+    }
+
+    #[test]
+    fn loaded_header_matches_entries() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        repo.write("lib/b.txt", "b\n");
+        repo.stage(&["."]);
+
+        let mut index = repo.index();
+        let header = *index.header().unwrap();
+        assert_eq!(&header.signature, b"DIRC");
+        assert_eq!(header.version, 2);
+        assert_eq!(header.entry_count as usize, index.each_entry().len());
+        // An index that was never written has no header.
+        assert!(Index::new(repo.git_dir().join("none")).header().is_none());
+    }
+
+    #[test]
+    fn flags_word_changes_only_through_setters() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "a").unwrap();
+
+        // Only the public getters and setters are used on the entry here.
+        let mut entry = Entry::from_tree("dir/a.txt", 0o100644, &OID);
+        assert_eq!(entry.flags(), 9);
+        entry.set_skip_worktree(true);
+        entry.set_intent_to_add();
+        assert_eq!(entry.flags(), FLAG_EXTENDED | 9);
+        assert_eq!(
+            entry.extended_flags(),
+            EXT_FLAG_SKIP_WORKTREE | EXT_FLAG_INTENT_TO_ADD
+        );
+        // The extended bit stays until the last extended flag is cleared.
+        entry.set_skip_worktree(false);
+        assert_eq!(entry.flags(), FLAG_EXTENDED | 9);
+        assert!(entry.intent_to_add() && !entry.skip_worktree());
+
+        entry.refresh_stat(fs::metadata(&file).unwrap());
+        assert_eq!(entry.flags(), FLAG_EXTENDED | 9);
+        assert_eq!((entry.size(), entry.mode()), (1, 0o100644));
+        assert_eq!((entry.path(), entry.oid()), ("dir/a.txt", &OID[..]));
+
+        // The name length saturates for long paths.
+        let long = "x".repeat(0x1000);
+        assert_eq!(Entry::from_tree(&long, 0o100644, &OID).flags(), 0xFFF);
+    }
+
+    #[test]
+    fn resolve_undo_round_trips_through_the_index_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("index");
+        let mut index = Index::new(path.clone());
+        index.add_entry(Entry::from_tree("merged.txt", 0o100644, &OID));
+        let record = ResolveUndo {
+            path: String::from("merged.txt"),
+            modes: [0o100644, 0o100644, 0o100755],
+            oids: [vec![1; 20], vec![2; 20], vec![3; 20]],
+        };
+        index.record_resolve_undo(record.clone());
+        assert!(index.write_updates().unwrap());
+
+        let data = fs::read(&path).unwrap();
+        let block = data
+            .windows(4)
+            .position(|window| window == b"REUC")
+            .expect("the index has a REUC extension");
+        let size = u32::from_be_bytes(data[block + 4..block + 8].try_into().unwrap()) as usize;
+        assert_eq!(
+            ResolveUndo::parse(&data[block + 8..block + 8 + size]).unwrap(),
+            std::slice::from_ref(&record)
+        );
+
+        let index = Index::load(path).unwrap();
+        assert_eq!(index.resolve_undo(), [record]);
+        assert!(index.entries.contains_key("merged.txt"));
     }
 }
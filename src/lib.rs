@@ -0,0 +1,39 @@
+pub mod add;
+pub mod attributes;
+pub mod author;
+pub mod blob;
+pub mod cache_tree;
+pub mod checkout;
+pub mod clean;
+pub mod color;
+pub mod commit;
+pub mod config;
+pub mod database;
+pub mod diff;
+pub mod editor;
+pub mod entry;
+pub mod filesystem;
+pub mod filter;
+pub mod fsck;
+pub mod hash;
+pub mod hooks;
+pub mod ignore;
+pub mod index;
+pub mod lockfile;
+pub mod merge;
+pub mod pack;
+pub mod refs;
+pub mod repository;
+pub mod resolve_undo;
+pub mod revert;
+pub mod revision;
+pub mod show;
+pub mod stash;
+pub mod status;
+pub mod traits;
+pub mod tree;
+pub mod utils;
+pub mod workspace;
+
+#[cfg(test)]
+mod test_support;
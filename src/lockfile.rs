@@ -3,21 +3,35 @@ use std::{
     fs::{self, File, OpenOptions},
     io::{self, Write},
     path::PathBuf,
+    thread,
+    time::{Duration, Instant},
 };
 
+// First wait between lock attempts, doubled after each failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
 #[derive(Debug)]
 pub enum LockfileError {
     MissingParent,
     NoPermission,
     StaleLock,
+    LockHeld,
+    // Writing the lock or renaming it over the target failed.
+    Io(io::Error),
 }
 
 impl error::Error for LockfileError {}
 
 impl fmt::Display for LockfileError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let err = self;
-        write!(f, "{}", err)
+        match self {
+            LockfileError::MissingParent => write!(f, "parent directory of the lock is missing"),
+            LockfileError::NoPermission => write!(f, "permission denied creating the lock"),
+            LockfileError::StaleLock => write!(f, "lock is not held"),
+            LockfileError::LockHeld => write!(f, "lock is held by another process"),
+            LockfileError::Io(err) => write!(f, "unable to write the lock: {}", err),
+        }
     }
 }
 
@@ -28,10 +42,16 @@ pub struct LockFile {
 }
 
 impl LockFile {
+    /*
+        Creates an unheld lock for any target path. The lock path appends
+        ".lock" to the full file name, so targets with an extension of
+        their own keep distinct locks.
+    */
     pub fn new(path: PathBuf) -> Self {
         let file_path = path.clone();
-        let mut lock_path = path.clone();
-        lock_path.set_extension("lock");
+        let mut lock_name = path.file_name().unwrap_or_default().to_os_string();
+        lock_name.push(".lock");
+        let lock_path = path.with_file_name(lock_name);
         Self {
             file_path,
             lock_path,
@@ -69,12 +89,32 @@ impl LockFile {
         }
     }
 
+    /*
+        Like hold_for_update, but while another process holds the lock keeps
+        retrying with exponential backoff until timeout has passed.
+        A zero timeout makes a single attempt.
+    */
+    pub fn hold_for_update_with_timeout(&mut self, timeout: Duration) -> Result<(), LockfileError> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            if self.hold_for_update()? {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(LockfileError::LockHeld);
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
     pub fn write(&self, s: String) -> Result<(), LockfileError> {
         match self.raise_on_stale_lock() {
             Ok(_) => {
                 let mut lock = self.lock.as_ref().unwrap();
-                let _ = lock.write_all(s.as_bytes());
-                Ok(())
+                lock.write_all(s.as_bytes()).map_err(LockfileError::Io)
             }
             Err(err) => Err(err),
         }
@@ -88,12 +128,24 @@ impl LockFile {
                 self.lock = None;
                 Ok(())
             }
-            Err(_) => {
-                panic!("Error in lockfile commit");
-            }
+            Err(err) => Err(LockfileError::Io(err)),
         }
     }
 
+    /*
+        Releases the lock without touching the locked file.
+    */
+    pub fn rollback(&mut self) -> Result<(), LockfileError> {
+        self.raise_on_stale_lock()?;
+        let _ = fs::remove_file(&self.lock_path);
+        self.lock = None;
+        Ok(())
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.lock.is_some()
+    }
+
     pub fn raise_on_stale_lock(&self) -> Result<(), LockfileError> {
         match &self.lock {
             Some(_) => Ok(()),
@@ -101,3 +153,135 @@ impl LockFile {
         }
     }
 }
+
+// A lock still held when it goes away was never committed or rolled back,
+// most likely because of an early error return, so release it.
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.lock.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn retry_acquires_lock_once_released() {
+        let dir = TempDir::new().unwrap();
+        let mut lockfile = LockFile::new(dir.path().join("index"));
+        fs::write(&lockfile.lock_path, "").unwrap();
+        assert!(!lockfile.hold_for_update().unwrap());
+
+        // Another process finishes with the lock partway through the wait.
+        let lock_path = lockfile.lock_path.clone();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            fs::remove_file(lock_path).unwrap();
+        });
+        let start = Instant::now();
+        lockfile
+            .hold_for_update_with_timeout(Duration::from_secs(10))
+            .unwrap();
+        releaser.join().unwrap();
+        assert!(lockfile.is_held());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        lockfile.write(String::from("data")).unwrap();
+        lockfile.commit().unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("index")).unwrap(),
+            "data"
+        );
+    }
+
+    #[test]
+    fn retry_gives_up_after_timeout() {
+        let dir = TempDir::new().unwrap();
+        let mut lockfile = LockFile::new(dir.path().join("index"));
+        fs::write(&lockfile.lock_path, "").unwrap();
+        let start = Instant::now();
+        let err = lockfile
+            .hold_for_update_with_timeout(Duration::from_millis(30))
+            .unwrap_err();
+        assert!(matches!(err, LockfileError::LockHeld));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert!(!lockfile.is_held());
+        // The other process's lock is left alone.
+        assert!(lockfile.lock_path.exists());
+    }
+
+    #[test]
+    fn zero_timeout_makes_a_single_attempt() {
+        let dir = TempDir::new().unwrap();
+        let mut lockfile = LockFile::new(dir.path().join("index"));
+        lockfile
+            .hold_for_update_with_timeout(Duration::ZERO)
+            .unwrap();
+        let mut other = LockFile::new(dir.path().join("index"));
+        assert!(matches!(
+            other.hold_for_update_with_timeout(Duration::ZERO),
+            Err(LockfileError::LockHeld)
+        ));
+    }
+
+    #[test]
+    fn lock_name_appends_to_the_full_file_name() {
+        let lockfile = LockFile::new(PathBuf::from("/repo/.git/refs/heads/v1.0"));
+        assert_eq!(
+            lockfile.lock_path,
+            PathBuf::from("/repo/.git/refs/heads/v1.0.lock")
+        );
+    }
+
+    #[test]
+    fn commit_replaces_an_arbitrary_target() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("nested").join("target.txt");
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::write(&target, "old").unwrap();
+        let mut lockfile = LockFile::new(target.clone());
+        assert_eq!(
+            lockfile.lock_path,
+            dir.path().join("nested/target.txt.lock")
+        );
+        assert!(!lockfile.is_held());
+        assert!(matches!(
+            lockfile.write(String::from("early")),
+            Err(LockfileError::StaleLock)
+        ));
+
+        assert!(lockfile.hold_for_update().unwrap());
+        assert!(lockfile.is_held() && lockfile.lock_path.exists());
+        lockfile.write(String::from("new ")).unwrap();
+        lockfile.write(String::from("contents")).unwrap();
+        // The target keeps its old contents until the commit.
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+        assert_eq!(
+            fs::read_to_string(&lockfile.lock_path).unwrap(),
+            "new contents"
+        );
+
+        lockfile.commit().unwrap();
+        assert!(!lockfile.is_held() && !lockfile.lock_path.exists());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new contents");
+        assert!(matches!(lockfile.commit(), Err(LockfileError::StaleLock)));
+    }
+
+    #[test]
+    fn rollback_leaves_the_target_alone() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("target.txt");
+        fs::write(&target, "old").unwrap();
+        let mut lockfile = LockFile::new(target.clone());
+        assert!(lockfile.hold_for_update().unwrap());
+        lockfile.write(String::from("discarded")).unwrap();
+        lockfile.rollback().unwrap();
+        assert!(!lockfile.is_held() && !lockfile.lock_path.exists());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+    }
+}
@@ -1,51 +1,402 @@
 use std::{
     env, fs, io,
-    path::{self, Path, PathBuf},
+    path::{Path, PathBuf},
     process,
+    time::Duration,
 };
 
-use blob::Blob;
 use chrono::Local;
 
-mod author;
-mod blob;
-mod commit;
-mod database;
-mod entry;
-mod index;
-mod lockfile;
-mod refs;
-mod traits;
-mod tree;
-mod utils;
-mod workspace;
-
-fn initialize_repo_directory(mut path_buf: PathBuf) -> io::Result<()> {
-    path_buf.push(".git");
-    let dirs = ["objects", "refs"];
+use shit::{
+    add, author, checkout, clean, color, commit, config, database, diff, editor, fsck, hash, hooks,
+    index, pack, refs, repository, revert, revision, show, stash, status, traits::Object, tree,
+    utils,
+};
+
+// How long add waits on an index lock held by another process.
+const INDEX_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/*
+    Creates the repository files in path/.git, or directly in path for a
+    bare repository, which has no working tree.
+*/
+fn initialize_repo_directory(mut path_buf: PathBuf, bare: bool) -> io::Result<()> {
+    if !bare {
+        path_buf.push(".git");
+    }
+    let dirs = ["objects", "refs", "refs/heads"];
     for dir in dirs.into_iter() {
-        path_buf.push(dir);
-        fs::create_dir_all(&path_buf)?;
-        path_buf.pop();
+        fs::create_dir_all(path_buf.join(dir))?;
     }
+    fs::write(
+        path_buf.join("config"),
+        format!("[core]\n\tbare = {}\n", bare),
+    )?;
+
+    // Point HEAD at the (unborn) default branch.
+    let config = config::Config::load_global()?;
+    let branch = config.get("init.defaultBranch").unwrap_or("main");
+    let refs = refs::Refs::new(path_buf);
+    refs.set_head_branch(branch)
+        .map_err(|err| io::Error::other(err.to_string()))?;
     Ok(())
 }
 
-fn init(dir: &str) -> io::Result<()> {
+fn init(dir: &str, bare: bool) -> io::Result<()> {
     let path: PathBuf = fs::canonicalize(dir).or_else(|_| {
         fs::create_dir_all(dir)?;
         Ok::<PathBuf, io::Error>(PathBuf::from(dir))
     })?;
     println!("Initialized empty Git repository in {}", path.display());
-    initialize_repo_directory(path)?;
+    initialize_repo_directory(path, bare)?;
+    Ok(())
+}
+
+/*
+    The repository containing the current directory. Exits when there is
+    none, since no command but init can do anything without one.
+*/
+fn repository() -> repository::Repository {
+    let discovered = env::current_dir().and_then(repository::Repository::discover);
+    match discovered {
+        Ok(repo) => repo,
+        Err(err) => {
+            eprintln!("fatal: {}", err);
+            process::exit(128);
+        }
+    }
+}
+
+// Whether to color output, from --color, color.ui and whether stdout is a terminal.
+fn use_color(args: &[String], repo: &repository::Repository) -> io::Result<bool> {
+    let config = config::Config::load(&repo.git_dir)?;
+    Ok(color::ColorMode::from_args(args, &config).enabled())
+}
+
+// Commands that read or write files in the working tree can't run in a bare repository.
+fn require_work_tree(repo: &repository::Repository) {
+    if repo.bare {
+        eprintln!("fatal: this operation must be run in a work tree");
+        process::exit(128);
+    }
+}
+
+// Shown below the message when committing from an editor.
+const COMMIT_EDITMSG_TEMPLATE: &str = "
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+";
+
+/*
+    The message for commit: each -m is a paragraph, -F reads a file or
+    stdin for "-", and otherwise the message is written in an editor
+    started on .git/COMMIT_EDITMSG. The message is cleaned up, comment
+    lines are only dropped when they come from the editor.
+*/
+fn read_commit_message(args: &[String], repo: &repository::Repository) -> io::Result<String> {
+    let mut paragraphs = Vec::new();
+    let mut file = None;
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-m" | "--message" => paragraphs.extend(rest.next().cloned()),
+            "-F" | "--file" => file = rest.next().cloned(),
+            _ => {
+                if let Some(message) = arg.strip_prefix("--message=") {
+                    paragraphs.push(message.to_string());
+                } else if let Some(path) = arg.strip_prefix("--file=") {
+                    file = Some(path.to_string());
+                }
+            }
+        }
+    }
+
+    if !paragraphs.is_empty() {
+        return Ok(commit::cleanup_message(&paragraphs.join("\n\n"), false));
+    }
+    if let Some(file) = file {
+        let message = if file == "-" {
+            io::read_to_string(io::stdin())?
+        } else {
+            fs::read_to_string(repo.prefix.join(&file)).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("could not read log file '{}': {}", file, err),
+                )
+            })?
+        };
+        return Ok(commit::cleanup_message(&message, false));
+    }
+
+    let path = repo.git_dir.join("COMMIT_EDITMSG");
+    fs::write(&path, COMMIT_EDITMSG_TEMPLATE)?;
+    editor::edit(&path, &config::Config::load(&repo.git_dir)?)?;
+    Ok(commit::cleanup_message(&fs::read_to_string(&path)?, true))
+}
+
+// The paths given to commit, skipping flags and the values they take.
+fn commit_paths(args: &[String]) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-m" | "--message" | "-F" | "--file" | "--date" => {
+                rest.next();
+            }
+            "--" => paths.extend(rest.by_ref().cloned()),
+            _ if arg.starts_with('-') => {}
+            _ => paths.push(arg.clone()),
+        }
+    }
+    paths
+}
+
+/*
+    commit --dry-run: prints the status and the id of the tree that would
+    be committed, and with -m or -F the id the commit itself would get.
+    Nothing is written, not even refreshed index stats. Exits with 1 when
+    there is nothing to commit.
+*/
+fn dry_run_commit(args: &[String], repo: &repository::Repository) -> io::Result<()> {
+    let workspace = repo.workspace()?;
+    let database = repo.database();
+    let refs = repo.refs();
+    let mut index = load_index(repo.index_path());
+    let parent = refs.read_head()?;
+
+    let paths = commit_paths(args)
+        .iter()
+        .map(|path| repo.resolve_pathspec(path).display().to_string())
+        .collect::<Vec<_>>();
+    let head_tree = if parent.is_empty() {
+        None
+    } else {
+        let (_, data) = database.read_object(&parent)?;
+        commit::parse_tree_oid(&data)
+    };
+    let tree_hex = if paths.is_empty() {
+        index.tree_oid_dry()
+    } else {
+        match index.partial(&database, head_tree.as_deref(), &paths) {
+            Ok(partial) => partial.tree_oid_dry(),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            }
+        }
+    };
+
+    let report = status::status(&workspace, &database, &mut index, &refs)?;
+    print!("{}", status::format_porcelain(&report));
+    let allow_empty = args.iter().any(|arg| arg == "--allow-empty");
+    if !allow_empty && head_tree.as_deref() == Some(tree_hex.as_str()) {
+        process::exit(1);
+    }
+    println!("tree {}", tree_hex);
+
+    let has_message = args.iter().any(|arg| {
+        ["-m", "--message", "-F", "--file"].contains(&arg.as_str())
+            || arg.starts_with("--message=")
+            || arg.starts_with("--file=")
+    });
+    if has_message {
+        let (author, committer) = commit_identities(args, repo)?;
+        let commit = commit::Commit {
+            tree: tree_hex,
+            parents: Some(parent)
+                .filter(|parent| !parent.is_empty())
+                .into_iter()
+                .collect(),
+            author,
+            committer,
+            extra_headers: Vec::new(),
+            message: read_commit_message(args, repo)?,
+            object_id: String::new(),
+        };
+        println!(
+            "commit {}",
+            hash::oid_hex(Object::to_string(&commit).as_bytes())
+        );
+    }
+    Ok(())
+}
+
+/*
+    Stores a commit of the tree with the given raw object id on top of
+    parent, moves HEAD to it, logs the update to HEAD and its branch and
+    prints the summary line. The reflog message starts with reflog_action.
+*/
+fn write_commit(
+    args: &[String],
+    repo: &repository::Repository,
+    parent: &str,
+    tree_object_id: String,
+    message: String,
+    reflog_action: &str,
+) -> io::Result<()> {
+    let (author, committer) = commit_identities(args, repo)?;
+    let (database, refs) = (repo.database(), repo.refs());
+
+    // Create commit, store it.
+    let mut commit = commit::Commit::new(parent.to_string(), tree_object_id, author, message);
+    commit.committer = committer;
+    database.store(&mut commit).unwrap();
+
+    // Write commit id to HEAD.
+    let commit_hex_str = utils::u8_to_hex_str(commit.object_id.as_bytes().to_vec());
+
+    let expected_old = Some(parent).filter(|parent| !parent.is_empty());
+    if let Err(err) = refs.update_head(expected_old, &commit_hex_str) {
+        eprintln!("fatal: cannot update HEAD: {}", err);
+        process::exit(128);
+    }
+
+    let first_line = commit.message.lines().next().unwrap();
+
+    // Log the update for both HEAD and the branch it points at.
+    let reflog_message = format!("{}: {}", reflog_action, first_line);
+    let mut logged = vec![String::from("HEAD")];
+    if let Ok(target) = refs.head_target() {
+        if let Ok(branch) = target.strip_prefix(&repo.git_dir) {
+            logged.push(branch.display().to_string());
+        }
+    }
+    logged.dedup();
+    for ref_name in logged {
+        refs.append_reflog(
+            &ref_name,
+            parent,
+            &commit_hex_str,
+            &commit.committer,
+            &reflog_message,
+        )?;
+    }
+
+    let mut is_root = String::from("");
+    if parent.is_empty() {
+        is_root = String::from("(root-commit) ");
+    }
+    println!(
+        "[{}{}] {}",
+        is_root,
+        utils::oid_abbrev(&commit_hex_str, 7),
+        first_line
+    );
     Ok(())
 }
 
+/*
+    The author and committer of a new commit. Names and emails come from
+    the environment or config, the committer falling back to the author.
+    Times come from --date, GIT_AUTHOR_DATE and GIT_COMMITTER_DATE and
+    default to now. Exits when an identity or date can't be worked out.
+*/
+fn commit_identities(
+    args: &[String],
+    repo: &repository::Repository,
+) -> io::Result<(author::Author, author::Author)> {
+    let config = config::Config::load(&repo.git_dir)?;
+    let (author_name, author_email) = author::identity("AUTHOR", &config).unwrap_or_else(|| {
+        eprintln!("fatal: unable to auto-detect author identity, set user.name and user.email");
+        process::exit(128);
+    });
+    let (committer_name, committer_email) = author::identity("COMMITTER", &config)
+        .unwrap_or_else(|| (author_name.clone(), author_email.clone()));
+
+    let now = Local::now().format("%s %z").to_string();
+    let date_arg =
+        args.iter()
+            .skip(2)
+            .enumerate()
+            .find_map(|(i, arg)| match arg.strip_prefix("--date=") {
+                Some(date) => Some(date.to_string()),
+                None if arg == "--date" => args.get(i + 3).cloned(),
+                None => None,
+            });
+    let date = |value: Option<String>| -> String {
+        match value {
+            Some(value) => author::parse_date(&value).unwrap_or_else(|| {
+                eprintln!("fatal: invalid date format: {}", value);
+                process::exit(128);
+            }),
+            None => now.clone(),
+        }
+    };
+    let author_date = date(date_arg.or_else(|| env::var("GIT_AUTHOR_DATE").ok()));
+    let committer_date = date(env::var("GIT_COMMITTER_DATE").ok());
+    Ok((
+        author::Author::new(author_name, author_email, author_date),
+        author::Author::new(committer_name, committer_email, committer_date),
+    ))
+}
+
+fn load_index(index_path: PathBuf) -> index::Index {
+    match index::Index::load(index_path) {
+        Ok(index) => index,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+    Locks the index before loading it, so entries written by a concurrent
+    command in between are not lost when this one writes. Waits up to
+    INDEX_LOCK_TIMEOUT for another process to release the lock.
+*/
+fn lock_index(index_path: PathBuf) -> index::Index {
+    match index::Index::load_for_update(index_path, INDEX_LOCK_TIMEOUT) {
+        Ok(Some(index)) => index,
+        Ok(None) => {
+            eprintln!("fatal: unable to write the index, .git/index.lock is held");
+            process::exit(128);
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+    Writes the index, returning false when another process holds its lock.
+    Any other failure to write it is fatal.
+*/
+fn write_index(index: &mut index::Index) -> bool {
+    match index.write_updates() {
+        Ok(written) => written,
+        Err(err) => {
+            eprintln!("fatal: {}", err);
+            process::exit(128);
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Command {
     Add,
+    Branch,
+    Checkout,
+    CheckoutIndex,
+    Clean,
     Commit,
+    CommitTree,
+    Diff,
+    Fsck,
+    Gc,
     Init,
+    LsTree,
+    ReadTree,
+    Revert,
+    RevParse,
+    Show,
+    ShowRef,
+    Stash,
+    Status,
+    UpdateIndex,
+    WriteTree,
+    VerifyIndex,
     Unknown,
 }
 
@@ -53,8 +404,27 @@ impl Command {
     fn from_string(s: &str) -> Command {
         match s {
             "add" => Self::Add,
+            "branch" => Self::Branch,
+            "checkout" => Self::Checkout,
+            "checkout-index" => Self::CheckoutIndex,
+            "clean" => Self::Clean,
             "commit" => Self::Commit,
+            "commit-tree" => Self::CommitTree,
+            "diff" => Self::Diff,
+            "fsck" => Self::Fsck,
+            "gc" => Self::Gc,
             "init" => Self::Init,
+            "ls-tree" => Self::LsTree,
+            "read-tree" => Self::ReadTree,
+            "revert" => Self::Revert,
+            "rev-parse" => Self::RevParse,
+            "show" => Self::Show,
+            "show-ref" => Self::ShowRef,
+            "stash" => Self::Stash,
+            "status" => Self::Status,
+            "update-index" => Self::UpdateIndex,
+            "write-tree" => Self::WriteTree,
+            "verify-index" => Self::VerifyIndex,
             _ => Self::Unknown,
         }
     }
@@ -62,42 +432,194 @@ impl Command {
 
 fn main() -> io::Result<()> {
     let args = env::args().collect::<Vec<String>>();
-    dbg!(&args);
     let cmd = args.get(1).expect("Usage: {} <command> [<directory>]");
     match Command::from_string(&cmd[..]) {
         Command::Add => {
+            let repo = repository();
+            require_work_tree(&repo);
             // set up paths.
-            let db_path = utils::get_db_path();
-            let root_path = utils::get_root_path();
-            let index_path = utils::get_index_path();
+            let db_path = repo.db_path();
+            let index_path = repo.index_path();
 
             // set up git data structures.
-            let workspace = workspace::Workspace::new(root_path.clone());
+            let workspace = repo.workspace()?;
             let database = database::Database::new(db_path);
-            let mut index = index::Index::new(index_path);
 
-            // Initialize absolute path.
-            let args: Vec<String> = env::args().collect();
+            let config = config::Config::load(&repo.git_dir.clone())?;
 
-            // Iterate over arguments starting from index 2
-            for pathname in args.iter().skip(2) {
-                let paths = workspace.list_files(&PathBuf::from(pathname)).unwrap();
-                for path in paths {
+            // -N records paths without staging their content.
+            let options = add::AddOptions {
+                ignore_case: config.get_bool("core.ignorecase").unwrap_or(false),
+                force: args
+                    .iter()
+                    .skip(2)
+                    .any(|arg| arg == "-f" || arg == "--force"),
+                intent_to_add: args
+                    .iter()
+                    .skip(2)
+                    .any(|arg| arg == "-N" || arg == "--intent-to-add"),
+                ignore_missing: args.iter().skip(2).any(|arg| arg == "--ignore-missing"),
+                update: args
+                    .iter()
+                    .skip(2)
+                    .any(|arg| arg == "-u" || arg == "--update"),
+            };
 
-                    // Get file data and store blob.
-                    let data = workspace.read_data(&path)?;
-                    let mut blob = blob::Blob::new(&data);
-                    database.store(&mut blob)?;
-                    let stat = workspace.stat_file(path.clone());
-                    index.add(&path, &blob.object_id, stat);
+            // Arguments from index 2 on are relative to the directory add
+            // was run from, and so are paths read with --pathspec-from-file.
+            let mut pathspecs = Vec::new();
+            let mut from_file = None;
+            let mut rest = args.iter().skip(2);
+            while let Some(arg) = rest.next() {
+                if arg == "--pathspec-from-file" {
+                    from_file = rest.next().cloned();
+                } else if let Some(file) = arg.strip_prefix("--pathspec-from-file=") {
+                    from_file = Some(file.to_string());
+                } else if !arg.starts_with('-') {
+                    pathspecs.push(arg.clone());
                 }
             }
-            index.write_updates();
+            if let Some(file) = from_file {
+                let nul = args.iter().any(|arg| arg == "--pathspec-file-nul");
+                let read = if file == "-" {
+                    add::read_pathspecs(io::stdin().lock(), nul)
+                } else {
+                    fs::File::open(repo.prefix.join(&file))
+                        .and_then(|reader| add::read_pathspecs(reader, nul))
+                };
+                match read {
+                    Ok(read) => pathspecs.extend(read),
+                    Err(err) => {
+                        eprintln!("fatal: could not read pathspecs from '{}': {}", file, err);
+                        process::exit(128);
+                    }
+                }
+            }
+            let pathnames = pathspecs
+                .iter()
+                .map(|arg| repo.resolve_pathspec(arg).display().to_string())
+                .collect::<Vec<String>>();
+            // -v reports each path once it has been staged.
+            let verbose = args
+                .iter()
+                .skip(2)
+                .any(|arg| arg == "-v" || arg == "--verbose");
+            let mut index = lock_index(index_path);
+            let mut report = |path: &Path| println!("add '{}'", path.display());
+            let progress: Option<&mut dyn FnMut(&Path)> =
+                if verbose { Some(&mut report) } else { None };
+            let skipped = match add::add(
+                &workspace, &database, &mut index, &pathnames, &options, progress,
+            ) {
+                Ok(skipped) => skipped,
+                Err(err) => {
+                    // Exiting skips destructors, so release the lock first.
+                    drop(index);
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }
+            };
+            // The paths that could be staged are kept, but skipping any
+            // fails the add.
+            write_index(&mut index);
+            if skipped > 0 {
+                eprintln!("fatal: adding files failed");
+                process::exit(128);
+            }
+        }
+        Command::Branch => {
+            let repo = repository();
+            let refs = refs::Refs::new(repo.git_dir.clone());
+
+            match args.get(2) {
+                // Without a name, list the branches and mark the current one.
+                None => {
+                    let current = refs.current_branch()?;
+                    for branch in refs.list_branches()? {
+                        let marker = if Some(&branch) == current.as_ref() {
+                            '*'
+                        } else {
+                            ' '
+                        };
+                        println!("{} {}", marker, branch);
+                    }
+                }
+                Some(name) => {
+                    let head = refs.read_head()?;
+                    if head.is_empty() {
+                        eprintln!(
+                            "fatal: cannot create branch '{}': HEAD does not point at a commit",
+                            name
+                        );
+                        process::exit(128);
+                    }
+                    if let Err(err) = refs.create_branch(name, &head) {
+                        eprintln!("fatal: {}", err);
+                        process::exit(128);
+                    }
+                }
+            }
+        }
+        Command::Checkout => {
+            let repo = repository();
+            require_work_tree(&repo);
+            let Some(branch) = args.get(2) else {
+                eprintln!("usage: checkout <branch>");
+                process::exit(1);
+            };
+
+            let workspace = repo.workspace()?;
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(repo.git_dir.clone());
+            let mut index = load_index(repo.index_path());
+
+            if refs.current_branch()?.as_ref() == Some(branch) {
+                eprintln!("Already on '{}'", branch);
+                return Ok(());
+            }
+            if let Err(err) =
+                checkout::checkout_branch(&workspace, &database, &mut index, &refs, branch)
+            {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            }
+            if !write_index(&mut index) {
+                eprintln!("fatal: unable to write the index, .git/index.lock is held");
+                process::exit(128);
+            }
+            eprintln!("Switched to branch '{}'", branch);
+        }
+        Command::CheckoutIndex => {
+            let repo = repository();
+            require_work_tree(&repo);
+            let db_path = repo.db_path();
+            let index_path = repo.index_path();
+
+            let workspace = repo.workspace()?;
+            let database = database::Database::new(db_path);
+            let mut index = load_index(index_path);
+
+            let force = args
+                .iter()
+                .skip(2)
+                .any(|arg| arg == "-f" || arg == "--force");
+            if !args.iter().skip(2).any(|arg| arg == "-a" || arg == "--all") {
+                eprintln!("usage: checkout-index -a [-f]");
+                process::exit(1);
+            }
+            for path in index.checkout_index(&workspace, &database, force)? {
+                eprintln!("{} already exists, no checkout", path);
+            }
         }
         Command::Init => {
             let default_dir = &"./".to_string();
-            let dir = args.get(2).unwrap_or(default_dir);
-            match init(dir) {
+            let bare = args.iter().skip(2).any(|arg| arg == "--bare");
+            let dir = args
+                .iter()
+                .skip(2)
+                .find(|arg| !arg.starts_with('-'))
+                .unwrap_or(default_dir);
+            match init(dir, bare) {
                 Ok(_) => {
                     println!("init success");
                 }
@@ -107,86 +629,550 @@ fn main() -> io::Result<()> {
                 }
             }
         }
+        Command::Clean => {
+            let repo = repository();
+            require_work_tree(&repo);
+            let workspace = repo.workspace()?;
+            let mut index = load_index(repo.index_path());
+
+            let flag = |short: &str, long: &str| {
+                args.iter().skip(2).any(|arg| {
+                    arg == long
+                        || (arg.starts_with('-') && !arg.starts_with("--") && arg.contains(short))
+                })
+            };
+            let options = clean::CleanOptions {
+                dry_run: flag("n", "--dry-run"),
+                ignored: flag("x", "--ignored"),
+                directories: flag("d", "-d"),
+            };
+            if !options.dry_run && !flag("f", "--force") {
+                eprintln!("fatal: refusing to clean without -f or -n");
+                process::exit(128);
+            }
+            let label = if options.dry_run {
+                "Would remove"
+            } else {
+                "Removing"
+            };
+            for path in clean::clean(&workspace, &mut index, &options)? {
+                println!("{} {}", label, path);
+            }
+        }
         Command::Commit => {
+            let repo = repository();
+            require_work_tree(&repo);
             // set up paths.
-            let git_path = utils::get_git_path();
-            let db_path = utils::get_db_path();
-            let root_path = utils::get_root_path();
+            let git_path = repo.git_dir.clone();
+            let db_path = repo.db_path();
+            let root_path = repo.root.clone();
 
             // set up git data structures.
-            let workspace = workspace::Workspace::new(root_path.clone());
             let database = database::Database::new(db_path);
             let refs = refs::Refs::new(git_path.clone());
 
-            // Read current workspace files into Entry vector (used to construct Tree).
-            let files = workspace.list_files(&root_path.clone())?;
-            dbg!(&files);
-            let mut entries = Vec::new();
-            for file in files {
-                let data = workspace.read_data(&file)?;
-                let mut blob = blob::Blob::new(&data);
-                database.store(&mut blob)?;
-                let filename = file
-                    .clone()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-
-                let stat = workspace.stat_file(file.clone());
-                let entry = entry::Entry::new(filename, file, &blob.object_id, stat);
-                entries.push(entry);
-            }
-            // TODO: can possibly delete moved to Tree::build
-            entries.sort_by_key(|e| e.path.clone().to_str().unwrap().to_string());
-
-            // Create and store tree for commit.
-            //let mut tree = tree::Tree::_new(entries.clone());
-
-            let mut tree = tree::Tree::build(entries);
-            tree.store_tree(&database);
+            if args.iter().any(|arg| arg == "--dry-run") {
+                return dry_run_commit(&args, &repo);
+            }
+
+            // The pre-commit hook can veto the commit unless bypassed.
+            let no_verify = args.iter().any(|arg| arg == "-n" || arg == "--no-verify");
+            if !no_verify && !hooks::run_hook(&git_path, &root_path, "pre-commit")? {
+                eprintln!("pre-commit hook failed, not committing");
+                process::exit(1);
+            }
+
+            // Read the commit message before anything is stored.
+            let commit_message = read_commit_message(&args, &repo)?;
+            if commit_message.is_empty() {
+                eprintln!("Aborting commit due to empty commit message.");
+                process::exit(1);
+            }
 
             // Get parent of current commit.
             let parent = refs.read_head().unwrap();
 
-            // Create Author.
-            let now = Local::now();
-            let formatted_datetime = now.format("%s %z").to_string();
-            let author_name = env::var("GIT_AUTHOR_NAME").expect("GIT_AUTHOR_NAME not set");
-            let author_email = env::var("GIT_AUTHOR_EMAIL").expect("GIT_AUTHOR_EMAIL not set");
-            let author = author::Author::new(author_name, author_email, formatted_datetime);
-
-            // Read commit message, create commit, store it.
-            let mut commit_message = String::new();
-            io::stdin().read_line(&mut commit_message)?;
-            let mut commit = commit::Commit::new(
-                parent.clone(),
+            // Create and store the tree recorded by the index. Given paths,
+            // only their staged changes are committed on top of HEAD.
+            let paths = commit_paths(&args)
+                .iter()
+                .map(|path| repo.resolve_pathspec(path).display().to_string())
+                .collect::<Vec<_>>();
+            let head_tree = if parent.is_empty() {
+                None
+            } else {
+                let (_, data) = database.read_object(&parent)?;
+                commit::parse_tree_oid(&data)
+            };
+            let tree = if paths.is_empty() {
+                // Unchanged directories keep their cached tree ids. The
+                // lock is held from reading the index until the refreshed
+                // cache is saved, and released unwritten otherwise.
+                let mut index = lock_index(repo.index_path());
+                let mut tree = tree::Tree::from_index(&mut index);
+                let cache = tree.store_tree_cached(&database, index.tree_cache());
+                index.set_tree_cache(cache);
+                if index.is_changed() {
+                    write_index(&mut index);
+                }
+                tree
+            } else {
+                let index = load_index(repo.index_path());
+                match index.partial(&database, head_tree.as_deref(), &paths) {
+                    Ok(mut partial) => {
+                        let mut tree = tree::Tree::from_index(&mut partial);
+                        tree.store_tree(&database);
+                        tree
+                    }
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        process::exit(1);
+                    }
+                }
+            };
+
+            // A commit recording its parent's tree unchanged is empty. The
+            // root commit never is.
+            let allow_empty = args.iter().any(|arg| arg == "--allow-empty");
+            let tree_hex = utils::u8_to_hex_str(tree.object_id.as_bytes().to_vec());
+            if !allow_empty && head_tree.as_deref() == Some(tree_hex.as_str()) {
+                println!("nothing to commit, working tree clean");
+                process::exit(1);
+            }
+
+            let reflog_action = if parent.is_empty() {
+                "commit (initial)"
+            } else {
+                "commit"
+            };
+            write_commit(
+                &args,
+                &repo,
+                &parent,
                 tree.object_id,
+                commit_message,
+                reflog_action,
+            )?;
+        }
+        Command::CommitTree => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(repo.git_dir.clone());
+
+            let mut tree = None;
+            let mut parents = Vec::new();
+            let mut paragraphs = Vec::new();
+            let mut rest = args.iter().skip(2);
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-p" => parents.extend(rest.next()),
+                    "-m" => paragraphs.extend(rest.next()),
+                    _ => tree = Some(arg),
+                }
+            }
+            let Some(tree) = tree else {
+                eprintln!("usage: commit-tree <tree> [-p <parent>]... [-m <message>]...");
+                process::exit(1);
+            };
+
+            let resolve = |rev: &str, kind: &str| {
+                let oid = match kind {
+                    "tree" => revision::resolve_tree(&database, &refs, rev),
+                    _ => revision::resolve_revision(&database, &refs, rev),
+                };
+                oid.unwrap_or_else(|err| {
+                    eprintln!("fatal: not a valid {} {}: {}", kind, rev, err);
+                    process::exit(128);
+                })
+            };
+            let tree = resolve(tree, "tree");
+            let parents = parents
+                .into_iter()
+                .map(|parent| resolve(parent, "commit"))
+                .collect::<Vec<_>>();
+
+            // Each -m is a paragraph, otherwise the message is read from
+            // stdin exactly as given.
+            let message = if paragraphs.is_empty() {
+                io::read_to_string(io::stdin())?
+            } else {
+                let mut message = paragraphs
+                    .iter()
+                    .map(|paragraph| paragraph.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                message.push('\n');
+                message
+            };
+
+            let (author, committer) = commit_identities(&args, &repo)?;
+            let mut commit = commit::Commit {
+                tree,
+                parents,
                 author,
-                commit_message.clone(),
+                committer,
+                extra_headers: Vec::new(),
+                message,
+                object_id: String::new(),
+            };
+            database.store(&mut commit)?;
+            println!("{}", utils::u8_to_hex_str(commit.object_id.into_bytes()));
+        }
+        Command::Diff => {
+            let repo = repository();
+            let git_path = repo.git_dir.clone();
+
+            let workspace = repo.workspace()?;
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(git_path);
+            let mut index = load_index(repo.index_path());
+
+            // --cached compares HEAD to the index instead of the index to the working tree.
+            let cached = args
+                .iter()
+                .skip(2)
+                .any(|arg| arg == "--cached" || arg == "--staged");
+            if !cached {
+                require_work_tree(&repo);
+            }
+            let output = if cached {
+                diff::diff_head_index(&database, &refs, &mut index)?
+            } else {
+                diff::diff_index_worktree(&workspace, &database, &mut index)?
+            };
+            if use_color(&args, &repo)? {
+                print!("{}", color::diff(&output));
+            } else {
+                print!("{}", output);
+            }
+        }
+        Command::Fsck => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(repo.git_dir.clone());
+
+            let problems = fsck::fsck(&database, &refs)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if problems.iter().any(fsck::Problem::is_error) {
+                process::exit(1);
+            }
+        }
+        Command::Gc => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+
+            let oids = database.list_objects()?;
+            if oids.is_empty() {
+                return Ok(());
+            }
+            let pack_path = pack::write_pack(&database, &oids, &database.path_buf.join("pack"))?;
+            println!(
+                "packed {} objects into {}",
+                oids.len(),
+                pack_path.file_name().unwrap().to_string_lossy()
             );
-            database.store(&mut commit).unwrap();
+            // Loose copies are only removed on request.
+            if args.iter().skip(2).any(|arg| arg == "--prune") {
+                for oid in &oids {
+                    database.remove_loose(oid)?;
+                }
+            }
+        }
+        Command::ReadTree => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(repo.git_dir.clone());
+            let mut index = load_index(repo.index_path());
 
-            // Write commit id to HEAD.
-            let commit_hex_str = utils::u8_to_hex_str(commit.object_id.as_bytes().to_vec());
+            let rev = match args.get(2) {
+                Some(rev) => rev,
+                None => {
+                    eprintln!("usage: read-tree <tree-ish>");
+                    process::exit(1);
+                }
+            };
+            let result = revision::resolve_tree(&database, &refs, rev)
+                .and_then(|oid| index.read_tree(&database, &oid));
+            if let Err(err) = result {
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            }
+            write_index(&mut index);
+        }
+        Command::LsTree => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(repo.git_dir.clone());
 
-            let _ = match refs.update_head(commit_hex_str.clone()) {
-                Ok(_) => Ok(()),
-                Err(err) => {
-                    dbg!(&err);
-                    println!("can't acquire lock");
-                    Err(err)
+            let recursive = args.iter().skip(2).any(|arg| arg == "-r");
+            let rev = match args.iter().skip(2).find(|arg| !arg.starts_with('-')) {
+                Some(rev) => rev,
+                None => {
+                    eprintln!("usage: ls-tree [-r] <tree-ish>");
+                    process::exit(1);
                 }
             };
+            let output = revision::resolve_tree(&database, &refs, rev)
+                .and_then(|oid| tree::ls_tree(&database, &oid, recursive));
+            match output {
+                Ok(output) => print!("{}", output),
+                Err(err) => {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }
+            }
+        }
+        Command::WriteTree => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+            let mut index = load_index(repo.index_path());
 
-            let first_line = commit.message.lines().next().unwrap();
+            let mut tree = tree::Tree::from_index(&mut index);
+            let cache = tree.store_tree_cached(&database, index.tree_cache());
+            index.set_tree_cache(cache);
+            if index.is_changed() {
+                write_index(&mut index);
+            }
+            println!("{}", utils::u8_to_hex_str(tree.object_id.into_bytes()));
+        }
+        Command::Revert => {
+            let repo = repository();
+            require_work_tree(&repo);
+            let Some(rev) = args.get(2) else {
+                eprintln!("usage: revert <commit>");
+                process::exit(1);
+            };
+            let workspace = repo.workspace()?;
+            let database = repo.database();
+            let refs = repo.refs();
+            let mut index = load_index(repo.index_path());
 
-            let mut is_root = String::from("");
+            let parent = refs.read_head()?;
             if parent.is_empty() {
-                is_root = String::from("(root-commit) ");
+                eprintln!("fatal: cannot revert without a commit on HEAD");
+                process::exit(128);
+            }
+            let target = match revision::resolve_revision(&database, &refs, rev) {
+                Ok(target) => target,
+                Err(err) => {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }
+            };
+            let message = match revert::revert(&workspace, &database, &mut index, &refs, &target) {
+                Ok(message) => message,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    process::exit(1);
+                }
+            };
+
+            let mut tree = tree::Tree::from_index(&mut index);
+            let cache = tree.store_tree_cached(&database, index.tree_cache());
+            index.set_tree_cache(cache);
+            write_index(&mut index);
+
+            // Reverting a change HEAD no longer has leaves nothing to commit.
+            let (_, data) = database.read_object(&parent)?;
+            let tree_hex = utils::u8_to_hex_str(tree.object_id.as_bytes().to_vec());
+            if commit::parse_tree_oid(&data).as_deref() == Some(tree_hex.as_str()) {
+                println!("nothing to commit, working tree clean");
+                process::exit(1);
+            }
+            write_commit(&args, &repo, &parent, tree.object_id, message, "revert")?;
+        }
+        Command::RevParse => {
+            let repo = repository();
+            let database = database::Database::new(repo.db_path());
+            let refs = refs::Refs::new(repo.git_dir.clone());
+
+            let revs = args.iter().skip(2).collect::<Vec<_>>();
+            if revs.is_empty() {
+                eprintln!("usage: rev-parse <rev>...");
+                process::exit(1);
+            }
+            for rev in revs {
+                match revision::resolve_revision(&database, &refs, rev) {
+                    Ok(oid) => println!("{}", oid),
+                    Err(err) => {
+                        eprintln!("fatal: {}", err);
+                        process::exit(128);
+                    }
+                }
+            }
+        }
+        Command::Show => {
+            let repo = repository();
+            let git_path = repo.git_dir.clone();
+            let db_path = repo.db_path();
+
+            let database = database::Database::new(db_path);
+            let refs = refs::Refs::new(git_path);
+
+            let relative = args
+                .iter()
+                .skip(2)
+                .any(|arg| arg == "--relative-date" || arg == "--date=relative");
+            let date_format = if relative {
+                author::DateFormat::Relative(Local::now().timestamp())
+            } else {
+                author::DateFormat::Default
+            };
+            let rev = args
+                .iter()
+                .skip(2)
+                .find(|arg| !arg.starts_with('-'))
+                .map(String::as_str)
+                .unwrap_or("HEAD");
+            let output = revision::resolve_revision(&database, &refs, rev)
+                .and_then(|oid| show::show(&database, &oid, date_format));
+            match output {
+                Ok(output) => print!("{}", output),
+                Err(err) => {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }
+            }
+        }
+        Command::ShowRef => {
+            let repo = repository();
+            let refs = repo.refs();
+
+            // --heads and --tags each keep their namespace, both keep either.
+            let mut prefixes = Vec::new();
+            for arg in args.iter().skip(2) {
+                match arg.as_str() {
+                    "--heads" => prefixes.push("refs/heads/"),
+                    "--tags" => prefixes.push("refs/tags/"),
+                    _ => {
+                        eprintln!("usage: show-ref [--heads] [--tags]");
+                        process::exit(1);
+                    }
+                }
+            }
+            let listed = refs
+                .list_refs()?
+                .into_iter()
+                .filter(|(name, _)| {
+                    prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix))
+                })
+                .collect::<Vec<_>>();
+            if listed.is_empty() {
+                process::exit(1);
+            }
+            for (name, oid) in listed {
+                println!("{} {}", oid, name);
+            }
+        }
+        Command::Stash => {
+            let repo = repository();
+            require_work_tree(&repo);
+            let workspace = repo.workspace()?;
+            let database = repo.database();
+            let refs = repo.refs();
+            let mut index = load_index(repo.index_path());
+
+            let result = match args.get(2).map(String::as_str) {
+                None | Some("push") => {
+                    let (author, committer) = commit_identities(&args, &repo)?;
+                    stash::save(
+                        &workspace, &database, &mut index, &refs, &author, &committer,
+                    )
+                    .map(|saved| match saved {
+                        Some(message) => {
+                            format!("Saved working directory and index state {}", message)
+                        }
+                        None => String::from("No local changes to save"),
+                    })
+                }
+                Some("pop") => stash::pop(&workspace, &database, &mut index, &refs)
+                    .map(|stash| format!("Dropped refs/stash@{{0}} ({})", stash)),
+                Some(_) => {
+                    eprintln!("usage: stash [push | pop]");
+                    process::exit(1);
+                }
+            };
+            match result {
+                Ok(summary) => println!("{}", summary),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    process::exit(1);
+                }
+            }
+            if !write_index(&mut index) {
+                eprintln!("fatal: unable to write the index, .git/index.lock is held");
+                process::exit(128);
+            }
+        }
+        Command::Status => {
+            let repo = repository();
+            require_work_tree(&repo);
+            // set up paths.
+            let git_path = repo.git_dir.clone();
+            let db_path = repo.db_path();
+            let index_path = repo.index_path();
+
+            // set up git data structures.
+            let workspace = repo.workspace()?;
+            let database = database::Database::new(db_path);
+            let refs = refs::Refs::new(git_path);
+            let mut index = load_index(index_path);
+
+            let report = status::status(&workspace, &database, &mut index, &refs)?;
+            // Saving refreshed stats is opportunistic, a held lock is not an error.
+            if index.is_changed() {
+                write_index(&mut index);
+            }
+            // Porcelain output is for scripts and never colored.
+            if args.iter().skip(2).any(|arg| arg == "--porcelain") {
+                print!("{}", status::format_porcelain(&report));
+            } else if use_color(&args, &repo)? {
+                print!("{}", color::status_long(&status::format_long(&report)));
+            } else {
+                print!("{}", status::format_long(&report));
+            }
+        }
+        Command::UpdateIndex => {
+            let repo = repository();
+            let index_path = repo.index_path();
+            let mut index = load_index(index_path);
+
+            let skip = match args.get(2).map(String::as_str) {
+                Some("--skip-worktree") => true,
+                Some("--no-skip-worktree") => false,
+                _ => {
+                    eprintln!(
+                        "usage: update-index (--skip-worktree | --no-skip-worktree) <file>..."
+                    );
+                    process::exit(1);
+                }
+            };
+            for arg in args.iter().skip(3) {
+                let path = repo.resolve_pathspec(arg).display().to_string();
+                if !index.set_skip_worktree(&path, skip) {
+                    eprintln!("error: {} is not in the index", path);
+                    process::exit(1);
+                }
+            }
+            write_index(&mut index);
+        }
+        Command::VerifyIndex => {
+            let repo = repository();
+            let index_path = repo.index_path();
+            match index::verify_index(&index_path) {
+                Ok(header) => {
+                    println!(
+                        "index ok: version {}, {} entries",
+                        header.version, header.entry_count
+                    );
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    process::exit(1);
+                }
             }
-            println!("[{}{}] {}", is_root, commit_hex_str, first_line);
         }
         Command::Unknown => {
             eprintln!("Usage: {} <command> [<directory>]", args[0]);
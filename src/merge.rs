@@ -0,0 +1,254 @@
+use std::{collections::BTreeMap, io};
+
+use crate::blob::Blob;
+use crate::database::Database;
+use crate::diff::{self, Edit};
+use crate::tree::TreeEntry;
+use crate::utils;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeResult {
+    // Both sides merged without overlapping changes.
+    Clean(Vec<String>),
+    // At least one region was changed differently by both sides. The lines
+    // include the conflict markers around each such region.
+    Conflicted(Vec<String>),
+}
+
+/*
+    Merges the changes ours and theirs each made to base, line by line in
+    the manner of diff3. Lines are expected to keep their trailing newline,
+    as produced by str::split_inclusive('\n').
+*/
+pub fn three_way(base: &[&str], ours: &[&str], theirs: &[&str]) -> MergeResult {
+    let ours_match = matches(base, ours);
+    let theirs_match = matches(base, theirs);
+
+    let mut merged = Vec::new();
+    let mut conflicted = false;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // A base line both sides kept in place is stable.
+        if i < base.len() && ours_match[i] == Some(j) && theirs_match[i] == Some(k) {
+            merged.push(base[i].to_string());
+            i += 1;
+            j += 1;
+            k += 1;
+            continue;
+        }
+
+        // Otherwise the changed chunk runs up to the next base line both kept.
+        let next = (i..base.len()).find(|&n| ours_match[n].is_some() && theirs_match[n].is_some());
+        let (base_end, ours_end, theirs_end) = match next {
+            Some(n) => (n, ours_match[n].unwrap(), theirs_match[n].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        let base_chunk = &base[i..base_end];
+        let ours_chunk = &ours[j..ours_end];
+        let theirs_chunk = &theirs[k..theirs_end];
+
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            merged.extend(theirs_chunk.iter().map(|line| line.to_string()));
+        } else if theirs_chunk == base_chunk {
+            merged.extend(ours_chunk.iter().map(|line| line.to_string()));
+        } else {
+            conflicted = true;
+            merged.push(String::from("<<<<<<< ours\n"));
+            push_lines(&mut merged, ours_chunk);
+            merged.push(String::from("=======\n"));
+            push_lines(&mut merged, theirs_chunk);
+            merged.push(String::from(">>>>>>> theirs\n"));
+        }
+
+        if next.is_none() {
+            break;
+        }
+        i = base_end;
+        j = ours_end;
+        k = theirs_end;
+    }
+
+    if conflicted {
+        MergeResult::Conflicted(merged)
+    } else {
+        MergeResult::Clean(merged)
+    }
+}
+
+/*
+    Merges the changes ours and theirs each made to the files of base,
+    all keyed by path as produced by flatten_tree. A file changed on one
+    side only takes that side. A text file changed on both sides with the
+    same mode is merged line by line and the result stored as a blob.
+    Returns the merged files and the paths that conflicted, which keep
+    their version from ours.
+*/
+pub fn merge_trees(
+    database: &Database,
+    base: &BTreeMap<String, TreeEntry>,
+    ours: &BTreeMap<String, TreeEntry>,
+    theirs: &BTreeMap<String, TreeEntry>,
+) -> io::Result<(BTreeMap<String, TreeEntry>, Vec<String>)> {
+    let same = |a: Option<&TreeEntry>, b: Option<&TreeEntry>| match (a, b) {
+        (Some(a), Some(b)) => a.mode == b.mode && a.object_id == b.object_id,
+        (None, None) => true,
+        _ => false,
+    };
+    let mut paths = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+
+    let mut merged = BTreeMap::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let file = if same(o, t) || same(b, t) {
+            o.cloned()
+        } else if same(b, o) {
+            t.cloned()
+        } else {
+            let content = match (b, o, t) {
+                (Some(b), Some(o), Some(t)) if o.mode == t.mode => merge_blobs(database, b, o, t)?,
+                _ => None,
+            };
+            if content.is_none() {
+                conflicts.push(path.clone());
+            }
+            content.or_else(|| o.cloned())
+        };
+        if let Some(file) = file {
+            merged.insert(path.clone(), file);
+        }
+    }
+    Ok((merged, conflicts))
+}
+
+// The stored blob of a clean line merge, or None when it conflicts or a
+// side isn't text.
+fn merge_blobs(
+    database: &Database,
+    base: &TreeEntry,
+    ours: &TreeEntry,
+    theirs: &TreeEntry,
+) -> io::Result<Option<TreeEntry>> {
+    let mut texts = Vec::new();
+    for file in [base, ours, theirs] {
+        let (_, data) = database.read_object(&file.object_id)?;
+        match String::from_utf8(data) {
+            Ok(text) => texts.push(text),
+            Err(_) => return Ok(None),
+        }
+    }
+    let lines = texts
+        .iter()
+        .map(|text| text.split_inclusive('\n').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    match three_way(&lines[0], &lines[1], &lines[2]) {
+        MergeResult::Clean(lines) => {
+            let mut blob = Blob::new(&lines.concat());
+            database.store(&mut blob)?;
+            Ok(Some(TreeEntry {
+                object_id: utils::u8_to_hex_str(blob.object_id.into_bytes()),
+                ..ours.clone()
+            }))
+        }
+        MergeResult::Conflicted(_) => Ok(None),
+    }
+}
+
+// For each base line, the index of the line it is kept as on the other side.
+fn matches(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; base.len()];
+    for edit in diff::diff_lines(base, other) {
+        if let Edit::Equal(a, b) = edit {
+            matched[a] = Some(b);
+        }
+    }
+    matched
+}
+
+// Conflict markers must start on their own line, even after a final line
+// that had no newline.
+fn push_lines(merged: &mut Vec<String>, lines: &[&str]) {
+    for line in lines {
+        let mut line = line.to_string();
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        merged.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> MergeResult {
+        let lines = |text| str::split_inclusive(text, '\n').collect::<Vec<_>>();
+        three_way(&lines(base), &lines(ours), &lines(theirs))
+    }
+
+    fn text(result: &MergeResult) -> String {
+        match result {
+            MergeResult::Clean(lines) | MergeResult::Conflicted(lines) => lines.concat(),
+        }
+    }
+
+    #[test]
+    fn separate_changes_merge_cleanly() {
+        let result = merge("a\nb\nc\nd\n", "A\nb\nc\nd\n", "a\nb\nc\nD\n");
+        assert_eq!(
+            result,
+            MergeResult::Clean(vec!["A\n".into(), "b\n".into(), "c\n".into(), "D\n".into()])
+        );
+    }
+
+    #[test]
+    fn both_sides_adding_the_same_line_is_clean() {
+        let result = merge("a\nb\n", "a\nnew\nb\n", "a\nnew\nb\n");
+        assert_eq!(text(&result), "a\nnew\nb\n");
+        assert!(matches!(result, MergeResult::Clean(_)));
+    }
+
+    #[test]
+    fn different_edits_to_a_line_conflict() {
+        let result = merge("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n");
+        assert!(matches!(result, MergeResult::Conflicted(_)));
+        assert_eq!(
+            text(&result),
+            "a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nc\n"
+        );
+    }
+
+    #[test]
+    fn deleting_a_region_the_other_side_edits_conflicts() {
+        let result = merge("a\nb\nc\nd\n", "a\nd\n", "a\nb\nC\nd\n");
+        assert!(matches!(result, MergeResult::Conflicted(_)));
+        assert_eq!(
+            text(&result),
+            "a\n<<<<<<< ours\n=======\nb\nC\n>>>>>>> theirs\nd\n"
+        );
+    }
+
+    #[test]
+    fn deleting_an_untouched_region_is_clean() {
+        let result = merge("a\nb\nc\nd\n", "a\nd\n", "a\nb\nc\nd\ne\n");
+        assert_eq!(
+            result,
+            MergeResult::Clean(vec!["a\n".into(), "d\n".into(), "e\n".into()])
+        );
+    }
+
+    #[test]
+    fn markers_start_on_their_own_line() {
+        let result = merge("a\nb", "a\nours", "a\ntheirs");
+        assert_eq!(
+            text(&result),
+            "a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n"
+        );
+    }
+}
@@ -0,0 +1,600 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use flate2::{read::ZlibDecoder, Crc};
+use tempfile::NamedTempFile;
+
+use crate::database::Database;
+use crate::hash::{self, OID_SIZE};
+use crate::utils;
+
+const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+const PACK_VERSION: u32 = 2;
+const IDX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+const IDX_VERSION: u32 = 2;
+// Offsets with this bit set in the index point into the 8 byte offset table.
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+// Object type codes stored in pack entry headers.
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
+
+fn type_code(kind: &str) -> io::Result<u8> {
+    match kind {
+        "commit" => Ok(OBJ_COMMIT),
+        "tree" => Ok(OBJ_TREE),
+        "blob" => Ok(OBJ_BLOB),
+        "tag" => Ok(OBJ_TAG),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown object type {}", kind),
+        )),
+    }
+}
+
+fn type_name(code: u8) -> Option<&'static str> {
+    match code {
+        OBJ_COMMIT => Some("commit"),
+        OBJ_TREE => Some("tree"),
+        OBJ_BLOB => Some("blob"),
+        OBJ_TAG => Some("tag"),
+        _ => None,
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/*
+    Writes the objects with the given hex ids into a new version 2 pack
+    in pack_dir, along with its version 2 index. Every object is stored
+    whole, no deltas are computed. The pack is named after its checksum,
+    pack-<hex>.pack, and its path is returned. The pack is written before
+    the index, so any index a reader finds has its pack in place.
+*/
+pub fn write_pack(database: &Database, oids: &[String], pack_dir: &Path) -> io::Result<PathBuf> {
+    let count = u32::try_from(oids.len())
+        .map_err(|_| invalid(format!("{} objects do not fit in a pack", oids.len())))?;
+    let mut pack = Vec::new();
+    pack.extend_from_slice(PACK_SIGNATURE);
+    pack.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    pack.extend_from_slice(&count.to_be_bytes());
+
+    // (raw oid, crc32 of the packed entry, offset of the entry)
+    let mut entries = Vec::new();
+    for oid in oids {
+        let (kind, data) = database.read_object(oid)?;
+        let offset = pack.len();
+        write_entry_header(&mut pack, type_code(&kind)?, data.len());
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(&data)?;
+        pack.extend_from_slice(&encoder.finish()?);
+
+        let mut crc = Crc::new();
+        crc.update(&pack[offset..]);
+        let raw = utils::hex_to_u8(oid).ok_or_else(|| invalid(format!("bad object id {}", oid)))?;
+        entries.push((raw, crc.sum(), offset as u64));
+    }
+    let checksum = hash::oid_bytes(&pack);
+    pack.extend_from_slice(&checksum);
+    entries.sort();
+
+    let mut idx = Vec::new();
+    idx.extend_from_slice(IDX_SIGNATURE);
+    idx.extend_from_slice(&IDX_VERSION.to_be_bytes());
+    // fanout[b] counts the objects whose first byte is at most b.
+    for byte in 0..=255u8 {
+        let below = entries
+            .iter()
+            .take_while(|(raw, _, _)| raw[0] <= byte)
+            .count() as u32;
+        idx.extend_from_slice(&below.to_be_bytes());
+    }
+    for (raw, _, _) in &entries {
+        idx.extend_from_slice(raw);
+    }
+    for (_, crc, _) in &entries {
+        idx.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut large_offsets = Vec::new();
+    for (_, _, offset) in &entries {
+        let small = match u32::try_from(*offset) {
+            Ok(offset) if offset & LARGE_OFFSET_FLAG == 0 => offset,
+            _ => {
+                let index = (large_offsets.len() / 8) as u32;
+                large_offsets.extend_from_slice(&offset.to_be_bytes());
+                index | LARGE_OFFSET_FLAG
+            }
+        };
+        idx.extend_from_slice(&small.to_be_bytes());
+    }
+    idx.extend_from_slice(&large_offsets);
+    idx.extend_from_slice(&checksum);
+    let idx_checksum = hash::oid_bytes(&idx);
+    idx.extend_from_slice(&idx_checksum);
+
+    fs::create_dir_all(pack_dir)?;
+    let name = format!("pack-{}", utils::u8_to_hex_str(checksum.to_vec()));
+    let pack_path = pack_dir.join(format!("{}.pack", name));
+    write_read_only(pack_dir, &pack_path, &pack)?;
+    write_read_only(pack_dir, &pack_dir.join(format!("{}.idx", name)), &idx)?;
+    Ok(pack_path)
+}
+
+// Packs are immutable, like loose objects they are stored read-only.
+fn write_read_only(dir: &Path, path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(data)?;
+    fs::set_permissions(temp_file.path(), fs::Permissions::from_mode(0o444))?;
+    temp_file.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/*
+    Encodes the type and inflated size of an entry. The first byte holds
+    the type and the low 4 bits of the size, each following byte 7 more
+    bits, with the high bit set on every byte but the last.
+*/
+fn write_entry_header(out: &mut Vec<u8>, code: u8, size: usize) {
+    let mut byte = (code << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size != 0 {
+        out.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    out.push(byte);
+}
+
+fn read_entry_header<R: Read>(reader: &mut R) -> io::Result<(u8, usize)> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let code = (byte[0] >> 4) & 0x07;
+    let mut size = (byte[0] & 0x0f) as usize;
+    let mut shift = 4;
+    while byte[0] & 0x80 != 0 {
+        reader.read_exact(&mut byte)?;
+        if shift > usize::BITS - 7 {
+            return Err(invalid(String::from("pack entry size overflows")));
+        }
+        size |= ((byte[0] & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+    Ok((code, size))
+}
+
+/*
+    A version 2 pack index, mapping object ids to offsets in its pack.
+*/
+#[derive(Debug)]
+pub struct PackIndex {
+    pack_path: PathBuf,
+    fanout: [u32; 256],
+    // The sorted raw object ids, OID_SIZE bytes each.
+    oids: Vec<u8>,
+    offsets: Vec<u32>,
+    large_offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    /*
+        Reads and validates the index at idx_path. Its pack is expected
+        next to it with the .pack extension.
+    */
+    pub fn open(idx_path: &Path) -> io::Result<Self> {
+        let data = fs::read(idx_path)?;
+        let corrupt = || invalid(format!("corrupt pack index {}", idx_path.display()));
+        let fanout_end = 8 + 256 * 4;
+        if data.len() < fanout_end + 2 * OID_SIZE
+            || &data[0..4] != IDX_SIGNATURE
+            || data[4..8] != IDX_VERSION.to_be_bytes()
+        {
+            return Err(corrupt());
+        }
+        let (content, checksum) = data.split_at(data.len() - OID_SIZE);
+        if hash::oid_bytes(content) != checksum {
+            return Err(corrupt());
+        }
+
+        let word = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+        let mut fanout = [0u32; 256];
+        for (i, count) in fanout.iter_mut().enumerate() {
+            *count = word(8 + i * 4);
+        }
+        let count = fanout[255] as usize;
+        let oids_end = fanout_end + count * OID_SIZE;
+        let offsets_start = oids_end + count * 4;
+        let offsets_end = offsets_start + count * 4;
+        let large_end = content.len() - OID_SIZE;
+        if offsets_end > large_end || !(large_end - offsets_end).is_multiple_of(8) {
+            return Err(corrupt());
+        }
+
+        Ok(PackIndex {
+            pack_path: idx_path.with_extension("pack"),
+            fanout,
+            oids: data[fanout_end..oids_end].to_vec(),
+            offsets: (0..count).map(|i| word(offsets_start + i * 4)).collect(),
+            large_offsets: data[offsets_end..large_end]
+                .chunks(8)
+                .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    fn oid_at(&self, i: usize) -> &[u8] {
+        &self.oids[i * OID_SIZE..(i + 1) * OID_SIZE]
+    }
+
+    /*
+        The offset in the pack of the object with the given hex id, found by
+        binary search within the fanout range of its first byte.
+    */
+    pub fn lookup(&self, oid_hex: &str) -> Option<u64> {
+        let raw = utils::hex_to_u8(oid_hex).filter(|raw| raw.len() == OID_SIZE)?;
+        let first = raw[0] as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let end = self.fanout[first] as usize;
+        let mut range = start..end;
+        while !range.is_empty() {
+            let mid = range.start + range.len() / 2;
+            match self.oid_at(mid).cmp(&raw[..]) {
+                std::cmp::Ordering::Less => range.start = mid + 1,
+                std::cmp::Ordering::Greater => range.end = mid,
+                std::cmp::Ordering::Equal => return self.offset_at(mid),
+            }
+        }
+        None
+    }
+
+    fn offset_at(&self, i: usize) -> Option<u64> {
+        let offset = self.offsets[i];
+        if offset & LARGE_OFFSET_FLAG == 0 {
+            return Some(offset as u64);
+        }
+        self.large_offsets
+            .get((offset & !LARGE_OFFSET_FLAG) as usize)
+            .copied()
+    }
+
+    /*
+        The hex ids of every object in the pack, sorted.
+    */
+    pub fn oids(&self) -> Vec<String> {
+        (0..self.len())
+            .map(|i| utils::u8_to_hex_str(self.oid_at(i).to_vec()))
+            .collect()
+    }
+}
+
+// Longest delta chain followed before the pack is assumed to loop.
+const MAX_DELTA_CHAIN: usize = 10_000;
+
+// Reads a whole object by hex id, used for the bases of ref deltas.
+pub type ReadBase<'a> = dyn Fn(&str) -> io::Result<(String, Vec<u8>)> + 'a;
+
+/*
+    Reads the entry at offset in the pack at pack_path, returning its type
+    and inflated content. Deltified entries are rebuilt by applying each
+    delta in the chain to the base it leads to. Offset deltas find their
+    base in the same pack, the base of a ref delta is named by object id
+    and is read through read_base, since it may be stored anywhere.
+*/
+pub fn read_entry(
+    pack_path: &Path,
+    offset: u64,
+    read_base: &ReadBase<'_>,
+) -> io::Result<(String, Vec<u8>)> {
+    let at = |offset: u64| format!("at offset {} in {}", offset, pack_path.display());
+    let mut reader = BufReader::new(File::open(pack_path)?);
+    let mut deltas = Vec::new();
+    let mut offset = offset;
+    let (kind, mut data) = loop {
+        if deltas.len() > MAX_DELTA_CHAIN {
+            return Err(invalid(format!("delta chain too long {}", at(offset))));
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let (code, size) = read_entry_header(&mut reader)?;
+        match code {
+            OBJ_OFS_DELTA => {
+                let distance = read_base_distance(&mut reader)?;
+                deltas.push(inflate_entry(&mut reader, size, &at(offset))?);
+                offset = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or_else(|| invalid(format!("bad delta base {}", at(offset))))?;
+            }
+            OBJ_REF_DELTA => {
+                let mut base = [0u8; OID_SIZE];
+                reader.read_exact(&mut base)?;
+                deltas.push(inflate_entry(&mut reader, size, &at(offset))?);
+                break read_base(&utils::u8_to_hex_str(base.to_vec()))?;
+            }
+            _ => match type_name(code) {
+                Some(kind) => {
+                    break (
+                        kind.to_string(),
+                        inflate_entry(&mut reader, size, &at(offset))?,
+                    )
+                }
+                None => {
+                    return Err(invalid(format!(
+                        "unknown object type {} {}",
+                        code,
+                        at(offset)
+                    )))
+                }
+            },
+        }
+    };
+    // The delta read first applies last, on top of everything below it.
+    for delta in deltas.iter().rev() {
+        data = apply_delta(&data, delta)?;
+    }
+    Ok((kind, data))
+}
+
+fn inflate_entry<R: Read>(reader: &mut R, size: usize, at: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(reader)
+        .take(size as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(invalid(format!(
+            "pack entry {} does not match its size of {} bytes",
+            at, size
+        )));
+    }
+    Ok(data)
+}
+
+/*
+    Reads how far before an offset delta its base starts. Like the
+    version 4 index paths, each continuation adds one before shifting.
+*/
+fn read_base_distance<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let mut distance = (byte[0] & 0x7f) as u64;
+    while byte[0] & 0x80 != 0 {
+        reader.read_exact(&mut byte)?;
+        distance = (distance + 1)
+            .checked_mul(128)
+            .ok_or_else(|| invalid(String::from("delta base offset overflows")))?
+            | (byte[0] & 0x7f) as u64;
+    }
+    Ok(distance)
+}
+
+// Reads a little-endian base 128 size from the start of a delta.
+fn delta_size(delta: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *delta
+            .get(*pos)
+            .ok_or_else(|| invalid(String::from("truncated delta header")))?;
+        *pos += 1;
+        if shift > usize::BITS - 7 {
+            return Err(invalid(String::from("delta size overflows")));
+        }
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/*
+    Rebuilds an object from its base and a delta. The delta starts with
+    the base and result sizes, followed by instructions that either copy
+    a range of the base, when the high bit is set, or insert the next n
+    literal bytes, for an instruction byte n from 1 to 127.
+*/
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let truncated = || invalid(String::from("truncated delta"));
+    let mut pos = 0;
+    if delta_size(delta, &mut pos)? != base.len() {
+        return Err(invalid(String::from("delta does not apply to its base")));
+    }
+    let size = delta_size(delta, &mut pos)?;
+    let mut result = Vec::with_capacity(size.min(base.len() + delta.len()));
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // Bits 0-3 select which offset bytes follow, bits 4-6 which
+            // size bytes, least significant first.
+            let mut offset = 0usize;
+            let mut length = 0usize;
+            for bit in 0..7 {
+                if op & (1 << bit) == 0 {
+                    continue;
+                }
+                let byte = *delta.get(pos).ok_or_else(truncated)? as usize;
+                pos += 1;
+                if bit < 4 {
+                    offset |= byte << (bit * 8);
+                } else {
+                    length |= byte << ((bit - 4) * 8);
+                }
+            }
+            // A size of zero stands for 64KiB.
+            if length == 0 {
+                length = 0x10000;
+            }
+            let copy = offset
+                .checked_add(length)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(|| invalid(String::from("delta copies past the end of its base")))?;
+            result.extend_from_slice(copy);
+        } else if op != 0 {
+            let insert = delta.get(pos..pos + op as usize).ok_or_else(truncated)?;
+            result.extend_from_slice(insert);
+            pos += op as usize;
+        } else {
+            return Err(invalid(String::from("reserved delta instruction 0")));
+        }
+        if result.len() > size {
+            break;
+        }
+    }
+    if result.len() != size {
+        return Err(invalid(String::from(
+            "delta result does not match its size",
+        )));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::blob::Blob;
+
+    // Stores each content as a loose blob, returning the hex ids.
+    fn store_blobs(database: &Database, contents: &[&str]) -> Vec<String> {
+        contents
+            .iter()
+            .map(|content| {
+                let mut blob = Blob::new(content);
+                database.store(&mut blob).unwrap();
+                utils::u8_to_hex_str(blob.object_id.into_bytes())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packed_objects_read_back_by_oid() {
+        let dir = TempDir::new().unwrap();
+        let database = Database::new(dir.path().join("objects"));
+        let oids = store_blobs(&database, &["one\n", "two\n", "three\n", ""]);
+        let pack_dir = dir.path().join("objects").join("pack");
+
+        let pack_path = write_pack(&database, &oids, &pack_dir).unwrap();
+        let idx = PackIndex::open(&pack_path.with_extension("idx")).unwrap();
+        assert_eq!(idx.pack_path(), pack_path);
+        assert_eq!(idx.len(), 4);
+        let mut sorted = oids.clone();
+        sorted.sort();
+        assert_eq!(idx.oids(), sorted);
+
+        let offset = idx.lookup(&oids[2]).unwrap();
+        let no_bases = |oid: &str| -> io::Result<(String, Vec<u8>)> {
+            panic!("unexpected base lookup for {}", oid)
+        };
+        let (kind, data) = read_entry(&pack_path, offset, &no_bases).unwrap();
+        assert_eq!((kind.as_str(), data.as_slice()), ("blob", &b"three\n"[..]));
+        assert_eq!(idx.lookup("0123456789abcdef0123456789abcdef01234567"), None);
+    }
+
+    fn deflated(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    const BASE: &[u8] = b"hello world\n";
+    // Copies "hello ", inserts "there ", then copies "world\n".
+    const DELTA: &[u8] = b"\x0c\x12\x90\x06\x06there \x91\x06\x06";
+
+    #[test]
+    fn delta_copies_and_inserts_onto_its_base() {
+        assert_eq!(apply_delta(BASE, DELTA).unwrap(), b"hello there world\n");
+
+        let err = apply_delta(b"short", DELTA).unwrap_err();
+        assert_eq!(err.to_string(), "delta does not apply to its base");
+        // Copies 16 bytes from a 12 byte base.
+        let err = apply_delta(BASE, b"\x0c\x10\x90\x10").unwrap_err();
+        assert_eq!(err.to_string(), "delta copies past the end of its base");
+        let err = apply_delta(BASE, b"\x0c\x03\x05ab").unwrap_err();
+        assert_eq!(err.to_string(), "truncated delta");
+        let err = apply_delta(BASE, b"\x0c\x03\x02ab").unwrap_err();
+        assert_eq!(err.to_string(), "delta result does not match its size");
+    }
+
+    #[test]
+    fn offset_delta_chains_rebuild_from_their_base() {
+        let dir = TempDir::new().unwrap();
+        let pack_path = dir.path().join("test.pack");
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x03".to_vec();
+        let base_offset = pack.len();
+        write_entry_header(&mut pack, OBJ_BLOB, BASE.len());
+        pack.extend(deflated(BASE));
+        let delta_offset = pack.len();
+        write_entry_header(&mut pack, OBJ_OFS_DELTA, DELTA.len());
+        pack.push((delta_offset - base_offset) as u8);
+        pack.extend(deflated(DELTA));
+        // A delta of the delta, keeping the first five bytes, "hello".
+        let top_offset = pack.len();
+        write_entry_header(&mut pack, OBJ_OFS_DELTA, 4);
+        pack.push((top_offset - delta_offset) as u8);
+        pack.extend(deflated(b"\x12\x05\x90\x05"));
+        fs::write(&pack_path, &pack).unwrap();
+
+        let no_bases = |oid: &str| -> io::Result<(String, Vec<u8>)> {
+            panic!("unexpected base lookup for {}", oid)
+        };
+        let read = |offset| read_entry(&pack_path, offset as u64, &no_bases).unwrap();
+        assert_eq!(read(base_offset), (String::from("blob"), BASE.to_vec()));
+        assert_eq!(
+            read(delta_offset),
+            (String::from("blob"), b"hello there world\n".to_vec())
+        );
+        assert_eq!(read(top_offset), (String::from("blob"), b"hello".to_vec()));
+    }
+
+    #[test]
+    fn ref_delta_reads_its_base_by_oid() {
+        let dir = TempDir::new().unwrap();
+        let pack_path = dir.path().join("test.pack");
+        let base_oid = "0123456789abcdef0123456789abcdef01234567";
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        write_entry_header(&mut pack, OBJ_REF_DELTA, DELTA.len());
+        pack.extend(utils::hex_to_u8(base_oid).unwrap());
+        pack.extend(deflated(DELTA));
+        fs::write(&pack_path, &pack).unwrap();
+
+        let read_base = |oid: &str| {
+            assert_eq!(oid, base_oid);
+            Ok((String::from("tree"), BASE.to_vec()))
+        };
+        // The rebuilt object takes the type of its base.
+        assert_eq!(
+            read_entry(&pack_path, 12, &read_base).unwrap(),
+            (String::from("tree"), b"hello there world\n".to_vec())
+        );
+    }
+}
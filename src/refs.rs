@@ -1,26 +1,97 @@
 use std::{
     error, fmt,
-    fs::File,
-    io::{self, Read},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
     path::PathBuf,
 };
 
+use crate::author::Author;
 use crate::lockfile;
 
+// Prefix of a HEAD file that points at a branch rather than a commit.
+const SYMREF_PREFIX: &str = "ref: ";
+// Old value recorded in the reflog when a ref is created.
+const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
 #[derive(Debug)]
 pub enum RefsError {
     LockDenied,
+    // The ref no longer holds the expected value: (name, actual value).
+    Stale(String, Option<String>),
+    // A branch name git would refuse, such as one containing "..".
+    InvalidName(String),
+    // A branch that would be created already exists.
+    Exists(String),
 }
 
 impl error::Error for RefsError {}
 
 impl fmt::Display for RefsError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let err = self;
-        write!(f, "{}", err)
+        match self {
+            RefsError::LockDenied => write!(f, "unable to acquire ref lock"),
+            RefsError::Stale(name, Some(actual)) => {
+                write!(f, "{} was concurrently updated to {}", name, actual)
+            }
+            RefsError::Stale(name, None) => write!(f, "{} was concurrently deleted", name),
+            RefsError::InvalidName(name) => write!(f, "'{}' is not a valid branch name", name),
+            RefsError::Exists(name) => write!(f, "a branch named '{}' already exists", name),
+        }
+    }
+}
+
+/*
+    One line of a reflog, recording a single update of a ref.
+*/
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old_oid: String,
+    pub new_oid: String,
+    pub committer: Author,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old_oid, rest) = header.split_once(' ')?;
+        let (new_oid, committer) = rest.split_once(' ')?;
+        Some(ReflogEntry {
+            old_oid: old_oid.to_string(),
+            new_oid: new_oid.to_string(),
+            committer: Author::parse(committer)?,
+            message: message.to_string(),
+        })
     }
 }
 
+/*
+    A subset of git check-ref-format for branch names: no empty or dot
+    leading components, no "..", "@{", control characters or any of
+    " ~^:?*[\\", and no trailing "/", "." or ".lock".
+*/
+pub fn valid_branch_name(name: &str) -> bool {
+    if name.is_empty()
+        || name == "HEAD"
+        || name.starts_with('-')
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.ends_with(".lock")
+        || name.contains("..")
+        || name.contains("@{")
+    {
+        return false;
+    }
+    if name
+        .chars()
+        .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    {
+        return false;
+    }
+    name.split('/')
+        .all(|component| !component.is_empty() && !component.starts_with('.'))
+}
+
 #[derive(Debug)]
 pub struct Refs {
     pub pathname: PathBuf,
@@ -31,35 +102,411 @@ impl Refs {
         Self { pathname }
     }
 
-    pub fn update_head(&self, commit_hex_str: String) -> Result<(), RefsError> {
-        let path = self.pathname.clone().join("HEAD");
+    /*
+        Points HEAD at the branch refs/heads/<branch>, which need not exist yet.
+    */
+    pub fn set_head_branch(&self, branch: &str) -> Result<(), RefsError> {
+        let contents = format!("{}refs/heads/{}", SYMREF_PREFIX, branch);
+        self.write_ref(self.head_path(), contents)
+    }
+
+    /*
+        Writes commit_hex_str to HEAD, or to the branch HEAD points at,
+        provided it still holds expected_old. None expects no commit yet.
+    */
+    pub fn update_head(
+        &self,
+        expected_old: Option<&str>,
+        commit_hex_str: &str,
+    ) -> Result<(), RefsError> {
+        let path = self.head_target().unwrap_or_else(|_| self.head_path());
+        self.swap_ref(path, expected_old, commit_hex_str)
+    }
+
+    /*
+        Sets the ref at ref_name, relative to the git directory, to new only
+        if it currently holds expected_old, checked while the ref is locked
+        so a concurrent update can't be lost. An expected_old of None
+        requires that the ref doesn't exist yet.
+    */
+    pub fn compare_and_swap(
+        &self,
+        ref_name: &str,
+        expected_old: Option<&str>,
+        new: &str,
+    ) -> Result<(), RefsError> {
+        self.swap_ref(self.pathname.join(ref_name), expected_old, new)
+    }
+
+    fn swap_ref(
+        &self,
+        path: PathBuf,
+        expected_old: Option<&str>,
+        new: &str,
+    ) -> Result<(), RefsError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|_| RefsError::LockDenied)?;
+        }
+        let mut lockfile = lockfile::LockFile::new(path.clone());
+        if !matches!(lockfile.hold_for_update(), Ok(true)) {
+            return Err(RefsError::LockDenied);
+        }
+
+        // A ref with no loose file may still be in packed-refs, and that
+        // is its current value.
+        let name = path.strip_prefix(&self.pathname).unwrap_or(&path);
+        let name = name.display().to_string();
+        let current = if path.is_file() {
+            Self::read_file(path.clone()).map(Some)
+        } else {
+            self.packed_ref(&name)
+        };
+        let current = match current {
+            Ok(current) => current.filter(|current| !current.is_empty()),
+            Err(_) => {
+                let _ = lockfile.rollback();
+                return Err(RefsError::LockDenied);
+            }
+        };
+        if current.as_deref() != expected_old {
+            let _ = lockfile.rollback();
+            return Err(RefsError::Stale(name, current));
+        }
+
+        let _ = lockfile.write(format!("{}\n", new));
+        lockfile.commit().map_err(|_| RefsError::LockDenied)
+    }
+
+    fn write_ref(&self, path: PathBuf, contents: String) -> Result<(), RefsError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|_| RefsError::LockDenied)?;
+        }
         let mut lockfile = lockfile::LockFile::new(path);
         match lockfile.hold_for_update() {
             Ok(true) => {
                 // uncaught results!
-                let _ = lockfile.write(commit_hex_str);
+                let _ = lockfile.write(contents);
                 let _ = lockfile.write(String::from("\n"));
                 let _ = lockfile.commit();
                 Ok(())
             }
-            Ok(false) => {
-                dbg!("false in update head");
-                Err(RefsError::LockDenied)
-            }
-            Err(err) => {
-                dbg!(err);
-                Err(RefsError::LockDenied)
-            }
+            Ok(false) | Err(_) => Err(RefsError::LockDenied),
+        }
+    }
+
+    /*
+        Creates refs/heads/<branch> pointing at commit_hex_str. An existing
+        branch is never overwritten.
+    */
+    pub fn create_branch(&self, branch: &str, commit_hex_str: &str) -> Result<(), RefsError> {
+        if !valid_branch_name(branch) {
+            return Err(RefsError::InvalidName(branch.to_string()));
         }
+        let ref_name = format!("refs/heads/{}", branch);
+        match self.compare_and_swap(&ref_name, None, commit_hex_str) {
+            Err(RefsError::Stale(_, Some(_))) => Err(RefsError::Exists(branch.to_string())),
+            result => result,
+        }
+    }
+
+    /*
+        The names of the branches under refs/heads, sorted.
+    */
+    pub fn list_branches(&self) -> io::Result<Vec<String>> {
+        Ok(self
+            .list_refs()?
+            .into_iter()
+            .filter_map(|(name, _)| name.strip_prefix("refs/heads/").map(str::to_string))
+            .collect())
+    }
+
+    /*
+        The branch HEAD points at, which may not have any commits yet.
+        None when HEAD is detached at a commit.
+    */
+    pub fn current_branch(&self) -> io::Result<Option<String>> {
+        let contents = Self::read_file(self.head_path())?;
+        Ok(contents
+            .strip_prefix(SYMREF_PREFIX)
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .map(str::to_string))
+    }
+
+    /*
+        Reads the commit id stored in ref_name, for example
+        "refs/heads/main", from its loose file or else from packed-refs.
+        An empty string is returned when it doesn't exist.
+    */
+    pub fn read_ref(&self, ref_name: &str) -> io::Result<String> {
+        let path = self.pathname.join(ref_name);
+        if path.is_file() {
+            return Self::read_file(path);
+        }
+        Ok(self.packed_ref(ref_name)?.unwrap_or_default())
+    }
+
+    /*
+        Looks up a ref by a possibly shortened name the way git does,
+        trying the name itself and then under refs/, refs/tags/,
+        refs/heads/ and refs/remotes/, loose or packed. A symbolic ref is
+        followed to its target. None is returned when no ref matches or it
+        has no commit.
+    */
+    pub fn lookup(&self, name: &str) -> io::Result<Option<String>> {
+        if name.is_empty() || name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Ok(None);
+        }
+        for prefix in ["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"] {
+            let full_name = format!("{}{}", prefix, name);
+            let path = self.pathname.join(&full_name);
+            let contents = if path.is_file() {
+                Self::read_file(path)?
+            } else if let Some(oid) = self.packed_ref(&full_name)? {
+                oid
+            } else {
+                continue;
+            };
+            let oid = match contents.strip_prefix(SYMREF_PREFIX) {
+                Some(target) => self.read_ref(target)?,
+                None => contents,
+            };
+            return Ok(Some(oid).filter(|oid| !oid.is_empty()));
+        }
+        Ok(None)
     }
 
     pub fn head_path(&self) -> PathBuf {
         self.pathname.join("HEAD")
     }
 
+    /*
+        Returns the file HEAD resolves to: the branch ref file when HEAD is
+        a symbolic ref, otherwise the HEAD file itself.
+    */
+    pub fn head_target(&self) -> io::Result<PathBuf> {
+        match Self::read_file(self.head_path())?.strip_prefix(SYMREF_PREFIX) {
+            Some(target) => Ok(self.pathname.join(target)),
+            None => Ok(self.head_path()),
+        }
+    }
+
+    /*
+        Returns the commit id HEAD points at, following a symbolic ref to
+        its branch. An empty string is returned when there is no commit yet.
+    */
     pub fn read_head(&self) -> io::Result<String> {
-        let head_path = self.head_path();
-        let path = head_path.as_path();
+        let contents = Self::read_file(self.head_path())?;
+        match contents.strip_prefix(SYMREF_PREFIX) {
+            Some(target) => self.read_ref(target),
+            None => Ok(contents),
+        }
+    }
+
+    /*
+        Appends an entry to the reflog of ref_name, for example "HEAD" or
+        "refs/heads/main", creating the log if needed. An empty old_oid
+        records the creation of the ref.
+    */
+    pub fn append_reflog(
+        &self,
+        ref_name: &str,
+        old_oid: &str,
+        new_oid: &str,
+        committer: &Author,
+        message: &str,
+    ) -> io::Result<()> {
+        let path = self.pathname.join("logs").join(ref_name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let old_oid = if old_oid.is_empty() {
+            NULL_OID
+        } else {
+            old_oid
+        };
+        // The message is a single line, a newline would start a new entry.
+        let message = message.lines().next().unwrap_or("");
+        let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(log, "{} {} {}\t{}", old_oid, new_oid, committer, message)
+    }
+
+    /*
+        Reads the reflog of ref_name, oldest entry first. A ref without a
+        log has no entries.
+    */
+    pub fn read_reflog(&self, ref_name: &str) -> io::Result<Vec<ReflogEntry>> {
+        let contents = Self::read_file(self.pathname.join("logs").join(ref_name))?;
+        contents
+            .lines()
+            .map(|line| {
+                ReflogEntry::parse(line).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("corrupt reflog entry for {}: {}", ref_name, line),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /*
+        Removes the newest entry of the reflog of ref_name, returning the
+        entries left, oldest first. A log left empty is deleted.
+    */
+    pub fn drop_reflog_entry(&self, ref_name: &str) -> io::Result<Vec<ReflogEntry>> {
+        let mut entries = self.read_reflog(ref_name)?;
+        entries.pop();
+        let path = self.pathname.join("logs").join(ref_name);
+        if entries.is_empty() {
+            return match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(entries),
+            };
+        }
+        let contents = Self::read_file(path.clone())?;
+        let kept = contents.lines().take(entries.len()).collect::<Vec<_>>();
+        fs::write(path, format!("{}\n", kept.join("\n")))?;
+        Ok(entries)
+    }
+
+    /*
+        Deletes the loose ref at ref_name, relative to the git directory,
+        along with its reflog. A missing ref is not an error.
+    */
+    pub fn delete_ref(&self, ref_name: &str) -> io::Result<()> {
+        for path in [
+            self.pathname.join(ref_name),
+            self.pathname.join("logs").join(ref_name),
+        ] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Resolves "<ref>@{n}", the value ref had n updates ago, from its
+        reflog. "@{0}" is the current value.
+    */
+    pub fn resolve_reflog(&self, rev: &str) -> io::Result<String> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a valid reflog revision {}", rev),
+            )
+        };
+        let (name, rest) = rev.split_once("@{").ok_or_else(invalid)?;
+        let n = rest
+            .strip_suffix('}')
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        let name = if name.is_empty() { "HEAD" } else { name };
+        let ref_name = match name {
+            "HEAD" => String::from("HEAD"),
+            name if name.starts_with("refs/") => name.to_string(),
+            branch => format!("refs/heads/{}", branch),
+        };
+
+        let entries = self.read_reflog(&ref_name)?;
+        if n >= entries.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("log for '{}' only has {} entries", name, entries.len()),
+            ));
+        }
+        Ok(entries[entries.len() - 1 - n].new_oid.clone())
+    }
+
+    /*
+        Lists every ref under refs/ as (name, commit id) pairs sorted by
+        name, for example ("refs/heads/main", "<hex>"). Refs in
+        packed-refs are included, a loose ref of the same name wins. A
+        symbolic ref such as refs/remotes/origin/HEAD lists the commit of
+        its target, and is left out when the target doesn't exist.
+    */
+    pub fn list_refs(&self) -> io::Result<Vec<(String, String)>> {
+        let mut refs = Vec::new();
+        let mut dirs = vec![self.pathname.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                // Lockfiles of refs being updated are not refs themselves.
+                if path.extension().is_some_and(|ext| ext == "lock") {
+                    continue;
+                }
+                let name = path
+                    .strip_prefix(&self.pathname)
+                    .unwrap()
+                    .display()
+                    .to_string();
+                refs.push((name, Self::read_file(path)?));
+            }
+        }
+        for (name, oid) in self.packed_refs()? {
+            if !refs.iter().any(|(loose, _)| *loose == name) {
+                refs.push((name, oid));
+            }
+        }
+        let targets = refs.clone();
+        refs.retain_mut(|(_, value)| match value.strip_prefix(SYMREF_PREFIX) {
+            Some(target) => match targets.iter().find(|(name, _)| name == target) {
+                Some((_, oid)) if !oid.starts_with(SYMREF_PREFIX) => {
+                    *value = oid.clone();
+                    true
+                }
+                _ => false,
+            },
+            None => true,
+        });
+        refs.sort();
+        Ok(refs)
+    }
+
+    /*
+        The (name, commit id) pairs of .git/packed-refs, in file order. The
+        header comment and the "^<oid>" lines peeling annotated tags are
+        skipped. A missing file has no refs.
+    */
+    pub fn packed_refs(&self) -> io::Result<Vec<(String, String)>> {
+        let contents = Self::read_file(self.pathname.join("packed-refs"))?;
+        contents
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('^'))
+            .map(|line| {
+                line.split_once(' ')
+                    .map(|(oid, name)| (name.to_string(), oid.to_string()))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("corrupt packed-refs line: {}", line),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    // The commit id packed-refs records for the full ref name, if any.
+    fn packed_ref(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(self
+            .packed_refs()?
+            .into_iter()
+            .find(|(packed, _)| packed == name)
+            .map(|(_, oid)| oid))
+    }
+
+    fn read_file(path: PathBuf) -> io::Result<String> {
+        let path = path.as_path();
         if path.exists() {
             let mut file = File::open(path)?;
             let mut contents = String::new();
@@ -71,3 +518,225 @@ impl Refs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::test_support::author;
+
+    const ONE: &str = "1111111111111111111111111111111111111111";
+    const TWO: &str = "2222222222222222222222222222222222222222";
+    const THREE: &str = "3333333333333333333333333333333333333333";
+
+    fn read_ref(refs: &Refs, name: &str) -> String {
+        Refs::read_file(refs.pathname.join(name)).unwrap()
+    }
+
+    fn refs() -> (TempDir, Refs) {
+        let dir = TempDir::new().unwrap();
+        let refs = Refs::new(dir.path().to_path_buf());
+        fs::write(refs.head_path(), "ref: refs/heads/main\n").unwrap();
+        (dir, refs)
+    }
+
+    #[test]
+    fn compare_and_swap_updates_matching_ref() {
+        let (_dir, refs) = refs();
+        refs.compare_and_swap("refs/heads/main", None, ONE).unwrap();
+        refs.compare_and_swap("refs/heads/main", Some(ONE), TWO)
+            .unwrap();
+        assert_eq!(read_ref(&refs, "refs/heads/main"), TWO);
+        assert!(!refs.pathname.join("refs/heads/main.lock").exists());
+    }
+
+    #[test]
+    fn compare_and_swap_rejects_stale_expectation() {
+        let (_dir, refs) = refs();
+        refs.compare_and_swap("refs/heads/main", None, ONE).unwrap();
+        let err = refs
+            .compare_and_swap("refs/heads/main", Some(TWO), TWO)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RefsError::Stale(ref name, Some(ref actual)) if name == "refs/heads/main" && actual == ONE
+        ));
+        assert_eq!(read_ref(&refs, "refs/heads/main"), ONE);
+        assert!(!refs.pathname.join("refs/heads/main.lock").exists());
+    }
+
+    #[test]
+    fn compare_and_swap_creates_only_when_absent() {
+        let (_dir, refs) = refs();
+        refs.compare_and_swap("refs/heads/topic", None, ONE)
+            .unwrap();
+        assert_eq!(read_ref(&refs, "refs/heads/topic"), ONE);
+        let err = refs
+            .compare_and_swap("refs/heads/topic", None, TWO)
+            .unwrap_err();
+        assert!(matches!(err, RefsError::Stale(_, Some(_))));
+
+        // Expecting a value for a ref that doesn't exist fails too.
+        let err = refs
+            .compare_and_swap("refs/heads/missing", Some(ONE), TWO)
+            .unwrap_err();
+        assert!(matches!(err, RefsError::Stale(_, None)));
+        assert!(!refs.pathname.join("refs/heads/missing").exists());
+    }
+
+    #[test]
+    fn update_head_swaps_the_current_branch() {
+        let (_dir, refs) = refs();
+        refs.update_head(None, ONE).unwrap();
+        assert_eq!(read_ref(&refs, "refs/heads/main"), ONE);
+        assert!(refs.update_head(None, TWO).is_err());
+        refs.update_head(Some(ONE), TWO).unwrap();
+        assert_eq!(refs.read_head().unwrap(), TWO);
+    }
+
+    #[test]
+    fn reflog_revisions_count_back_from_the_newest_entry() {
+        let (_dir, refs) = refs();
+        for (old, new) in [("", ONE), (ONE, TWO), (TWO, THREE)] {
+            refs.append_reflog("HEAD", old, new, &author(), "commit: change")
+                .unwrap();
+            refs.append_reflog("refs/heads/main", old, new, &author(), "commit: change")
+                .unwrap();
+        }
+        assert_eq!(refs.read_reflog("HEAD").unwrap()[0].old_oid, NULL_OID);
+
+        assert_eq!(refs.resolve_reflog("HEAD@{0}").unwrap(), THREE);
+        assert_eq!(refs.resolve_reflog("HEAD@{1}").unwrap(), TWO);
+        assert_eq!(refs.resolve_reflog("HEAD@{2}").unwrap(), ONE);
+        assert_eq!(refs.resolve_reflog("@{1}").unwrap(), TWO);
+        assert_eq!(refs.resolve_reflog("main@{2}").unwrap(), ONE);
+
+        let err = refs.resolve_reflog("HEAD@{3}").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "log for 'HEAD' only has 3 entries");
+        let err = refs.resolve_reflog("HEAD@{x}").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn packed_ref_is_the_current_value_for_compare_and_swap() {
+        let (_dir, refs) = refs();
+        fs::write(
+            refs.pathname.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n{} refs/heads/topic\n",
+                ONE
+            ),
+        )
+        .unwrap();
+
+        let err = refs.create_branch("topic", TWO).unwrap_err();
+        assert!(matches!(err, RefsError::Exists(ref name) if name == "topic"));
+        assert!(!refs.pathname.join("refs/heads/topic").exists());
+        assert!(refs
+            .compare_and_swap("refs/heads/topic", Some(TWO), THREE)
+            .is_err());
+
+        // Updating from the packed value writes a loose ref, which wins.
+        refs.compare_and_swap("refs/heads/topic", Some(ONE), TWO)
+            .unwrap();
+        assert_eq!(read_ref(&refs, "refs/heads/topic"), TWO);
+        assert_eq!(refs.list_branches().unwrap(), ["topic"]);
+    }
+
+    #[test]
+    fn packed_refs_are_read_when_there_is_no_loose_ref() {
+        let (_dir, refs) = refs();
+        fs::write(
+            refs.pathname.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n\
+                 {} refs/heads/main\n{} refs/tags/v1\n^{}\n",
+                ONE, TWO, THREE
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(refs.read_ref("refs/heads/main").unwrap(), ONE);
+        assert_eq!(refs.read_head().unwrap(), ONE);
+        assert_eq!(refs.lookup("main").unwrap().as_deref(), Some(ONE));
+        assert_eq!(refs.lookup("HEAD").unwrap().as_deref(), Some(ONE));
+        assert_eq!(refs.lookup("v1").unwrap().as_deref(), Some(TWO));
+        assert_eq!(refs.lookup("tags/v1").unwrap().as_deref(), Some(TWO));
+        assert_eq!(refs.lookup("v2").unwrap(), None);
+        assert_eq!(refs.read_ref("refs/heads/topic").unwrap(), "");
+
+        // A loose ref wins over its packed value.
+        refs.update_head(Some(ONE), THREE).unwrap();
+        assert_eq!(refs.read_head().unwrap(), THREE);
+        assert_eq!(refs.lookup("main").unwrap().as_deref(), Some(THREE));
+    }
+
+    #[test]
+    fn list_refs_merges_loose_and_packed_refs() {
+        let (_dir, refs) = refs();
+        fs::write(
+            refs.pathname.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n\
+                 {} refs/heads/main\n{} refs/tags/v1\n^{}\n",
+                ONE, TWO, THREE
+            ),
+        )
+        .unwrap();
+        refs.create_branch("topic", THREE).unwrap();
+        refs.compare_and_swap("refs/heads/main", Some(ONE), TWO)
+            .unwrap();
+        fs::create_dir_all(refs.pathname.join("refs/remotes/origin")).unwrap();
+        fs::write(
+            refs.pathname.join("refs/remotes/origin/HEAD"),
+            "ref: refs/heads/topic\n",
+        )
+        .unwrap();
+        fs::write(refs.pathname.join("refs/heads/next.lock"), ONE).unwrap();
+
+        let listed = refs.list_refs().unwrap();
+        let listed = listed
+            .iter()
+            .map(|(name, oid)| (name.as_str(), oid.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listed,
+            [
+                ("refs/heads/main", TWO),
+                ("refs/heads/topic", THREE),
+                ("refs/remotes/origin/HEAD", THREE),
+                ("refs/tags/v1", TWO),
+            ]
+        );
+    }
+
+    #[test]
+    fn create_branch_refuses_invalid_and_existing_names() {
+        let (_dir, refs) = refs();
+        refs.create_branch("topic", ONE).unwrap();
+        assert_eq!(read_ref(&refs, "refs/heads/topic"), ONE);
+        assert!(matches!(
+            refs.create_branch("topic", TWO),
+            Err(RefsError::Exists(_))
+        ));
+        assert_eq!(read_ref(&refs, "refs/heads/topic"), ONE);
+        assert!(matches!(
+            refs.create_branch("bad..name", ONE),
+            Err(RefsError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn list_branches_and_current_branch() {
+        let (_dir, refs) = refs();
+        refs.create_branch("main", ONE).unwrap();
+        refs.create_branch("feature/x", TWO).unwrap();
+        assert_eq!(refs.list_branches().unwrap(), ["feature/x", "main"]);
+        assert_eq!(refs.current_branch().unwrap().as_deref(), Some("main"));
+        fs::write(refs.head_path(), format!("{}\n", ONE)).unwrap();
+        assert_eq!(refs.current_branch().unwrap(), None);
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    env, fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::config::Config;
+use crate::database::Database;
+use crate::filter::Filter;
+use crate::index::Index;
+use crate::refs::Refs;
+use crate::status;
+use crate::workspace::Workspace;
+
+/*
+    The locations of a repository found from some directory inside it.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repository {
+    // The top of the working tree, or the repository directory itself
+    // when the repository is bare.
+    pub root: PathBuf,
+    pub git_dir: PathBuf,
+    // The directory discovery started from, relative to root. Empty when
+    // started at the root.
+    pub prefix: PathBuf,
+    pub bare: bool,
+}
+
+impl Repository {
+    /*
+        Finds the repository for start, where a command was run. GIT_DIR
+        and GIT_WORK_TREE, when set, override the locations it would
+        otherwise be found at.
+    */
+    pub fn discover(start: PathBuf) -> io::Result<Self> {
+        let git_dir = env::var_os("GIT_DIR").map(PathBuf::from);
+        let work_tree = env::var_os("GIT_WORK_TREE").map(PathBuf::from);
+        Self::locate(start, git_dir, work_tree)
+    }
+
+    /*
+        Finds the repository for start with explicit overrides, relative
+        to start. Given git_dir, it is used as the repository without any
+        search and its working tree is start, unless it is bare. Otherwise
+        the repository is searched for from start. A given work_tree then
+        replaces the working tree either way.
+    */
+    pub fn locate(
+        start: PathBuf,
+        git_dir: Option<PathBuf>,
+        work_tree: Option<PathBuf>,
+    ) -> io::Result<Self> {
+        let resolve = |path: PathBuf| {
+            let path = start.join(path);
+            fs::canonicalize(&path).unwrap_or(path)
+        };
+        let mut repo = match git_dir.map(resolve) {
+            Some(git_dir) => {
+                if !git_dir.join("HEAD").is_file() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("not a git repository: '{}'", git_dir.display()),
+                    ));
+                }
+                let bare = is_bare_dir(&git_dir);
+                Repository {
+                    root: if bare { git_dir.clone() } else { start.clone() },
+                    git_dir,
+                    prefix: PathBuf::new(),
+                    bare,
+                }
+            }
+            None => Self::search(&start)?,
+        };
+        if let Some(work_tree) = work_tree.map(resolve) {
+            repo.root = work_tree;
+            repo.bare = false;
+        }
+        repo.prefix = start
+            .strip_prefix(&repo.root)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(repo)
+    }
+
+    /*
+        Finds the repository containing start by walking up its ancestors,
+        stopping after the filesystem root. A directory is the root when it
+        holds .git or is itself a bare repository.
+    */
+    fn search(start: &Path) -> io::Result<Self> {
+        for dir in start.ancestors() {
+            let bare = if dir.join(".git").exists() {
+                false
+            } else if is_bare_dir(dir) {
+                true
+            } else {
+                continue;
+            };
+            let git_dir = if bare {
+                dir.to_path_buf()
+            } else {
+                dir.join(".git")
+            };
+            return Ok(Repository {
+                root: dir.to_path_buf(),
+                git_dir,
+                prefix: start.strip_prefix(dir).unwrap().to_path_buf(),
+                bare,
+            });
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "not a git repository (or any of the parent directories): .git",
+        ))
+    }
+
+    pub fn db_path(&self) -> PathBuf {
+        self.git_dir.join("objects")
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.git_dir.join("index")
+    }
+
+    pub fn database(&self) -> Database {
+        Database::new(self.db_path())
+    }
+
+    pub fn refs(&self) -> Refs {
+        Refs::new(self.git_dir.clone())
+    }
+
+    pub fn workspace(&self) -> io::Result<Workspace> {
+        let config = Config::load(&self.git_dir)?;
+        Ok(Workspace::open(self.root.clone())?.with_filter(Filter::new(&config)))
+    }
+
+    /*
+        Whether the working tree and index match HEAD with no untracked
+        files, as a guard before commands that would overwrite them.
+    */
+    pub fn is_clean(&self) -> io::Result<bool> {
+        let mut index = Index::load(self.index_path())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        status::is_clean(
+            &self.workspace()?,
+            &self.database(),
+            &mut index,
+            &self.refs(),
+        )
+    }
+
+    /*
+        Turns a path given on the command line, relative to where the
+        command was run, into a path relative to the root, resolving "."
+        and ".." lexically. The root itself is ".".
+    */
+    pub fn resolve_pathspec(&self, pathspec: &str) -> PathBuf {
+        let mut resolved = PathBuf::new();
+        for component in self.prefix.join(pathspec).components() {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => resolved.push(name),
+                _ => {}
+            }
+        }
+        if resolved.as_os_str().is_empty() {
+            resolved.push(".");
+        }
+        resolved
+    }
+}
+
+// A bare repository holds HEAD and objects directly and sets core.bare.
+fn is_bare_dir(dir: &Path) -> bool {
+    if !dir.join("HEAD").is_file() || !dir.join("objects").is_dir() {
+        return false;
+    }
+    let mut config = Config::default();
+    match config.read_file(&dir.join("config")) {
+        Ok(()) => config.get_bool("core.bare").unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::test_support::TestRepo;
+
+    #[test]
+    fn discovers_from_the_root() {
+        let repo = TestRepo::new();
+        let found = Repository::locate(repo.root().to_path_buf(), None, None).unwrap();
+        assert_eq!(
+            found,
+            Repository {
+                root: repo.root().to_path_buf(),
+                git_dir: repo.git_dir(),
+                prefix: PathBuf::new(),
+                bare: false,
+            }
+        );
+        assert_eq!(found.resolve_pathspec("."), Path::new("."));
+    }
+
+    #[test]
+    fn discovers_from_a_nested_directory() {
+        let repo = TestRepo::new();
+        let nested = repo.root().join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+        let found = Repository::locate(nested, None, None).unwrap();
+        assert_eq!(found.root, repo.root());
+        assert_eq!(found.git_dir, repo.git_dir());
+        assert_eq!(found.prefix, Path::new("src/bin"));
+        assert_eq!(
+            found.resolve_pathspec("main.rs"),
+            Path::new("src/bin/main.rs")
+        );
+        assert_eq!(found.resolve_pathspec("../lib.rs"), Path::new("src/lib.rs"));
+        assert_eq!(found.resolve_pathspec("../.."), Path::new("."));
+    }
+
+    #[test]
+    fn fails_outside_any_repository() {
+        let dir = TempDir::new().unwrap();
+        let err = Repository::locate(dir.path().join("missing"), None, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "not a git repository (or any of the parent directories): .git"
+        );
+    }
+
+    #[test]
+    fn committed_tree_is_clean() {
+        let repo = TestRepo::new();
+        repo.write("file.txt", "content\n");
+        repo.stage(&["file.txt"]);
+        repo.commit("initial");
+        let found = Repository::locate(repo.root().to_path_buf(), None, None).unwrap();
+        assert!(found.is_clean().unwrap());
+    }
+
+    #[test]
+    fn one_untracked_file_is_not_clean() {
+        let repo = TestRepo::new();
+        repo.write("file.txt", "content\n");
+        repo.stage(&["file.txt"]);
+        repo.commit("initial");
+        repo.write("untracked.txt", "new\n");
+        let found = Repository::locate(repo.root().to_path_buf(), None, None).unwrap();
+        assert!(!found.is_clean().unwrap());
+    }
+
+    #[test]
+    fn git_dir_and_work_tree_overrides_replace_discovery() {
+        let store = TestRepo::new();
+        store.write("stored.txt", "stored\n");
+        store.stage(&["stored.txt"]);
+        let head = store.commit("stored");
+        let work = TempDir::new().unwrap();
+        let start = work.path().join("sub");
+        fs::create_dir_all(&start).unwrap();
+
+        let found = Repository::locate(
+            start.clone(),
+            Some(store.git_dir()),
+            Some(work.path().to_path_buf()),
+        )
+        .unwrap();
+        assert_eq!(found.git_dir, store.git_dir());
+        assert_eq!(found.root, work.path());
+        assert_eq!(found.prefix, Path::new("sub"));
+        assert!(!found.bare);
+        // Objects and refs come from the given repository, files from the
+        // given working tree, where stored.txt is missing.
+        assert_eq!(found.refs().read_head().unwrap(), head);
+        assert!(found.database().exists(&head));
+        assert!(!found.is_clean().unwrap());
+
+        // Without a working tree the directory the command ran in is used.
+        let found = Repository::locate(start.clone(), Some(store.git_dir()), None).unwrap();
+        assert_eq!((found.root, found.prefix), (start, PathBuf::new()));
+    }
+
+    #[test]
+    fn git_dir_override_must_be_a_repository() {
+        let dir = TempDir::new().unwrap();
+        let err = Repository::locate(dir.path().to_path_buf(), Some(PathBuf::from("nope")), None)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "not a git repository: '{}'",
+                dir.path().join("nope").display()
+            )
+        );
+    }
+}
@@ -0,0 +1,91 @@
+/*
+    A path recorded in the REUC index extension: the stages its merge
+    conflict had before it was resolved, kept so the resolution can be
+    undone and the conflict recreated. Stages are the base, ours and
+    theirs, and a stage the conflict lacked has mode 0 and no object id.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveUndo {
+    pub path: String,
+    pub modes: [u32; 3],
+    // Raw object ids, empty for a missing stage.
+    pub oids: [Vec<u8>; 3],
+}
+
+impl ResolveUndo {
+    /*
+        Parses the data of a REUC extension. Each path is written as
+        "<path>\0", the three modes in octal each ending in "\0", and then
+        the 20 byte id of every stage whose mode isn't zero.
+    */
+    pub fn parse(data: &[u8]) -> Option<Vec<Self>> {
+        let mut records = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (path, after) = split_nul(rest)?;
+            let path = String::from_utf8(path.to_vec()).ok()?;
+            rest = after;
+            let mut modes = [0; 3];
+            for mode in &mut modes {
+                let (octal, after) = split_nul(rest)?;
+                *mode = u32::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok()?;
+                rest = after;
+            }
+            let mut oids: [Vec<u8>; 3] = Default::default();
+            for (oid, mode) in oids.iter_mut().zip(modes) {
+                if mode != 0 {
+                    *oid = rest.get(..20)?.to_vec();
+                    rest = &rest[20..];
+                }
+            }
+            records.push(ResolveUndo { path, modes, oids });
+        }
+        Some(records)
+    }
+
+    // Serializes records as the extension data parse reads.
+    pub fn to_bytes(records: &[Self]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for record in records {
+            out.extend_from_slice(record.path.as_bytes());
+            out.push(0);
+            for mode in record.modes {
+                out.extend_from_slice(format!("{:o}", mode).as_bytes());
+                out.push(0);
+            }
+            for (oid, mode) in record.oids.iter().zip(record.modes) {
+                if mode != 0 {
+                    out.extend_from_slice(oid);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn split_nul(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let nul = data.iter().position(|&b| b == 0)?;
+    Some((&data[..nul], &data[nul + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_stages_are_written_without_an_id() {
+        let record = ResolveUndo {
+            path: String::from("added.txt"),
+            modes: [0, 0o100644, 0o100644],
+            oids: [Vec::new(), vec![0xaa; 20], vec![0xbb; 20]],
+        };
+        let data = ResolveUndo::to_bytes(std::slice::from_ref(&record));
+        let mut expected = b"added.txt\x000\x00100644\x00100644\x00".to_vec();
+        expected.extend([0xaa; 20]);
+        expected.extend([0xbb; 20]);
+        assert_eq!(data, expected);
+        assert_eq!(ResolveUndo::parse(&data).unwrap(), [record]);
+        // A record cut off in its ids doesn't parse.
+        assert_eq!(ResolveUndo::parse(&data[..data.len() - 1]), None);
+    }
+}
@@ -0,0 +1,172 @@
+use std::io;
+
+use crate::checkout;
+use crate::commit;
+use crate::database::{Database, EMPTY_TREE_OID};
+use crate::diff;
+use crate::index::Index;
+use crate::merge;
+use crate::refs::Refs;
+use crate::tree;
+use crate::utils;
+use crate::workspace::Workspace;
+
+/*
+    Undoes the changes the commit with hex id target made to its first
+    parent, on top of HEAD: the HEAD files are merged with the parent's,
+    taking the target's files as the base. The workspace and the index are
+    updated, the index is not written, and the message for the revert
+    commit is returned. Nothing is touched when a file conflicts, local
+    changes would be overwritten or the index already differs from HEAD,
+    since the revert commit is written from the index.
+*/
+pub fn revert(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+    target: &str,
+) -> io::Result<String> {
+    let (_, data) = database.read_object(target)?;
+    let reverted = commit::parse_commit(&data)?;
+    let subject = reverted.message.lines().next().unwrap_or("");
+    let abbrev = utils::oid_abbrev(target, 7);
+    if reverted.parents.len() > 1 {
+        return Err(io::Error::other(format!(
+            "commit {} is a merge, which can't be reverted",
+            target
+        )));
+    }
+
+    // A root commit is reverted against an empty tree.
+    let base = tree::flatten_tree(database, &reverted.tree)?;
+    let theirs = match reverted.parents.first() {
+        Some(parent) => {
+            let (_, data) = database.read_object(parent)?;
+            tree::flatten_tree(database, &commit::parse_commit(&data)?.tree)?
+        }
+        None => Default::default(),
+    };
+    let head = refs.read_head()?;
+    let head_tree = if head.is_empty() {
+        String::from(EMPTY_TREE_OID)
+    } else {
+        let (_, data) = database.read_object(&head)?;
+        commit::parse_commit(&data)?.tree
+    };
+    if index.tree_oid_dry() != head_tree {
+        return Err(io::Error::other(
+            "your local changes would be overwritten by revert.\n\
+             hint: commit your changes or stash them to proceed.",
+        ));
+    }
+
+    let ours = tree::flatten_head_tree(database, refs)?;
+    let (merged, conflicts) = merge::merge_trees(database, &base, &ours, &theirs)?;
+    if !conflicts.is_empty() {
+        let mut message = format!("could not revert {}... {}", abbrev, subject);
+        for path in conflicts {
+            message.push_str(&format!("\nCONFLICT (content): Merge conflict in {}", path));
+        }
+        return Err(io::Error::other(message));
+    }
+
+    let changes = diff::diff_file_maps(&ours, &merged);
+    checkout::check_overwrites(
+        workspace, database, index, refs, &changes, "revert", "revert",
+    )?;
+    checkout::apply_changes(workspace, database, index, changes)?;
+    Ok(format!(
+        "Revert \"{}\"\n\nThis reverts commit {}.\n",
+        subject, target
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::TestRepo;
+
+    fn revert_in(repo: &TestRepo, target: &str) -> io::Result<String> {
+        let mut index = repo.index();
+        let message = revert(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+            target,
+        )?;
+        assert!(index.write_updates().unwrap());
+        Ok(message)
+    }
+
+    #[test]
+    fn reverting_an_added_file_removes_it() {
+        let repo = TestRepo::new();
+        repo.write("kept.txt", "kept\n");
+        repo.stage(&["kept.txt"]);
+        repo.commit("initial");
+        repo.write("added.txt", "added\n");
+        repo.stage(&["added.txt"]);
+        let added = repo.commit("Add a file");
+
+        let message = revert_in(&repo, &added).unwrap();
+        assert_eq!(
+            message,
+            format!("Revert \"Add a file\"\n\nThis reverts commit {}.\n", added)
+        );
+        assert!(!repo.root().join("added.txt").exists());
+        repo.commit(&message);
+        let files = tree::flatten_head_tree(&repo.database(), &repo.refs()).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["kept.txt"]);
+        assert!(repo.is_clean());
+    }
+
+    #[test]
+    fn staged_changes_block_the_revert() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        repo.stage(&["a.txt"]);
+        let target = repo.commit("Add a");
+        repo.write("b.txt", "b\n");
+        repo.stage(&["b.txt"]);
+
+        // The staged b.txt would otherwise end up in the revert commit.
+        let err = revert_in(&repo, &target).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("your local changes would be overwritten by revert."));
+        assert!(repo.root().join("a.txt").exists());
+        assert!(repo.index().get("a.txt").is_some());
+    }
+
+    #[test]
+    fn conflicting_revert_changes_nothing() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "one\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("initial");
+        repo.write("a.txt", "two\n");
+        repo.stage(&["a.txt"]);
+        let target = repo.commit("Change to two");
+        repo.write("a.txt", "three\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("Change to three");
+
+        let err = revert_in(&repo, &target).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "could not revert {}... Change to two\n\
+                 CONFLICT (content): Merge conflict in a.txt",
+                utils::oid_abbrev(&target, 7)
+            )
+        );
+        assert_eq!(
+            std::fs::read_to_string(repo.root().join("a.txt")).unwrap(),
+            "three\n"
+        );
+        assert!(repo.is_clean());
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+
+use crate::commit;
+use crate::database::Database;
+use crate::refs::Refs;
+
+/*
+    Resolves a revision to a full hex id. The revision is HEAD, a reflog
+    entry such as HEAD@{1}, a branch or tag name, or a full or abbreviated
+    object id, optionally followed by ancestry suffixes as understood by
+    resolve_ancestry.
+*/
+pub fn resolve_revision(database: &Database, refs: &Refs, rev: &str) -> io::Result<String> {
+    let (base, suffix) = rev.split_at(rev.find(['^', '~']).unwrap_or(rev.len()));
+    let oid = resolve_base(database, refs, base)?;
+    resolve_ancestry(database, &oid, suffix)
+        .map_err(|err| io::Error::new(err.kind(), format!("unknown revision {}: {}", rev, err)))
+}
+
+/*
+    Walks from the commit oid through a chain of ancestry suffixes: "^n"
+    selects the n-th parent, "~n" follows first parents n times, and both
+    default to 1 without a number. "^0" and "~0" are the commit itself.
+    Errors when a commit doesn't have the parent asked for.
+*/
+pub fn resolve_ancestry(database: &Database, oid: &str, suffixes: &str) -> io::Result<String> {
+    let mut oid = oid.to_string();
+    let mut rest = suffixes;
+    while let Some(op) = rest.chars().next() {
+        rest = &rest[op.len_utf8()..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n = match &rest[..digits] {
+            "" => 1,
+            n => n.parse::<usize>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("bad number {}", n))
+            })?,
+        };
+        rest = &rest[digits..];
+        match op {
+            // Still has to name a commit.
+            '^' if n == 0 => {
+                parents(database, &oid)?;
+            }
+            '^' => oid = nth_parent(database, &oid, n)?,
+            '~' => {
+                for _ in 0..n {
+                    oid = nth_parent(database, &oid, 1)?;
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unexpected {} in ancestry", op),
+                ))
+            }
+        }
+    }
+    Ok(oid)
+}
+
+// A revision without ancestry suffixes.
+fn resolve_base(database: &Database, refs: &Refs, rev: &str) -> io::Result<String> {
+    if rev.contains("@{") {
+        return refs.resolve_reflog(rev);
+    }
+    if rev == "HEAD" {
+        let head = refs.read_head()?;
+        if head.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "HEAD does not point at a commit yet",
+            ));
+        }
+        return Ok(head);
+    }
+    // A full object id wins over a ref of the same name, a ref over an
+    // abbreviated object id.
+    let is_hex = !rev.is_empty() && rev.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex && rev.len() == 40 && database.exists(&rev.to_ascii_lowercase()) {
+        return Ok(rev.to_ascii_lowercase());
+    }
+    if let Some(oid) = refs.lookup(rev)? {
+        return Ok(oid);
+    }
+    if !is_hex {
+        return Err(unknown_revision(rev));
+    }
+    database.expand_oid(rev)
+}
+
+// The parents of a commit, erroring for any other kind of object.
+fn parents(database: &Database, oid: &str) -> io::Result<Vec<String>> {
+    let (kind, data) = database.read_object(oid)?;
+    if kind != "commit" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is a {}, not a commit", oid, kind),
+        ));
+    }
+    Ok(commit::parse_commit(&data)?.parents)
+}
+
+// Parents are numbered from 1, as in "^2" for the second parent of a merge.
+fn nth_parent(database: &Database, oid: &str, n: usize) -> io::Result<String> {
+    let parents = parents(database, oid)?;
+    parents.get(n - 1).cloned().ok_or_else(|| {
+        let message = match parents.len() {
+            0 => format!("commit {} has no parent", oid),
+            count => format!("commit {} has only {} parent(s)", oid, count),
+        };
+        io::Error::new(io::ErrorKind::NotFound, message)
+    })
+}
+
+fn unknown_revision(rev: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("unknown revision {}", rev))
+}
+
+/*
+    Resolves a tree-ish to the hex id of a tree, accepting a commit in
+    place of its tree.
+*/
+pub fn resolve_tree(database: &Database, refs: &Refs, rev: &str) -> io::Result<String> {
+    let oid = resolve_revision(database, refs, rev)?;
+    let (kind, data) = database.read_object(&oid)?;
+    match kind.as_str() {
+        "commit" => commit::parse_tree_oid(&data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "commit has no tree")),
+        "tree" => Ok(oid),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a tree", rev),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::test_support::TestRepo;
+    use crate::utils;
+
+    // Three commits of a.txt, returning their ids oldest first.
+    fn history(repo: &TestRepo) -> Vec<String> {
+        ["one", "two", "three"]
+            .iter()
+            .map(|message| {
+                repo.write("a.txt", message);
+                repo.stage(&["a.txt"]);
+                repo.commit(message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolve_revision_accepts_names_ids_and_ancestry() {
+        let repo = TestRepo::new();
+        let commits = history(&repo);
+        let (database, refs) = (repo.database(), repo.refs());
+        refs.create_branch("topic", &commits[1]).unwrap();
+        let resolve = |rev: &str| resolve_revision(&database, &refs, rev).unwrap();
+
+        assert_eq!(resolve("HEAD"), commits[2]);
+        assert_eq!(resolve("main"), commits[2]);
+        assert_eq!(resolve("topic"), commits[1]);
+        assert_eq!(resolve("refs/heads/topic"), commits[1]);
+        assert_eq!(resolve(&commits[0]), commits[0]);
+        assert_eq!(resolve(&commits[0][..7]), commits[0]);
+        assert_eq!(resolve(&commits[0][..7].to_uppercase()), commits[0]);
+        assert_eq!(resolve("HEAD~2"), commits[0]);
+        assert_eq!(resolve("topic^"), commits[0]);
+
+        // Packed branches resolve like loose ones.
+        fs::write(
+            repo.git_dir().join("packed-refs"),
+            format!("{} refs/heads/packed\n", commits[0]),
+        )
+        .unwrap();
+        assert_eq!(resolve("packed"), commits[0]);
+    }
+
+    #[test]
+    fn resolve_revision_rejects_unknown_revisions() {
+        let repo = TestRepo::new();
+        let (database, refs) = (repo.database(), repo.refs());
+        let err = resolve_revision(&database, &refs, "HEAD").unwrap_err();
+        assert_eq!(err.to_string(), "HEAD does not point at a commit yet");
+
+        history(&repo);
+        let err = resolve_revision(&database, &refs, "nope").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "unknown revision nope");
+        assert!(resolve_revision(&database, &refs, "0000000").is_err());
+        let err = resolve_revision(&database, &refs, "HEAD~3").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("unknown revision HEAD~3: commit "));
+        assert!(err.to_string().ends_with(" has no parent"));
+    }
+
+    #[test]
+    fn resolve_ancestry_follows_first_and_nth_parents() {
+        let repo = TestRepo::new();
+        let commits = history(&repo);
+        let database = repo.database();
+        // A merge of the tip with its grandparent, as second parent.
+        let (_, tip) = database.read_object(&commits[2]).unwrap();
+        let tree = commit::parse_commit(&tip).unwrap().tree;
+        let mut merge = commit::Commit::new(
+            commits[2].clone(),
+            String::new(),
+            crate::test_support::author(),
+            String::from("merge\n"),
+        );
+        merge.tree = tree;
+        merge.parents.push(commits[0].clone());
+        database.store(&mut merge).unwrap();
+        let merge = utils::u8_to_hex_str(merge.object_id.into_bytes());
+        let walk = |suffixes: &str| resolve_ancestry(&database, &merge, suffixes).unwrap();
+
+        assert_eq!(walk(""), merge);
+        assert_eq!(walk("~0"), merge);
+        assert_eq!(walk("^0"), merge);
+        assert_eq!(walk("^"), commits[2]);
+        assert_eq!(walk("^1"), commits[2]);
+        assert_eq!(walk("~"), commits[2]);
+        assert_eq!(walk("~2"), commits[1]);
+        assert_eq!(walk("^2"), commits[0]);
+        assert_eq!(walk("~1~2"), commits[0]);
+
+        let err = resolve_ancestry(&database, &merge, "^3").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            format!("commit {} has only 2 parent(s)", merge)
+        );
+        let err = resolve_ancestry(&database, &merge, "~4").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("commit {} has no parent", commits[0])
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::io;
+
+use crate::author::DateFormat;
+use crate::commit;
+use crate::database::Database;
+use crate::diff;
+use crate::utils;
+
+/*
+    Formats a commit with its metadata followed by the patch it introduced
+    against its first parent, or against nothing for a root commit. The
+    author date is formatted according to date_format.
+*/
+pub fn show(database: &Database, oid: &str, date_format: DateFormat) -> io::Result<String> {
+    let (kind, data) = database.read_object(oid)?;
+    if kind != "commit" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("object {} is a {}, not a commit", oid, kind),
+        ));
+    }
+    let commit = commit::parse_commit(&data)?;
+
+    let mut out = format!("commit {}\n", oid);
+    if commit.parents.len() > 1 {
+        let abbrevs = commit
+            .parents
+            .iter()
+            .map(|parent| utils::oid_abbrev(parent, 7))
+            .collect::<Vec<&str>>();
+        out.push_str(&format!("Merge: {}\n", abbrevs.join(" ")));
+    }
+    out.push_str(&format!(
+        "Author: {} <{}>\n",
+        commit.author.name, commit.author.email
+    ));
+    out.push_str(&format!("Date:   {}\n", date_format.format(&commit.author)));
+    out.push('\n');
+    for line in commit.message.lines() {
+        out.push_str(&format!("    {}\n", line));
+    }
+
+    // Diff against the tree of the first parent.
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(commit::parse_commit(&database.read_object(parent)?.1)?.tree),
+        None => None,
+    };
+    let changes = diff::diff_trees(database, parent_tree.as_deref(), Some(&commit.tree))?;
+    if !changes.is_empty() {
+        out.push('\n');
+        out.push_str(&diff::format_tree_changes(database, &changes)?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::TestRepo;
+
+    #[test]
+    fn show_root_commit_with_new_file() {
+        let repo = TestRepo::new();
+        repo.write("hello.txt", "hello\n");
+        repo.stage(&["hello.txt"]);
+        let oid = repo.commit("Add hello");
+
+        let out = show(&repo.database(), &oid, DateFormat::Default).unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "commit {}\n\
+                 Author: A U Thor <author@example.com>\n\
+                 Date:   Tue Nov 14 22:13:20 2023 +0000\n\
+                 \n    Add hello\n\
+                 \n\
+                 diff --git a/hello.txt b/hello.txt\n\
+                 new file mode 100644\n\
+                 index 0000000..ce01362\n\
+                 --- /dev/null\n\
+                 +++ b/hello.txt\n\
+                 @@ -0,0 +1 @@\n\
+                 +hello\n",
+                oid
+            )
+        );
+    }
+}
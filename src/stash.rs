@@ -0,0 +1,304 @@
+use std::{io, path::Path};
+
+use crate::author::Author;
+use crate::blob::Blob;
+use crate::checkout;
+use crate::commit::{self, Commit};
+use crate::database::Database;
+use crate::diff::TreeChange;
+use crate::entry;
+use crate::hash;
+use crate::index::{Entry, Index};
+use crate::merge;
+use crate::refs::Refs;
+use crate::status::{self, Change};
+use crate::tree::{self, Tree};
+use crate::utils;
+use crate::workspace::Workspace;
+
+pub const STASH_REF: &str = "refs/stash";
+
+/*
+    Saves the staged and unstaged changes to tracked files as a stash
+    commit on refs/stash, then resets them to HEAD. As in git, the stash
+    commit records the working tree files and has HEAD and a commit of the
+    index as its parents. Untracked files are left alone. Returns the
+    message of the stash, or None when there was nothing to save. The
+    index is updated but not written.
+*/
+pub fn save(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+    author: &Author,
+    committer: &Author,
+) -> io::Result<Option<String>> {
+    let head = refs.read_head()?;
+    if head.is_empty() {
+        return Err(io::Error::other("you do not have the initial commit yet"));
+    }
+    let report = status::status(workspace, database, index, refs)?;
+    if report.staged.is_empty() && report.modified.is_empty() && report.deleted.is_empty() {
+        return Ok(None);
+    }
+
+    let (_, data) = database.read_object(&head)?;
+    let subject = commit::parse_commit(&data)?
+        .message
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_string();
+    let branch = refs
+        .current_branch()?
+        .unwrap_or_else(|| String::from("(no branch)"));
+    let on = format!("{}: {} {}", branch, utils::oid_abbrev(&head, 7), subject);
+    let store_commit = |tree: &Tree, parents: Vec<String>, message: String| {
+        let mut commit = Commit::new(
+            String::new(),
+            tree.object_id.clone(),
+            author.clone(),
+            message,
+        );
+        commit.parents = parents;
+        commit.committer = committer.clone();
+        database.store(&mut commit)?;
+        Ok::<_, io::Error>(utils::u8_to_hex_str(commit.object_id.into_bytes()))
+    };
+
+    let mut index_tree = Tree::from_index(index);
+    index_tree.store_tree(database);
+    let index_commit = store_commit(
+        &index_tree,
+        vec![head.clone()],
+        format!("index on {}\n", on),
+    )?;
+
+    // The working tree version of every tracked file.
+    let mut files = Vec::new();
+    for entry in index.each_entry() {
+        if entry.intent_to_add() || report.deleted.iter().any(|path| path == entry.path()) {
+            continue;
+        }
+        if !report.modified.iter().any(|path| path == entry.path()) {
+            files.push(entry::Entry::from_index(&entry));
+            continue;
+        }
+        let path = Path::new(entry.path());
+        let data = String::from_utf8(workspace.read_blob(path)?).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", entry.path(), err),
+            )
+        })?;
+        let mut blob = Blob::new(&data);
+        database.store(&mut blob)?;
+        let stat = workspace.symlink_metadata(path)?;
+        let fresh = Entry::from_stat(
+            entry.path(),
+            hash::raw_oid(blob.object_id.as_bytes())?,
+            &stat,
+        );
+        files.push(entry::Entry::from_index(&fresh));
+    }
+    let mut work_tree = Tree::build(files);
+    work_tree.store_tree(database);
+    let message = format!("WIP on {}", on);
+    let stash = store_commit(
+        &work_tree,
+        vec![head, index_commit],
+        format!("{}\n", message),
+    )?;
+
+    let previous = refs.read_ref(STASH_REF)?;
+    let expected = Some(previous.as_str()).filter(|previous| !previous.is_empty());
+    refs.compare_and_swap(STASH_REF, expected, &stash)
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    refs.append_reflog(STASH_REF, &previous, &stash, committer, &message)?;
+
+    // Every changed path goes back to its HEAD version.
+    let head_files = tree::flatten_head_tree(database, refs)?;
+    let mut paths = report.modified.clone();
+    paths.extend(report.deleted.iter().cloned());
+    for (path, change) in &report.staged {
+        paths.push(path.clone());
+        if let Change::Renamed(old_path) = change {
+            paths.push(old_path.clone());
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    let changes = paths
+        .into_iter()
+        .map(|path| TreeChange {
+            new: head_files.get(&path).cloned(),
+            path,
+            old: None,
+            old_path: None,
+        })
+        .collect();
+    checkout::apply_changes(workspace, database, index, changes)?;
+    Ok(Some(message))
+}
+
+/*
+    Applies the newest stash on top of HEAD and drops it from refs/stash,
+    returning its hex id. The stashed files are merged into the working
+    tree as changes against the commit they were saved on, and only files
+    new to HEAD are staged, as git stash pop does without --index. Nothing
+    is touched when a file conflicts or local changes would be
+    overwritten. The index is updated but not written.
+*/
+pub fn pop(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+) -> io::Result<String> {
+    let stash = refs.read_ref(STASH_REF)?;
+    if stash.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No stash entries found.",
+        ));
+    }
+    let (_, data) = database.read_object(&stash)?;
+    let saved = commit::parse_commit(&data)?;
+    let Some(base) = saved.parents.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a stash commit", stash),
+        ));
+    };
+    let (_, data) = database.read_object(base)?;
+    let base_files = tree::flatten_tree(database, &commit::parse_commit(&data)?.tree)?;
+    let stashed_files = tree::flatten_tree(database, &saved.tree)?;
+    let head_files = tree::flatten_head_tree(database, refs)?;
+
+    let (merged, conflicts) =
+        merge::merge_trees(database, &base_files, &head_files, &stashed_files)?;
+    if !conflicts.is_empty() {
+        let mut message = String::from("could not apply the stash");
+        for path in conflicts {
+            message.push_str(&format!("\nCONFLICT (content): Merge conflict in {}", path));
+        }
+        return Err(io::Error::other(message));
+    }
+    let changes = crate::diff::diff_file_maps(&head_files, &merged);
+    checkout::check_overwrites(
+        workspace,
+        database,
+        index,
+        refs,
+        &changes,
+        "stash pop",
+        "pop the stash",
+    )?;
+
+    // Changes to files HEAD has stay unstaged.
+    let restaged = changes
+        .iter()
+        .filter_map(|change| Some((change.path.clone(), change.old.clone()?)))
+        .collect::<Vec<_>>();
+    checkout::apply_changes(workspace, database, index, changes)?;
+    for (path, file) in restaged {
+        let mode = u32::from_str_radix(&file.mode, 8).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad mode {} for {}", file.mode, path),
+            )
+        })?;
+        index.add_entry(Entry::from_tree(
+            &path,
+            mode,
+            hash::raw_oid(&utils::hex_to_u8(&file.object_id).unwrap())?,
+        ));
+    }
+
+    let remaining = refs.drop_reflog_entry(STASH_REF)?;
+    match remaining.last() {
+        Some(previous) => refs
+            .compare_and_swap(STASH_REF, Some(&stash), &previous.new_oid)
+            .map_err(|err| io::Error::other(err.to_string()))?,
+        None => refs.delete_ref(STASH_REF)?,
+    }
+    Ok(stash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    use crate::test_support::{author, TestRepo};
+
+    #[test]
+    fn pop_restores_what_save_stashed() {
+        let repo = TestRepo::new();
+        repo.write("file.txt", "original\n");
+        repo.stage(&["file.txt"]);
+        repo.commit("initial");
+        repo.write("file.txt", "modified\n");
+        assert!(!repo.is_clean());
+
+        let mut index = repo.index();
+        let message = save(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+            &author(),
+            &author(),
+        )
+        .unwrap();
+        index.write_updates().unwrap();
+        assert!(message.is_some());
+        assert!(repo.is_clean());
+        assert_eq!(
+            fs::read_to_string(repo.root().join("file.txt")).unwrap(),
+            "original\n"
+        );
+        let stash = repo.refs().read_ref(STASH_REF).unwrap();
+        assert!(!stash.is_empty());
+
+        let mut index = repo.index();
+        let popped = pop(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+        )
+        .unwrap();
+        index.write_updates().unwrap();
+        assert_eq!(popped, stash);
+        assert_eq!(
+            fs::read_to_string(repo.root().join("file.txt")).unwrap(),
+            "modified\n"
+        );
+        assert!(!repo.is_clean());
+        assert!(repo.refs().read_ref(STASH_REF).unwrap().is_empty());
+    }
+
+    #[test]
+    fn save_without_changes_stashes_nothing() {
+        let repo = TestRepo::new();
+        repo.write("file.txt", "original\n");
+        repo.stage(&["file.txt"]);
+        repo.commit("initial");
+
+        let mut index = repo.index();
+        let message = save(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+            &author(),
+            &author(),
+        )
+        .unwrap();
+        assert_eq!(message, None);
+        assert!(repo.refs().read_ref(STASH_REF).unwrap().is_empty());
+    }
+}
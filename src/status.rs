@@ -0,0 +1,717 @@
+use std::{collections::BTreeMap, io, path::PathBuf};
+
+use crate::database::Database;
+use crate::diff;
+use crate::hash;
+use crate::index::{Entry, Index};
+use crate::refs::Refs;
+use crate::tree;
+use crate::utils;
+use crate::workspace::Workspace;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    // Moved from the path held, with identical content.
+    Renamed(String),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StatusReport {
+    pub untracked: Vec<String>,
+    pub modified: Vec<String>,
+    // Tracked files missing from the working tree.
+    pub deleted: Vec<String>,
+    pub staged: Vec<(String, Change)>,
+    pub intent_to_add: Vec<String>,
+    // Tracked files replaced by another kind of file, such as a symlink
+    // or a directory.
+    pub type_changed: Vec<String>,
+}
+
+impl StatusReport {
+    pub fn is_clean(&self) -> bool {
+        self.untracked.is_empty()
+            && self.modified.is_empty()
+            && self.deleted.is_empty()
+            && self.staged.is_empty()
+            && self.intent_to_add.is_empty()
+            && self.type_changed.is_empty()
+    }
+}
+
+/*
+    Compares the working tree against the index, and the index against the
+    tree of the HEAD commit. All paths in the report are sorted. Files
+    whose stat changed but whose content still hashes to the indexed
+    object id get their cached stat refreshed in the index.
+*/
+pub fn status(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+) -> io::Result<StatusReport> {
+    let mut report = StatusReport {
+        untracked: untracked_files(workspace, index),
+        ..Default::default()
+    };
+
+    let head_tree = tree::flatten_head_tree(database, refs)?;
+    let entries = index.each_entry();
+    for entry in &entries {
+        // Intent-to-add entries have no staged content to compare.
+        if entry.intent_to_add() {
+            report.intent_to_add.push(entry.path().to_string());
+            continue;
+        }
+
+        let object_id = utils::u8_to_hex_str(entry.oid().to_vec());
+        let mode = format!("{:o}", entry.mode());
+
+        // Paths outside a sparse checkout are never compared to the working tree.
+        let stat = if entry.skip_worktree() {
+            None
+        } else {
+            match workspace.symlink_metadata(&PathBuf::from(entry.path())) {
+                Ok(stat) => Some(stat),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    report.deleted.push(entry.path().to_string());
+                    None
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        if let Some(stat) = stat {
+            if entry.type_changed(&stat) {
+                report.type_changed.push(entry.path().to_string());
+            } else if !entry.stat_matches(&stat) || index.is_racily_clean(entry) {
+                if content_matches(workspace, entry)? {
+                    index.refresh_stat(entry.path(), stat);
+                } else {
+                    report.modified.push(entry.path().to_string());
+                }
+            }
+        }
+
+        match head_tree.get(entry.path()) {
+            None => report
+                .staged
+                .push((entry.path().to_string(), Change::Added)),
+            Some(head) if head.object_id != object_id || head.mode != mode => report
+                .staged
+                .push((entry.path().to_string(), Change::Modified)),
+            Some(_) => {}
+        }
+    }
+    for path in head_tree.keys() {
+        if index.get(path).is_none() {
+            report.staged.push((path.clone(), Change::Deleted));
+        }
+    }
+
+    // A staged deletion and addition of identical content is a rename.
+    let side = |change: Change| {
+        report
+            .staged
+            .iter()
+            .filter(|(_, staged)| *staged == change)
+            .map(|(path, _)| {
+                let oid = match change {
+                    Change::Deleted => head_tree[path].object_id.clone(),
+                    _ => utils::u8_to_hex_str(index.get(path).unwrap().oid().to_vec()),
+                };
+                (path.clone(), oid)
+            })
+            .collect::<Vec<_>>()
+    };
+    let (deleted, added) = (side(Change::Deleted), side(Change::Added));
+    for (old_path, new_path) in diff::pair_exact_renames(&as_refs(&deleted), &as_refs(&added)) {
+        report.staged.retain(|(path, _)| *path != old_path);
+        if let Some(staged) = report.staged.iter_mut().find(|(path, _)| *path == new_path) {
+            staged.1 = Change::Renamed(old_path);
+        }
+    }
+    report.staged.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(report)
+}
+
+/*
+    Whether status would report nothing at all, stopping at the first
+    change found: a staged change, a modified or deleted tracked file, an
+    intent-to-add entry or an untracked file. Nothing is refreshed, so the
+    index is left as it was.
+*/
+pub fn is_clean(
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    refs: &Refs,
+) -> io::Result<bool> {
+    let head_tree = tree::flatten_head_tree(database, refs)?;
+    let entries = index.each_entry();
+    if entries.len() != head_tree.len() {
+        return Ok(false);
+    }
+    for entry in &entries {
+        let staged = match head_tree.get(entry.path()) {
+            Some(head) => {
+                head.object_id != utils::u8_to_hex_str(entry.oid().to_vec())
+                    || head.mode != format!("{:o}", entry.mode())
+            }
+            None => true,
+        };
+        if staged || entry.intent_to_add() {
+            return Ok(false);
+        }
+    }
+    for entry in entries.iter().filter(|entry| !entry.skip_worktree()) {
+        let stat = match workspace.symlink_metadata(&PathBuf::from(entry.path())) {
+            Ok(stat) => stat,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if entry.type_changed(&stat) {
+            return Ok(false);
+        }
+        let unsure = !entry.stat_matches(&stat) || index.is_racily_clean(entry);
+        if unsure && !content_matches(workspace, entry)? {
+            return Ok(false);
+        }
+    }
+    Ok(untracked_files(workspace, index).is_empty())
+}
+
+// Hashing the working file avoids reading the indexed blob.
+fn content_matches(workspace: &Workspace, entry: &Entry) -> io::Result<bool> {
+    let data = workspace.read_blob(&PathBuf::from(entry.path()))?;
+    let mut blob = format!("blob {}\0", data.len()).into_bytes();
+    blob.extend_from_slice(&data);
+    Ok(hash::oid_bytes(&blob) == entry.oid())
+}
+
+fn as_refs(files: &[(String, String)]) -> Vec<(&str, &str)> {
+    files
+        .iter()
+        .map(|(path, oid)| (path.as_str(), oid.as_str()))
+        .collect()
+}
+
+/*
+    Lists the files in the workspace that are not in the index, sorted,
+    without comparing any tracked content. Ignored files are not listed.
+*/
+pub fn untracked_files(workspace: &Workspace, index: &Index) -> Vec<String> {
+    untracked_paths(workspace.list_files(workspace.root(), None), index)
+}
+
+/*
+    Keeps the listed files that are not in the index, sorted. Paths that
+    could not be read, or whose names are not UTF-8, are reported on
+    stderr and skipped, so one bad entry doesn't hide every other file.
+*/
+pub fn untracked_paths(files: Vec<io::Result<PathBuf>>, index: &Index) -> Vec<String> {
+    let mut paths = Vec::new();
+    for file in files {
+        match file {
+            Ok(file) => match file.to_str() {
+                Some(path) => paths.push(path.to_string()),
+                None => eprintln!("warning: skipping non-UTF-8 path '{}'", file.display()),
+            },
+            Err(err) => eprintln!("warning: {}", err),
+        }
+    }
+    paths.sort();
+    paths.retain(|path| index.get(path).is_none());
+    paths
+}
+
+/*
+    Formats a status report in the long human readable form.
+*/
+pub fn format_long(report: &StatusReport) -> String {
+    let mut out = String::new();
+    if report.is_clean() {
+        out.push_str("nothing to commit, working tree clean\n");
+        return out;
+    }
+    if !report.staged.is_empty() {
+        out.push_str("Changes to be committed:\n");
+        for (path, change) in &report.staged {
+            let label = match change {
+                Change::Added => "new file:",
+                Change::Modified => "modified:",
+                Change::Deleted => "deleted:",
+                Change::Renamed(old_path) => {
+                    out.push_str(&format!("\t{:<12}{} -> {}\n", "renamed:", old_path, path));
+                    continue;
+                }
+            };
+            out.push_str(&format!("\t{:<12}{}\n", label, path));
+        }
+        out.push('\n');
+    }
+    if !report.modified.is_empty()
+        || !report.deleted.is_empty()
+        || !report.intent_to_add.is_empty()
+        || !report.type_changed.is_empty()
+    {
+        out.push_str("Changes not staged for commit:\n");
+        let mut unstaged: Vec<(&String, &str)> = Vec::new();
+        unstaged.extend(report.modified.iter().map(|path| (path, "modified:")));
+        unstaged.extend(report.deleted.iter().map(|path| (path, "deleted:")));
+        unstaged.extend(report.intent_to_add.iter().map(|path| (path, "new file:")));
+        unstaged.extend(report.type_changed.iter().map(|path| (path, "typechange:")));
+        unstaged.sort();
+        for (path, label) in unstaged {
+            out.push_str(&format!("\t{:<12}{}\n", label, path));
+        }
+        out.push('\n');
+    }
+    if !report.untracked.is_empty() {
+        out.push_str("Untracked files:\n");
+        for path in &report.untracked {
+            out.push_str(&format!("\t{}\n", path));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/*
+    Formats a status report as stable "XY path" lines, where X is the
+    index status and Y is the working tree status. A rename is shown as
+    "R  old -> new".
+*/
+pub fn format_porcelain(report: &StatusReport) -> String {
+    let mut codes: BTreeMap<&str, [char; 2]> = BTreeMap::new();
+    let mut renamed_from = BTreeMap::new();
+    for (path, change) in &report.staged {
+        let code = codes.entry(path.as_str()).or_insert([' ', ' ']);
+        code[0] = match change {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::Renamed(old_path) => {
+                renamed_from.insert(path.as_str(), old_path.as_str());
+                'R'
+            }
+        };
+    }
+    for path in &report.modified {
+        codes.entry(path.as_str()).or_insert([' ', ' '])[1] = 'M';
+    }
+    for path in &report.deleted {
+        codes.entry(path.as_str()).or_insert([' ', ' '])[1] = 'D';
+    }
+    for path in &report.intent_to_add {
+        codes.entry(path.as_str()).or_insert([' ', ' '])[1] = 'A';
+    }
+    for path in &report.type_changed {
+        codes.entry(path.as_str()).or_insert([' ', ' '])[1] = 'T';
+    }
+
+    let mut out = String::new();
+    for (path, [x, y]) in codes {
+        match renamed_from.get(path) {
+            Some(old_path) => out.push_str(&format!(
+                "{}{} {} -> {}\n",
+                x,
+                y,
+                quote_path(old_path),
+                quote_path(path)
+            )),
+            None => out.push_str(&format!("{}{} {}\n", x, y, quote_path(path))),
+        }
+    }
+    for path in &report.untracked {
+        out.push_str(&format!("?? {}\n", quote_path(path)));
+    }
+    out
+}
+
+/*
+    Quotes a path in the manner of a C string literal when it contains
+    whitespace, quotes, backslashes or other nonprintable characters.
+    Other paths are returned unchanged.
+*/
+pub fn quote_path(path: &str) -> String {
+    let needs_quoting = path.bytes().any(|b| {
+        b.is_ascii_whitespace() || b.is_ascii_control() || b == b'"' || b == b'\\' || b >= 0x80
+    });
+    if !needs_quoting {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            0x20..=0x7e => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        ffi::OsStr,
+        fs,
+        os::unix::{ffi::OsStrExt, fs::symlink},
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::filesystem::mock::MockFs;
+    use crate::utils;
+
+    use crate::test_support::TestRepo;
+
+    fn status_of(repo: &TestRepo) -> StatusReport {
+        let mut index = repo.index();
+        status(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn report_lists_untracked_modified_and_staged() {
+        let repo = TestRepo::new();
+        repo.write("committed.txt", "one\n");
+        repo.stage(&["committed.txt"]);
+        repo.commit("initial");
+
+        repo.write("committed.txt", "two\n");
+        repo.write("new.txt", "new\n");
+        repo.stage(&["new.txt"]);
+        repo.write("untracked.txt", "?\n");
+
+        assert_eq!(
+            status_of(&repo),
+            StatusReport {
+                untracked: vec![String::from("untracked.txt")],
+                modified: vec![String::from("committed.txt")],
+                staged: vec![(String::from("new.txt"), Change::Added)],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn report_is_clean_after_commit() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a\n");
+        repo.stage(&["."]);
+        repo.commit("initial");
+        assert!(status_of(&repo).is_clean());
+    }
+
+    #[test]
+    fn format_long_groups_changes() {
+        let report = StatusReport {
+            untracked: vec![String::from("u.txt")],
+            modified: vec![String::from("m.txt")],
+            staged: vec![
+                (String::from("a.txt"), Change::Added),
+                (String::from("d.txt"), Change::Deleted),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_long(&report),
+            "Changes to be committed:\n\
+             \tnew file:   a.txt\n\
+             \tdeleted:    d.txt\n\
+             \n\
+             Changes not staged for commit:\n\
+             \tmodified:   m.txt\n\
+             \n\
+             Untracked files:\n\
+             \tu.txt\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn format_long_reports_clean_tree() {
+        assert_eq!(
+            format_long(&StatusReport::default()),
+            "nothing to commit, working tree clean\n"
+        );
+    }
+
+    #[test]
+    fn porcelain_codes_untracked_modified_and_added() {
+        let repo = TestRepo::new();
+        repo.write("modified.txt", "one\n");
+        repo.stage(&["modified.txt"]);
+        repo.commit("initial");
+
+        repo.write("modified.txt", "changed\n");
+        repo.write("staged.txt", "staged\n");
+        repo.stage(&["staged.txt"]);
+        repo.write("untracked.txt", "?\n");
+
+        assert_eq!(
+            format_porcelain(&status_of(&repo)),
+            " M modified.txt\nA  staged.txt\n?? untracked.txt\n"
+        );
+    }
+
+    #[test]
+    fn porcelain_quotes_paths_with_spaces() {
+        let report = StatusReport {
+            untracked: vec![String::from("with space.txt")],
+            staged: vec![(String::from("tab\there"), Change::Added)],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_porcelain(&report),
+            "A  \"tab\\there\"\n?? \"with space.txt\"\n"
+        );
+        assert_eq!(quote_path("plain.txt"), "plain.txt");
+        assert_eq!(quote_path("q\"uote"), "\"q\\\"uote\"");
+    }
+
+    #[test]
+    fn quote_path_escapes_control_characters() {
+        assert_eq!(quote_path("c\rd"), "\"c\\rd\"");
+        assert_eq!(quote_path("\x07\x08\x0b\x0c"), "\"\\a\\b\\v\\f\"");
+        // Others keep their octal escape, as do bytes outside ASCII.
+        assert_eq!(quote_path("\x01é"), "\"\\001\\303\\251\"");
+    }
+
+    #[test]
+    fn intent_to_add_is_reported_but_not_staged() {
+        let repo = TestRepo::new();
+        repo.write("new.txt", "content\n");
+        let mut index = repo.index();
+        let stat = repo.workspace().stat_file(repo.root().join("new.txt"));
+        index
+            .add_intent_to_add(&PathBuf::from("new.txt"), stat)
+            .unwrap();
+        assert!(index.write_updates().unwrap());
+
+        let report = status_of(&repo);
+        assert_eq!(report.intent_to_add, vec![String::from("new.txt")]);
+        assert!(report.staged.is_empty());
+        assert!(report.modified.is_empty());
+        assert!(report.untracked.is_empty());
+    }
+
+    #[test]
+    fn removed_trailing_newline_is_a_modification() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "line\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("initial");
+
+        repo.write("a.txt", "line");
+        assert_eq!(status_of(&repo).modified, vec![String::from("a.txt")]);
+    }
+
+    #[test]
+    fn missing_skip_worktree_file_is_not_deleted() {
+        let repo = TestRepo::new();
+        repo.write("sparse/a.txt", "a\n");
+        repo.write("b.txt", "b\n");
+        repo.stage(&["."]);
+        repo.commit("initial");
+
+        let mut index = repo.index();
+        assert!(index.set_skip_worktree("sparse/a.txt", true));
+        assert!(index.write_updates().unwrap());
+        fs::remove_dir_all(repo.root().join("sparse")).unwrap();
+        fs::remove_file(repo.root().join("b.txt")).unwrap();
+
+        let report = status_of(&repo);
+        assert_eq!(report.deleted, vec![String::from("b.txt")]);
+        assert!(report.staged.is_empty());
+    }
+
+    #[test]
+    fn symlink_content_is_its_target_path() {
+        let repo = TestRepo::new();
+        repo.write("target.txt", "one\n");
+        symlink("target.txt", repo.root().join("link")).unwrap();
+        repo.stage(&["."]);
+        repo.commit("initial");
+
+        // Changing what the link points at doesn't change the link.
+        repo.write("target.txt", "two\n");
+        let index = repo.index();
+        let link = index.get("link").unwrap();
+        assert!(content_matches(&repo.workspace(), link).unwrap());
+        assert!(!content_matches(&repo.workspace(), index.get("target.txt").unwrap()).unwrap());
+
+        fs::remove_file(repo.root().join("link")).unwrap();
+        symlink("elsewhere.txt", repo.root().join("link")).unwrap();
+        assert!(!content_matches(&repo.workspace(), link).unwrap());
+    }
+
+    #[test]
+    fn untracked_files_skips_tracked_and_ignored() {
+        let repo = TestRepo::new();
+        repo.write("tracked.txt", "t\n");
+        repo.stage(&["tracked.txt"]);
+        repo.write("untracked.txt", "u\n");
+        repo.write("build/out.txt", "o\n");
+        repo.write(".gitignore", "build/\n");
+
+        let files = untracked_files(&repo.workspace(), &repo.index());
+        assert_eq!(files, [String::from("untracked.txt")]);
+    }
+
+    #[test]
+    fn unreadable_directory_does_not_hide_other_untracked_files() {
+        let mut mock = MockFs::new();
+        mock.add_file(Path::new("/repo/a.txt"), b"a");
+        mock.add_file(Path::new("/repo/locked/secret.txt"), b"s");
+        mock.add_file(Path::new("/repo/z.txt"), b"z");
+        mock.deny_read(Path::new("/repo/locked"));
+        let workspace = Workspace::with_fs(PathBuf::from("/repo"), mock);
+        let index = Index::new(PathBuf::from("/repo/.git/index"));
+
+        let files = untracked_paths(workspace.list_files(Path::new("/repo"), None), &index);
+        assert_eq!(files, ["a.txt", "z.txt"]);
+    }
+
+    #[test]
+    fn non_utf8_names_are_skipped() {
+        let index = Index::new(PathBuf::from("index"));
+        let files = vec![
+            Ok(PathBuf::from("b.txt")),
+            Ok(PathBuf::from(OsStr::from_bytes(b"bin\xff.dat"))),
+            Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+            Ok(PathBuf::from("a.txt")),
+        ];
+        assert_eq!(untracked_paths(files, &index), ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn staged_move_is_reported_as_a_rename() {
+        let repo = TestRepo::new();
+        repo.write("old.txt", "moved\n");
+        repo.stage(&["old.txt"]);
+        repo.commit("initial");
+        fs::remove_file(repo.root().join("old.txt")).unwrap();
+        repo.write("lib/new.txt", "moved\n");
+        let mut index = repo.index();
+        assert!(index.remove("old.txt"));
+        assert!(index.write_updates().unwrap());
+        repo.stage(&["lib/new.txt"]);
+
+        assert_eq!(
+            format_porcelain(&status_of(&repo)),
+            "R  old.txt -> lib/new.txt\n"
+        );
+    }
+
+    #[test]
+    fn removed_tracked_file_is_deleted() {
+        let repo = TestRepo::new();
+        repo.write("kept.txt", "k\n");
+        repo.write("lib/gone.txt", "g\n");
+        repo.stage(&["."]);
+        repo.commit("initial");
+        fs::remove_file(repo.root().join("lib/gone.txt")).unwrap();
+
+        let report = status_of(&repo);
+        assert_eq!(report.deleted, [String::from("lib/gone.txt")]);
+        assert!(report.modified.is_empty() && report.untracked.is_empty());
+        assert_eq!(format_porcelain(&report), " D lib/gone.txt\n");
+    }
+
+    #[test]
+    fn touched_identical_file_is_unmodified_without_reading_its_blob() {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "same\n");
+        repo.stage(&["a.txt"]);
+        repo.commit("initial");
+
+        // The stat no longer matches but the content is unchanged.
+        let path = repo.root().join("a.txt");
+        let touched = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        let stat = fs::symlink_metadata(&path).unwrap();
+        assert!(!repo.index().get("a.txt").unwrap().stat_matches(&stat));
+
+        // Comparing object ids never needs the stored blob.
+        let oid = utils::u8_to_hex_str(repo.index().get("a.txt").unwrap().oid().to_vec());
+        let (subdir, file) = utils::hash_to_path(&oid);
+        let blob = repo.git_dir().join("objects").join(subdir).join(file);
+        fs::remove_file(blob).unwrap();
+
+        let mut index = repo.index();
+        let report = status(
+            &repo.workspace(),
+            &repo.database(),
+            &mut index,
+            &repo.refs(),
+        )
+        .unwrap();
+        assert!(report.is_clean());
+        // The fresh stat is cached so the next status skips hashing.
+        assert!(index.is_changed());
+        assert!(index.get("a.txt").unwrap().stat_matches(&stat));
+    }
+
+    #[test]
+    fn file_replaced_by_a_directory_is_a_type_change() {
+        let repo = TestRepo::new();
+        repo.write("foo", "file\n");
+        repo.stage(&["foo"]);
+        repo.commit("initial");
+        fs::remove_file(repo.root().join("foo")).unwrap();
+        repo.write("foo/inner.txt", "inner\n");
+
+        let report = status_of(&repo);
+        assert_eq!(report.type_changed, ["foo"]);
+        assert!(report.modified.is_empty() && report.deleted.is_empty());
+        assert_eq!(report.untracked, ["foo/inner.txt"]);
+        assert_eq!(format_porcelain(&report), " T foo\n?? foo/inner.txt\n");
+        assert!(!repo.is_clean());
+    }
+
+    #[test]
+    fn file_replaced_by_a_symlink_is_a_type_change() {
+        let repo = TestRepo::new();
+        repo.write("foo", "file\n");
+        repo.write("target.txt", "target\n");
+        repo.stage(&["."]);
+        repo.commit("initial");
+        fs::remove_file(repo.root().join("foo")).unwrap();
+        symlink("target.txt", repo.root().join("foo")).unwrap();
+
+        let report = status_of(&repo);
+        assert_eq!(report.type_changed, ["foo"]);
+        assert!(report.modified.is_empty() && report.untracked.is_empty());
+        assert_eq!(format_porcelain(&report), " T foo\n");
+        assert!(format_long(&report).contains("\ttypechange: foo\n"));
+        assert!(!repo.is_clean());
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tempfile::TempDir;
+
+use crate::add::{self, AddOptions};
+use crate::author::Author;
+use crate::commit::Commit;
+use crate::database::Database;
+use crate::index::Index;
+use crate::refs::Refs;
+use crate::status;
+use crate::tree::Tree;
+use crate::utils;
+use crate::workspace::Workspace;
+
+/*
+    A scratch repository in a temporary directory, removed when dropped.
+    HEAD starts out on an unborn main branch.
+*/
+pub struct TestRepo {
+    pub dir: TempDir,
+}
+
+impl TestRepo {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let git_dir = dir.path().join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::create_dir_all(git_dir.join("refs").join("heads")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        TestRepo { dir }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn git_dir(&self) -> PathBuf {
+        self.dir.path().join(".git")
+    }
+
+    pub fn workspace(&self) -> Workspace {
+        Workspace::new(self.root().to_path_buf())
+    }
+
+    pub fn database(&self) -> Database {
+        Database::new(self.git_dir().join("objects"))
+    }
+
+    pub fn refs(&self) -> Refs {
+        Refs::new(self.git_dir())
+    }
+
+    pub fn index(&self) -> Index {
+        Index::load(self.git_dir().join("index")).unwrap()
+    }
+
+    // Writes a working file relative to the root, creating its directories.
+    pub fn write(&self, path: &str, content: &str) {
+        let path = self.root().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    // Stages pathspecs as add would and writes the index.
+    pub fn stage(&self, pathspecs: &[&str]) {
+        let mut index = self.index();
+        let pathspecs = pathspecs.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        add::add(
+            &self.workspace(),
+            &self.database(),
+            &mut index,
+            &pathspecs,
+            &AddOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert!(index.write_updates().unwrap());
+    }
+
+    // Whether the working tree and index match HEAD, as status sees it.
+    pub fn is_clean(&self) -> bool {
+        status::is_clean(
+            &self.workspace(),
+            &self.database(),
+            &mut self.index(),
+            &self.refs(),
+        )
+        .unwrap()
+    }
+
+    // Commits the indexed paths on top of HEAD, returning the hex commit id.
+    pub fn commit(&self, message: &str) -> String {
+        let (database, refs) = (self.database(), self.refs());
+        let mut tree = Tree::from_index(&mut self.index());
+        tree.store_tree(&database);
+        let parent = refs.read_head().unwrap();
+        let mut commit = Commit::new(
+            parent.clone(),
+            tree.object_id,
+            author(),
+            format!("{}\n", message),
+        );
+        database.store(&mut commit).unwrap();
+        let oid = utils::u8_to_hex_str(commit.object_id.into_bytes());
+        let expected = Some(parent.as_str()).filter(|parent| !parent.is_empty());
+        refs.update_head(expected, &oid).unwrap();
+        oid
+    }
+}
+
+pub fn author() -> Author {
+    Author::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        String::from("1700000000 +0000"),
+    )
+}
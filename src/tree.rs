@@ -1,10 +1,140 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
 use std::path::PathBuf;
 
 use crate::blob::Kind;
+use crate::cache_tree::CacheTree;
+use crate::commit;
 use crate::database::Database;
 use crate::entry::Entry;
+use crate::hash;
+use crate::index::Index;
+use crate::refs::Refs;
 use crate::traits::Object;
+use crate::utils;
+
+// Mode used for subtree entries in a tree object.
+pub const TREE_MODE: &str = "40000";
+
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    pub mode: String,
+    pub name: String,
+    pub object_id: String,
+}
+
+impl TreeEntry {
+    pub fn is_tree(&self) -> bool {
+        self.mode == TREE_MODE
+    }
+}
+
+/*
+    Parses the body of a stored tree object into its entries.
+    Each entry is "<mode> <name>\0<20 byte oid>", object ids are returned as hex.
+*/
+pub fn parse_tree(data: &[u8]) -> io::Result<Vec<TreeEntry>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt tree object");
+    let mut entries = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ').ok_or_else(invalid)?;
+        let nul = rest.iter().position(|&b| b == 0).ok_or_else(invalid)?;
+        if nul < space || rest.len() < nul + 21 {
+            return Err(invalid());
+        }
+        let mode = String::from_utf8(rest[..space].to_vec()).map_err(|_| invalid())?;
+        let name = String::from_utf8(rest[space + 1..nul].to_vec()).map_err(|_| invalid())?;
+        let object_id = utils::u8_to_hex_str(rest[nul + 1..nul + 21].to_vec());
+        entries.push(TreeEntry {
+            mode,
+            name,
+            object_id,
+        });
+        rest = &rest[nul + 21..];
+    }
+    Ok(entries)
+}
+
+/*
+    Recursively reads the tree with the given hex object id, returning
+    every non-tree entry keyed by its full path relative to the tree root.
+*/
+pub fn flatten_tree(db: &Database, tree_oid: &str) -> io::Result<BTreeMap<String, TreeEntry>> {
+    let mut files = BTreeMap::new();
+    flatten_into(db, tree_oid, "", &mut files)?;
+    Ok(files)
+}
+
+/*
+    Flattens the tree of the commit HEAD points at. An unborn HEAD has no
+    files.
+*/
+pub fn flatten_head_tree(db: &Database, refs: &Refs) -> io::Result<BTreeMap<String, TreeEntry>> {
+    let head = refs.read_head()?;
+    if head.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let (_, data) = db.read_object(&head)?;
+    flatten_tree(db, &commit::parse_commit(&data)?.tree)
+}
+
+/*
+    Lists a tree as "<mode> <type> <oid>\t<name>" lines in the manner of
+    ls-tree. Subtrees are listed as entries of their own unless recursive
+    is set, in which case they are descended into and only their files are
+    listed, by full path.
+*/
+pub fn ls_tree(db: &Database, tree_oid: &str, recursive: bool) -> io::Result<String> {
+    let mut out = String::new();
+    ls_tree_into(db, tree_oid, "", recursive, &mut out)?;
+    Ok(out)
+}
+
+fn ls_tree_into(
+    db: &Database,
+    tree_oid: &str,
+    prefix: &str,
+    recursive: bool,
+    out: &mut String,
+) -> io::Result<()> {
+    let (_, data) = db.read_object(tree_oid)?;
+    for entry in parse_tree(&data)? {
+        let path = format!("{}{}", prefix, entry.name);
+        if recursive && entry.is_tree() {
+            ls_tree_into(db, &entry.object_id, &format!("{}/", path), recursive, out)?;
+            continue;
+        }
+        let kind = match entry.mode.as_str() {
+            TREE_MODE => "tree",
+            "160000" => "commit",
+            _ => "blob",
+        };
+        out.push_str(&format!(
+            "{:0>6} {} {}\t{}\n",
+            entry.mode, kind, entry.object_id, path
+        ));
+    }
+    Ok(())
+}
+
+fn flatten_into(
+    db: &Database,
+    tree_oid: &str,
+    prefix: &str,
+    files: &mut BTreeMap<String, TreeEntry>,
+) -> io::Result<()> {
+    let (_, data) = db.read_object(tree_oid)?;
+    for entry in parse_tree(&data)? {
+        let path = format!("{}{}", prefix, entry.name);
+        if entry.is_tree() {
+            flatten_into(db, &entry.object_id, &format!("{}/", path), files)?;
+        } else {
+            files.insert(path, entry);
+        }
+    }
+    Ok(())
+}
 
 #[derive(Debug)]
 pub enum EntryOrTree {
@@ -20,6 +150,12 @@ pub struct Tree {
     pub object_id: String,
 }
 
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Tree {
     pub fn new() -> Self {
         Self {
@@ -39,6 +175,20 @@ impl Tree {
         root
     }
 
+    /*
+        Builds the tree recorded by the index. Intent-to-add entries are
+        left out since they have no staged content yet.
+    */
+    pub fn from_index(index: &mut Index) -> Self {
+        let entries = index
+            .each_entry()
+            .iter()
+            .filter(|entry| !entry.intent_to_add())
+            .map(Entry::from_index)
+            .collect();
+        Self::build(entries)
+    }
+
     pub fn store_tree(&mut self, db: &Database) {
         for entry_or_tree in self.entries.values_mut() {
             if let EntryOrTree::Tree(subtree) = entry_or_tree {
@@ -48,31 +198,79 @@ impl Tree {
         let _ = Database::store(db, self);
     }
 
+    /*
+        Stores the tree like store_tree, but the id of any directory the
+        cache still holds as valid, and whose tree is in the database, is
+        reused without hashing or storing it again. Returns the cache for
+        the stored tree.
+    */
+    pub fn store_tree_cached(&mut self, db: &Database, cache: Option<&CacheTree>) -> CacheTree {
+        if let Some(cache) = cache {
+            if let Some(oid) = cache.valid_oid() {
+                if db.exists(&utils::u8_to_hex_str(oid.to_vec())) {
+                    self.object_id = unsafe { String::from_utf8_unchecked(oid.to_vec()) };
+                    return cache.clone();
+                }
+            }
+        }
+
+        let mut fresh = CacheTree {
+            entry_count: 0,
+            ..Default::default()
+        };
+        for name in &self.entries_order {
+            match self.entries.get_mut(name) {
+                Some(EntryOrTree::Tree(subtree)) => {
+                    let child = subtree.store_tree_cached(db, cache.and_then(|c| c.child(name)));
+                    fresh.entry_count += child.entry_count;
+                    fresh.children.push((name.clone(), child));
+                }
+                Some(EntryOrTree::Entry(_)) => fresh.entry_count += 1,
+                None => {}
+            }
+        }
+        let _ = Database::store(db, self);
+        fresh.oid = self.object_id.clone().into_bytes();
+        fresh
+    }
+
+    /*
+        Computes the object ids of this tree and its subtrees the same way
+        store_tree does, but without writing anything to the database.
+    */
+    pub fn compute_object_ids(&mut self) {
+        for entry_or_tree in self.entries.values_mut() {
+            if let EntryOrTree::Tree(subtree) = entry_or_tree {
+                subtree.compute_object_ids()
+            }
+        }
+        let object_id = hash::oid_bytes(self.to_string().as_bytes()).to_vec();
+        self.object_id = unsafe { String::from_utf8_unchecked(object_id) };
+    }
+
     pub fn add_entry(&mut self, parents: Vec<PathBuf>, entry: Entry) {
         if parents.is_empty() {
             self.entries_order.push(entry.filename.clone());
             self.entries
                 .insert(entry.filename.clone(), EntryOrTree::Entry(entry));
         } else {
-            dbg!(&parents);
             let path = &parents[0];
             // foo/bar/world.txt   bar/world.txt
 
-            let first_component = path.components().last().unwrap();
-            let mut basename = String::new();
-            match first_component {
+            let first_component = path.components().next_back().unwrap();
+            let basename = match first_component {
                 std::path::Component::RootDir => {
                     eprintln!("The path starts with a root directory.");
                     panic!();
                 }
                 std::path::Component::Normal(component) => {
-                    basename = String::from(component.to_str().unwrap());
+                    String::from(component.to_str().unwrap())
                 }
                 _ => {
                     println!("The first component is not a directory.");
                     panic!();
                 }
-            }
+            };
             if !self.entries.contains_key(&basename) {
                 self.entries_order.push(basename.clone());
             }
@@ -100,12 +298,12 @@ impl Object for Tree {
             let entry_or_tree = self.entries.get(filename).unwrap();
             let (mode, object_id) = match entry_or_tree {
                 EntryOrTree::Entry(entry) => (entry.mode(), entry.object_id.clone()),
-                EntryOrTree::Tree(tree) => (String::from("40000"), tree.object_id.clone()),
+                EntryOrTree::Tree(tree) => (String::from(TREE_MODE), tree.get_object_id()),
             };
 
             content.push_str(&format!("{} {}\0{}", mode, filename, object_id,))
         }
-        format!("{} {}\0{}", kind, content.bytes().len(), content)
+        format!("{} {}\0{}", kind, content.len(), content)
     }
 
     fn get_object_id(&self) -> String {
@@ -116,3 +314,85 @@ impl Object for Tree {
         self.object_id = object_id;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs, path::Path};
+
+    use crate::test_support::TestRepo;
+
+    // The tree of a.txt "hello\n" and lib/b.txt "world\n", as git writes it.
+    const TREE: &str = "8522d47075ad7804031c0d8aca3d89f4fa480edd";
+
+    fn repo_with_subdirectory() -> TestRepo {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "hello\n");
+        repo.write("lib/b.txt", "world\n");
+        repo.stage(&["."]);
+        let mut tree = Tree::from_index(&mut repo.index());
+        tree.store_tree(&repo.database());
+        assert_eq!(utils::u8_to_hex_str(tree.object_id.into_bytes()), TREE);
+        repo
+    }
+
+    #[test]
+    fn ls_tree_lists_subtrees_as_entries() {
+        let repo = repo_with_subdirectory();
+        assert_eq!(
+            ls_tree(&repo.database(), TREE, false).unwrap(),
+            "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\ta.txt\n\
+             040000 tree 721eea743f274b162a059c0032155c36a62cd740\tlib\n"
+        );
+    }
+
+    #[test]
+    fn ls_tree_recursive_lists_files_by_full_path() {
+        let repo = repo_with_subdirectory();
+        assert_eq!(
+            ls_tree(&repo.database(), TREE, true).unwrap(),
+            "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\ta.txt\n\
+             100644 blob cc628ccd10742baea8241c5924df992b5c019f71\tlib/b.txt\n"
+        );
+    }
+
+    #[test]
+    fn cached_store_rebuilds_only_changed_subtrees() {
+        let repo = TestRepo::new();
+        let database = repo.database();
+        let mut index = Index::new(repo.git_dir().join("index"));
+        // Only the entries matter, so every path shares one file's stat.
+        repo.write("r.txt", "r\n");
+        let stat = fs::metadata(repo.root().join("r.txt")).unwrap();
+        for path in ["a/x.txt", "b/y.txt", "c/d/z.txt", "r.txt"] {
+            index.add(Path::new(path), &[1; 20], stat.clone()).unwrap();
+        }
+        let mut tree = Tree::from_index(&mut index);
+        let cache = tree.store_tree_cached(&database, index.tree_cache());
+        index.set_tree_cache(cache);
+        // The root, a, b, c and c/d.
+        assert_eq!(database.stores.get(), 5);
+        let first = tree.object_id;
+
+        // Nothing changed, so the whole tree is reused.
+        let mut tree = Tree::from_index(&mut index);
+        tree.store_tree_cached(&database, index.tree_cache());
+        assert_eq!(database.stores.get(), 5);
+        assert_eq!(tree.object_id, first);
+
+        index.add(Path::new("c/d/z.txt"), &[2; 20], stat).unwrap();
+        let mut tree = Tree::from_index(&mut index);
+        let cache = tree.store_tree_cached(&database, index.tree_cache());
+        // Only c/d, c and the root are stored again.
+        assert_eq!(database.stores.get(), 8);
+        let mut uncached = Tree::from_index(&mut index);
+        uncached.compute_object_ids();
+        assert_eq!(tree.object_id, uncached.object_id);
+        index.set_tree_cache(cache);
+        assert_eq!(
+            index.tree_cache().unwrap().valid_oid(),
+            Some(tree.object_id.as_bytes())
+        );
+    }
+}
@@ -1,6 +1,5 @@
 use flate2::read::ZlibDecoder;
-use sha1::{Digest, Sha1};
-use std::{env, fs, io::Read, path::PathBuf, process};
+use std::{fs, io::Read, path::PathBuf};
 
 /*
     object_path is assumed to point to an object in the objects directory.
@@ -17,23 +16,13 @@ pub fn inflate(object_path: PathBuf) -> String {
                 Err(_) => panic!("error decompressing!"),
             }
         }
-        Err(e) => {
+        Err(_) => {
             eprintln!("Could not read object data");
             std::process::exit(1);
         }
     }
 }
 
-/*
-    Compute Sha1 hash of content_str.
-*/
-pub fn hash_content(content_str: &str) -> Vec<u8> {
-    let mut hasher = Sha1::new();
-    hasher.update(content_str.as_bytes());
-    let hash_result = hasher.finalize();
-    hash_result.as_slice().to_vec()
-}
-
 /*
     Formats bytes as a displayable string. FOR DISPLAY PURPOSES ONLY.
     The formatting expands each byte into a displayable character,
@@ -49,6 +38,20 @@ pub fn u8_to_hex_str(content_hash: Vec<u8>) -> String {
     content_hash_hex
 }
 
+/*
+    Inverse of u8_to_hex_str, converts a hex string back into raw bytes.
+    Returns None if the string is not valid hex.
+*/
+pub fn hex_to_u8(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 /*
     Splits an object path hash into a tuple,
     with the first element being the first two bytes of the hash,
@@ -58,33 +61,23 @@ pub fn hash_to_path(content_hash_hex: &str) -> (&str, &str) {
     (&content_hash_hex[0..2], &content_hash_hex[2..])
 }
 
-pub fn get_root_path() -> PathBuf {
-    match env::current_dir() {
-        Ok(cwd) => cwd,
-        Err(_) => {
-            eprintln!("current_dir() failure in commit case.");
-            process::exit(1);
-        }
-    }
-}
-
-pub fn get_git_path() -> PathBuf {
-    let root_path = get_root_path();
-    let mut git_path = PathBuf::from(&root_path);
-    git_path.push(".git");
-    git_path
+/*
+    Shortens a hex object id to its first len characters,
+    or returns it unchanged if it is already shorter.
+*/
+pub fn oid_abbrev(oid: &str, len: usize) -> &str {
+    &oid[..len.min(oid.len())]
 }
 
-pub fn get_db_path() -> PathBuf {
-    let git_path = get_git_path();
-    let mut db_path = PathBuf::from(&git_path);
-    db_path.push("objects");
-    db_path
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub fn get_index_path() -> PathBuf {
-    let git_path = get_git_path();
-    let mut index_path = PathBuf::from(&git_path);
-    index_path.push("index");
-    index_path
+    #[test]
+    fn oid_abbrev_shortens_to_len() {
+        let oid = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        assert_eq!(oid_abbrev(oid, 7), "e69de29");
+        assert_eq!(oid_abbrev(oid, 40), oid);
+        assert_eq!(oid_abbrev("abc", 7), "abc");
+    }
 }
@@ -1,69 +1,403 @@
 use std::{
-    fs::{self, Metadata},
-    io,
-    path::{Path, PathBuf},
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{symlink, PermissionsExt},
+    },
+    path::{Component, Path, PathBuf},
     process,
 };
 
+use tempfile::NamedTempFile;
+
+use crate::attributes::AttributeStack;
+use crate::filesystem::{FileMetadata, FileSystem, RealFs};
+use crate::filter::Filter;
+use crate::ignore::IgnoreStack;
+
 #[derive(Debug)]
-pub struct Workspace {
-    ignore: [&'static str; 7],
+pub struct Workspace<F: FileSystem = RealFs> {
+    // Names never listed. A leading "/" anchors the name to the root of
+    // the workspace, otherwise it is ignored at any depth.
+    ignore: [&'static str; 5],
     path: PathBuf,
+    fs: F,
+    // Applied to file content moving to and from the object database.
+    filter: Filter,
 }
 
+// File type bits of a symlink mode, as recorded in the index.
+const MODE_SYMLINK: u32 = 0xA000;
+
 impl Workspace {
     pub fn new(path: PathBuf) -> Self {
+        Workspace::with_fs(path, RealFs)
+    }
+
+    /*
+        Like new, but fails unless path is an existing directory, so a bad
+        root is reported up front instead of on the first file access.
+    */
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let stat = fs::metadata(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        if !stat.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{}: not a directory", path.display()),
+            ));
+        }
+        Ok(Workspace::new(path))
+    }
+
+    /*
+        Writes data to rel, relative to the workspace root, creating missing
+        parent directories. The file is written to a temporary name and
+        renamed into place so readers never see partial content. A symlink
+        mode creates a symlink with data as its target, any other mode sets
+        the permission bits of a regular file.
+    */
+    pub fn write_file(&self, rel: &Path, data: &[u8], mode: u32) -> io::Result<()> {
+        let path = self.path.join(rel);
+        let parent = path.parent().unwrap_or(&self.path);
+        fs::create_dir_all(parent)?;
+
+        if mode & 0xF000 == MODE_SYMLINK {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let temp_path = parent.join(format!(".{}.{}.tmp", name, process::id()));
+            let target = OsStr::from_bytes(data);
+            let _ = fs::remove_file(&temp_path);
+            symlink(target, &temp_path)?;
+            return fs::rename(&temp_path, &path).inspect_err(|_| {
+                let _ = fs::remove_file(&temp_path);
+            });
+        }
+
+        let mut temp_file = NamedTempFile::new_in(parent)?;
+        temp_file.write_all(data)?;
+        fs::set_permissions(temp_file.path(), fs::Permissions::from_mode(mode & 0o777))?;
+        temp_file.persist(&path).map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    /*
+        Like write_file, but data is a stored blob and is passed through
+        the workspace filter first. Symlink targets are never converted.
+    */
+    pub fn write_blob(&self, rel: &Path, data: Vec<u8>, mode: u32) -> io::Result<()> {
+        if mode & 0xF000 == MODE_SYMLINK {
+            return self.write_file(rel, &data, mode);
+        }
+        let data = self.filter.smudge(&self.attributes_for(rel), rel, data);
+        self.write_file(rel, &data, mode)
+    }
+
+    /*
+        Removes the file at rel, relative to the workspace root, along with
+        any parent directories left empty. A missing file is not an error.
+    */
+    pub fn remove_file(&self, rel: &Path) -> io::Result<()> {
+        match fs::remove_file(self.path.join(rel)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let mut parent = rel.parent();
+        while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty()) {
+            // Stops at the first directory that still has entries.
+            if fs::remove_dir(self.path.join(dir)).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> Workspace<F> {
+    pub fn with_fs(path: PathBuf, fs: F) -> Self {
         Workspace {
-            ignore: [".", "..", ".vscode", ".git", "target", "src", ".gitignore"],
+            ignore: [".git", "/.vscode", "/target", "/src", "/.gitignore"],
             path,
+            fs,
+            filter: Filter::default(),
         }
     }
 
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.path
+    }
+
     pub fn read_data(&self, path: &Path) -> io::Result<String> {
-        let mut absolute_path = self.path.clone();
-        absolute_path.push(path);
-        fs::read_to_string(absolute_path)
-    }
-
-    pub fn list_files(&self, cur_path: &PathBuf) -> io::Result<Vec<PathBuf>> {
-        let metadata = fs::metadata(cur_path)?;
-        let mut v = Vec::new();
-        if metadata.is_dir() {
-
-            let read_files_res = fs::read_dir(cur_path);
-            match read_files_res {
-                Ok(read_files) => {
-                    for file in read_files {
-                        let path = file?.path();
-                        if self.ignore.into_iter().all(|x| !path.ends_with(x)) {
-                            if path.is_dir() {
-                                let mut files_from_dir = Self::list_files(self, &path.clone())?;
-                                v.append(&mut files_from_dir);
-                            } else if path.is_file() {
-                                // Strip root path.
-                                let absolute_path = path.as_path();
-                                let relative_path = absolute_path.strip_prefix(self.path.clone());
-                                match relative_path {
-                                    Ok(p) => v.push(PathBuf::from(p)),
-                                    Err(_) => v.push(PathBuf::from(absolute_path))
-                                }
-                            }
-                        }
-                    }
+        let data = self.read_data_bytes(path)?;
+        String::from_utf8(data).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", self.path.join(path).display(), err),
+            )
+        })
+    }
+
+    /*
+        Reads the raw bytes of a file, without any text decoding, so that
+        content comparisons see every byte including trailing newlines.
+        Errors keep their kind and name the absolute path that was read.
+    */
+    pub fn read_data_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = self.path.join(path);
+        self.fs
+            .read(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    /*
+        Reads a file as the content of the blob it would be stored as,
+        passing it through the workspace filter. A symlink is stored as
+        the path it points at, unfiltered, and is never followed.
+    */
+    pub fn read_blob(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let full_path = self.path.join(path);
+        let describe = |err: io::Error| {
+            io::Error::new(err.kind(), format!("{}: {}", full_path.display(), err))
+        };
+        let stat = self.fs.symlink_metadata(&full_path).map_err(describe)?;
+        if stat.is_symlink() {
+            let target = self.fs.read_link(&full_path).map_err(describe)?;
+            return Ok(target.into_os_string().into_vec());
+        }
+        let data = self.read_data_bytes(path)?;
+        Ok(self.filter.clean(&self.attributes_for(path), path, data))
+    }
+
+    /*
+        Recursively lists the files under cur_path, skipping ignored names.
+        Paths that can't be read are yielded as errors naming the path,
+        rather than failing the whole listing.
+        Symlinks are listed as files and never followed, so a link pointing
+        back up the tree can't send the walk into a cycle.
+        When a progress callback is given it is invoked once per listed file.
+    */
+    pub fn list_files(
+        &self,
+        cur_path: &Path,
+        mut progress: Option<&mut dyn FnMut(&Path)>,
+    ) -> Vec<io::Result<PathBuf>> {
+        let mut files = Vec::new();
+        if let Some(mut ignores) = self.ignore_stack_for(&self.relative_path(cur_path)) {
+            self.walk(cur_path, Some(&mut ignores), &mut progress, &mut files);
+        }
+        files
+    }
+
+    /*
+        Lists the files under each pathspec, a path relative to the root of
+        the workspace. A pathspec that is itself ignored, or lies inside an
+        ignored directory, yields nothing unless force is set, in which case
+        .gitignore files are not consulted at all.
+    */
+    pub fn list_pathspecs(
+        &self,
+        pathspecs: &[PathBuf],
+        force: bool,
+        mut progress: Option<&mut dyn FnMut(&Path)>,
+    ) -> Vec<io::Result<PathBuf>> {
+        let mut files = Vec::new();
+        for pathspec in pathspecs {
+            let path = self.path.join(pathspec);
+            if force {
+                self.walk(&path, None, &mut progress, &mut files);
+                continue;
+            }
+            if self.is_ignored(pathspec) {
+                continue;
+            }
+            if let Some(mut ignores) = self.ignore_stack_for(pathspec) {
+                self.walk(&path, Some(&mut ignores), &mut progress, &mut files);
+            }
+        }
+        files
+    }
+
+    /*
+        Loads the .gitignore files of every directory from the root down to
+        the parent of path, relative to the root, checking each directory
+        on the way and then path itself. Returns None if any of them is
+        ignored. The .gitignore of path itself is loaded by the walk.
+    */
+    fn ignore_stack_for(&self, path: &Path) -> Option<IgnoreStack> {
+        let mut ignores = IgnoreStack::new();
+        let mut dir = PathBuf::new();
+        let names = path
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect::<Vec<_>>();
+        for (i, name) in names.iter().enumerate() {
+            self.load_ignore_file(&mut ignores, &dir);
+            dir.push(name);
+            let is_dir = i + 1 < names.len()
+                || self
+                    .fs
+                    .symlink_metadata(&self.path.join(&dir))
+                    .is_ok_and(|metadata| metadata.is_dir());
+            if ignores.is_ignored(&dir, is_dir) {
+                return None;
+            }
+        }
+        Some(ignores)
+    }
+
+    /*
+        Loads the .gitattributes files of every directory from the root
+        down to the parent of path, relative to the root. Files that can't
+        be read are skipped.
+    */
+    fn attributes_for(&self, path: &Path) -> AttributeStack {
+        let mut dirs = vec![PathBuf::new()];
+        for component in path.parent().into_iter().flat_map(Path::components) {
+            if let Component::Normal(name) = component {
+                let dir = dirs[dirs.len() - 1].join(name);
+                dirs.push(dir);
+            }
+        }
+        let mut attributes = AttributeStack::new();
+        for dir in dirs {
+            if let Ok(contents) = self.fs.read(&self.path.join(&dir).join(".gitattributes")) {
+                attributes.push(&String::from_utf8_lossy(&contents), &dir);
+            }
+        }
+        attributes
+    }
+
+    // Pushes the patterns of dir/.gitignore, returning the previous depth.
+    fn load_ignore_file(&self, ignores: &mut IgnoreStack, dir: &Path) -> usize {
+        match self.fs.read(&self.path.join(dir).join(".gitignore")) {
+            Ok(contents) => ignores.push(&String::from_utf8_lossy(&contents), dir),
+            Err(_) => ignores.push("", dir),
+        }
+    }
+
+    /*
+        Whether path, relative to the workspace root, is ignored itself or
+        lies inside an ignored directory.
+    */
+    fn is_ignored(&self, path: &Path) -> bool {
+        path.components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .enumerate()
+            .any(|(depth, component)| {
+                let name = component.as_os_str();
+                self.ignore
+                    .iter()
+                    .any(|pattern| match pattern.strip_prefix('/') {
+                        Some(anchored) => depth == 0 && name == anchored,
+                        None => name == *pattern,
+                    })
+            })
+    }
+
+    // Without an ignore stack only the built in ignore names apply.
+    fn walk(
+        &self,
+        cur_path: &Path,
+        mut ignores: Option<&mut IgnoreStack>,
+        progress: &mut Option<&mut dyn FnMut(&Path)>,
+        files: &mut Vec<io::Result<PathBuf>>,
+    ) {
+        let metadata = match self.fs.symlink_metadata(cur_path) {
+            Ok(metadata) => metadata,
+            Err(err) => return files.push(Err(self.path_error(cur_path, err))),
+        };
+        if !metadata.is_dir() {
+            let file = self.relative_path(cur_path);
+            if let Some(callback) = progress.as_mut() {
+                callback(&file);
+            }
+            return files.push(Ok(file));
+        }
+
+        let read_files = match self.fs.read_dir(cur_path) {
+            Ok(read_files) => read_files,
+            Err(err) => return files.push(Err(self.path_error(cur_path, err))),
+        };
+        let depth = ignores
+            .as_deref_mut()
+            .map(|ignores| self.load_ignore_file(ignores, &self.relative_path(cur_path)));
+        for file in read_files {
+            let path = match file {
+                Ok(path) => path,
+                Err(err) => {
+                    files.push(Err(self.path_error(cur_path, err)));
+                    continue;
                 }
-                Err(_) => {
-                    eprintln!("error reading files in current directory");
-                    process::exit(1);
+            };
+            let relative = self.relative_path(&path);
+            if self.is_ignored(&relative) {
+                continue;
+            }
+            let metadata = match self.fs.symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    files.push(Err(self.path_error(&path, err)));
+                    continue;
                 }
+            };
+            if ignores
+                .as_deref()
+                .is_some_and(|ignores| ignores.is_ignored(&relative, metadata.is_dir()))
+            {
+                continue;
             }
-        } else {
-            v.push(cur_path.clone());
+            if metadata.is_dir() {
+                self.walk(&path, ignores.as_deref_mut(), progress, files);
+            } else if metadata.is_file() || metadata.is_symlink() {
+                if let Some(callback) = progress.as_mut() {
+                    callback(&relative);
+                }
+                files.push(Ok(relative));
+            }
+        }
+        if let (Some(ignores), Some(depth)) = (ignores, depth) {
+            ignores.truncate(depth);
+        }
+    }
+
+    // Adds the offending path to an io error so callers can report it.
+    fn path_error(&self, path: &Path, err: io::Error) -> io::Error {
+        let path = self.relative_path(path);
+        io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+    }
+
+    // Strip root path.
+    fn relative_path(&self, absolute_path: &Path) -> PathBuf {
+        match absolute_path.strip_prefix(&self.path) {
+            Ok(p) => PathBuf::from(p),
+            Err(_) => PathBuf::from(absolute_path),
         }
-        Ok(v)
     }
 
-    pub fn stat_file(&self, path: PathBuf) -> Metadata {
-        match fs::metadata(path) {
+    pub fn metadata(&self, path: &Path) -> io::Result<F::Metadata> {
+        self.fs.metadata(&self.path.join(path))
+    }
+
+    // Like metadata, but describes a symlink itself rather than its target.
+    pub fn symlink_metadata(&self, path: &Path) -> io::Result<F::Metadata> {
+        self.fs.symlink_metadata(&self.path.join(path))
+    }
+
+    // The path a symlink points at, without following it.
+    pub fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.fs.read_link(&self.path.join(path))
+    }
+
+    pub fn stat_file(&self, path: PathBuf) -> F::Metadata {
+        match self.fs.metadata(&self.path.join(path)) {
             Ok(metadata) => metadata,
             Err(_) => {
                 panic!("Could not stat_file in workspace");
@@ -71,3 +405,295 @@ impl Workspace {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        fs,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::TempDir;
+
+    use crate::filesystem::mock::MockFs;
+
+    // A smoke version of the list_files benchmark in benches/index.rs,
+    // with files spread over a tree ten directories deep. The bound only
+    // catches the walk going quadratic.
+    #[test]
+    fn list_files_smoke() {
+        let n = 5_000;
+        let dir = TempDir::new().unwrap();
+        for i in 0..n {
+            let mut path = dir.path().to_path_buf();
+            for depth in 0..(i % 10) {
+                path.push(format!("level{}", depth));
+            }
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join(format!("file{}.txt", i)), "bench").unwrap();
+        }
+
+        let workspace = Workspace::new(dir.path().to_path_buf());
+        let start = Instant::now();
+        let files = workspace
+            .list_files(dir.path(), None)
+            .into_iter()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(files.len(), n);
+        assert!(
+            elapsed < Duration::from_secs(5),
+            "listing {} files took {:?}",
+            n,
+            elapsed
+        );
+    }
+
+    // Writes each file under the root, creating parent directories.
+    fn populate(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+    }
+
+    #[test]
+    fn list_files_reports_progress_per_file() {
+        let dir = TempDir::new().unwrap();
+        populate(dir.path(), &["a.txt", "lib/b.txt", "lib/deep/c.txt"]);
+
+        let mut seen = Vec::new();
+        let mut progress = |path: &Path| seen.push(path.to_path_buf());
+        let workspace = Workspace::new(dir.path().to_path_buf());
+        let mut listed = workspace
+            .list_files(dir.path(), Some(&mut progress))
+            .into_iter()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        listed.sort();
+        seen.sort();
+        let expected = ["a.txt", "lib/b.txt", "lib/deep/c.txt"].map(PathBuf::from);
+        assert_eq!(listed, expected);
+        assert_eq!(seen, expected);
+    }
+
+    fn sorted(files: Vec<io::Result<PathBuf>>) -> Vec<PathBuf> {
+        let mut files = files.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn list_pathspecs_walks_only_the_given_subtree() {
+        let dir = TempDir::new().unwrap();
+        populate(
+            dir.path(),
+            &["a.txt", "lib/b.txt", "lib/c/d.txt", "other/e.txt"],
+        );
+        let workspace = Workspace::new(dir.path().to_path_buf());
+
+        let files = workspace.list_pathspecs(&[PathBuf::from("lib")], false, None);
+        assert_eq!(
+            sorted(files),
+            [PathBuf::from("lib/b.txt"), PathBuf::from("lib/c/d.txt")]
+        );
+    }
+
+    #[test]
+    fn ignored_pathspec_yields_nothing_unless_forced() {
+        let dir = TempDir::new().unwrap();
+        populate(
+            dir.path(),
+            &["a.txt", "build/out.txt", "build/deep/more.txt"],
+        );
+        fs::write(dir.path().join(".gitignore"), "build/\n").unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+
+        let pathspecs = [PathBuf::from("build")];
+        assert!(workspace.list_pathspecs(&pathspecs, false, None).is_empty());
+        let inside = [PathBuf::from("build/deep")];
+        assert!(workspace.list_pathspecs(&inside, false, None).is_empty());
+        let file = [PathBuf::from("build/out.txt")];
+        assert!(workspace.list_pathspecs(&file, false, None).is_empty());
+        assert_eq!(
+            sorted(workspace.list_pathspecs(&pathspecs, true, None)),
+            [
+                PathBuf::from("build/deep/more.txt"),
+                PathBuf::from("build/out.txt")
+            ]
+        );
+        assert_eq!(
+            sorted(workspace.list_pathspecs(&[PathBuf::from(".")], false, None)),
+            [PathBuf::from("a.txt")]
+        );
+    }
+
+    #[test]
+    fn walk_terminates_on_symlink_cycles() {
+        let mut mock = MockFs::new();
+        mock.add_file(Path::new("/repo/dir/file.txt"), b"f");
+        // A link back up to its own directory, and two links to each other.
+        mock.add_symlink(Path::new("/repo/dir/up"), Path::new("/repo"));
+        mock.add_symlink(Path::new("/repo/ping"), Path::new("pong"));
+        mock.add_symlink(Path::new("/repo/pong"), Path::new("ping"));
+        let workspace = Workspace::with_fs(PathBuf::from("/repo"), mock);
+
+        let files = workspace.list_files(Path::new("/repo"), None);
+        assert_eq!(
+            sorted(files),
+            ["dir/file.txt", "dir/up", "ping", "pong"].map(PathBuf::from)
+        );
+        let err = workspace.metadata(Path::new("ping")).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("too many levels of symbolic links"));
+    }
+
+    #[test]
+    fn unreadable_directory_is_reported_without_stopping_the_walk() {
+        let mut mock = MockFs::new();
+        mock.add_file(Path::new("/repo/a.txt"), b"a");
+        mock.add_file(Path::new("/repo/locked/secret.txt"), b"s");
+        mock.add_file(Path::new("/repo/z.txt"), b"z");
+        mock.deny_read(Path::new("/repo/locked"));
+        let workspace = Workspace::with_fs(PathBuf::from("/repo"), mock);
+
+        let files = workspace.list_files(Path::new("/repo"), None);
+        let (listed, errors): (Vec<_>, Vec<_>) = files.into_iter().partition(Result::is_ok);
+        let listed = listed.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(listed, [PathBuf::from("a.txt"), PathBuf::from("z.txt")]);
+        assert_eq!(errors.len(), 1);
+        let err = errors.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().starts_with("locked: "));
+    }
+
+    #[test]
+    fn root_only_ignores_leave_nested_names_tracked() {
+        let dir = TempDir::new().unwrap();
+        populate(
+            dir.path(),
+            &[
+                "target/debug.txt",
+                "src/main.txt",
+                "lib/target/keep.txt",
+                "lib/src/keep.txt",
+                "build/out.txt",
+                "lib/build/keep.txt",
+            ],
+        );
+        fs::write(dir.path().join(".gitignore"), "/build\n").unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+
+        assert_eq!(
+            sorted(workspace.list_files(dir.path(), None)),
+            [
+                "lib/build/keep.txt",
+                "lib/src/keep.txt",
+                "lib/target/keep.txt"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn write_file_creates_parents_and_sets_mode() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+        workspace
+            .write_file(Path::new("a/b/run.sh"), b"#!/bin/sh\n", 0o100755)
+            .unwrap();
+        let path = dir.path().join("a/b/run.sh");
+        assert_eq!(fs::read(&path).unwrap(), b"#!/bin/sh\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+
+        // Rewriting replaces the content and mode, leaving no temp files.
+        workspace
+            .write_file(Path::new("a/b/run.sh"), b"plain\n", 0o100644)
+            .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"plain\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o644
+        );
+        assert_eq!(fs::read_dir(dir.path().join("a/b")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn write_file_creates_symlinks() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+        workspace
+            .write_file(Path::new("lib/link"), b"../target.txt", 0o120000)
+            .unwrap();
+        let path = dir.path().join("lib/link");
+        assert!(fs::symlink_metadata(&path).unwrap().is_symlink());
+        assert_eq!(fs::read_link(&path).unwrap(), Path::new("../target.txt"));
+
+        // A link replaces whatever was there before.
+        workspace
+            .write_file(Path::new("lib/link"), b"other.txt", 0o120000)
+            .unwrap();
+        assert_eq!(fs::read_link(&path).unwrap(), Path::new("other.txt"));
+        assert_eq!(fs::read_dir(dir.path().join("lib")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn open_requires_an_existing_directory() {
+        let dir = TempDir::new().unwrap();
+        assert!(Workspace::open(dir.path().to_path_buf()).is_ok());
+
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "f").unwrap();
+        let err = Workspace::open(file.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotADirectory);
+        assert_eq!(
+            err.to_string(),
+            format!("{}: not a directory", file.display())
+        );
+
+        let missing = dir.path().join("missing");
+        let err = Workspace::open(missing.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}: ", missing.display())));
+    }
+
+    #[test]
+    fn read_errors_name_the_absolute_path() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+        let missing = dir.path().join("lib/missing.txt");
+        for err in [
+            workspace
+                .read_data(Path::new("lib/missing.txt"))
+                .unwrap_err(),
+            workspace
+                .read_data_bytes(Path::new("lib/missing.txt"))
+                .unwrap_err(),
+            workspace
+                .read_blob(Path::new("lib/missing.txt"))
+                .unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+            assert!(err
+                .to_string()
+                .starts_with(&format!("{}: ", missing.display())));
+        }
+
+        fs::write(dir.path().join("binary.bin"), [0xff, 0xfe]).unwrap();
+        let err = workspace.read_data(Path::new("binary.bin")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}: ", dir.path().join("binary.bin").display())));
+    }
+}
@@ -0,0 +1,660 @@
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use shit::{commit, database::Database, index::Index};
+use tempfile::TempDir;
+
+/*
+    A scratch directory to run jit in, with its own HOME so the user's
+    global config never leaks in, and a fixed identity and date so
+    commits are reproducible.
+*/
+struct Sandbox {
+    dir: TempDir,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("home")).unwrap();
+        fs::create_dir(dir.path().join("work")).unwrap();
+        Sandbox { dir }
+    }
+
+    // Runs jit init in the work directory.
+    fn init() -> Self {
+        let sandbox = Sandbox::new();
+        assert!(sandbox.run(&["init"]).status.success());
+        sandbox
+    }
+
+    fn home(&self) -> PathBuf {
+        self.dir.path().join("home")
+    }
+
+    fn work(&self) -> PathBuf {
+        self.dir.path().join("work")
+    }
+
+    // Writes a file relative to the work directory, creating its directories.
+    fn write(&self, path: &str, content: &str) {
+        let path = self.work().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.work().join(path)).unwrap()
+    }
+
+    // Runs jit and asserts it succeeded, returning its stdout.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "jit {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_in(&self.work(), args)
+    }
+
+    fn run_in(&self, dir: &Path, args: &[&str]) -> Output {
+        self.command(dir, args).output().unwrap()
+    }
+
+    // Runs jit in the work directory with input written to its stdin.
+    fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(&self.work(), args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn command(&self, dir: &Path, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_jit"));
+        command
+            .args(args)
+            .current_dir(dir)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.home())
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", "1700000000 +0000")
+            .env("GIT_COMMITTER_NAME", "C O Mitter")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", "1700000000 +0000");
+        command
+    }
+}
+
+#[test]
+fn init_points_head_at_main() {
+    let sandbox = Sandbox::init();
+    assert_eq!(sandbox.read(".git/HEAD"), "ref: refs/heads/main\n");
+}
+
+#[test]
+fn init_honors_default_branch_config() {
+    let sandbox = Sandbox::new();
+    fs::write(
+        sandbox.home().join(".gitconfig"),
+        "[init]\n\tdefaultBranch = trunk\n",
+    )
+    .unwrap();
+    assert!(sandbox.run(&["init"]).status.success());
+    assert_eq!(sandbox.read(".git/HEAD"), "ref: refs/heads/trunk\n");
+}
+
+#[test]
+fn failing_pre_commit_hook_aborts_unless_bypassed() {
+    let sandbox = Sandbox::init();
+    sandbox.write(".git/hooks/pre-commit", "#!/bin/sh\nexit 1\n");
+    let hook = sandbox.work().join(".git/hooks/pre-commit");
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    sandbox.write("file.txt", "content\n");
+    sandbox.ok(&["add", "file.txt"]);
+
+    let output = sandbox.run(&["commit", "-m", "first"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pre-commit hook failed"));
+    assert!(!sandbox.work().join(".git/refs/heads/main").exists());
+
+    sandbox.ok(&["commit", "--no-verify", "-m", "first"]);
+    assert_eq!(sandbox.read(".git/refs/heads/main").len(), 41);
+}
+
+#[test]
+fn write_tree_matches_git() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "hello\n");
+    sandbox.write("lib/b.txt", "world\n");
+    sandbox.ok(&["add", "a.txt", "lib/b.txt"]);
+
+    // The id stock git write-tree prints for this layout.
+    let oid = "8522d47075ad7804031c0d8aca3d89f4fa480edd";
+    assert_eq!(sandbox.ok(&["write-tree"]), format!("{}\n", oid));
+    let object = sandbox
+        .work()
+        .join(".git/objects")
+        .join(&oid[..2])
+        .join(&oid[2..]);
+    assert!(object.is_file());
+}
+
+#[test]
+fn add_lists_paths_only_when_verbose() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    sandbox.write("lib/b.txt", "b\n");
+    assert_eq!(sandbox.ok(&["add", "a.txt"]), "");
+    assert_eq!(
+        sandbox.ok(&["add", "-v", "a.txt", "lib"]),
+        "add 'a.txt'\nadd 'lib/b.txt'\n"
+    );
+}
+
+#[test]
+fn commit_date_override_sets_the_author_timestamp() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit", "--date", "@1700000123 +0100", "-m", "dated"]);
+
+    let oid = sandbox.read(".git/refs/heads/main");
+    let database = Database::new(sandbox.work().join(".git/objects"));
+    let (_, data) = database.read_object(oid.trim()).unwrap();
+    let commit = commit::parse_commit(&data).unwrap();
+    assert_eq!(commit.author.time, "1700000123 +0100");
+    // The committer keeps GIT_COMMITTER_DATE.
+    assert_eq!(commit.committer.time, "1700000000 +0000");
+}
+
+#[test]
+fn init_bare_lays_out_the_repository_at_the_root() {
+    let sandbox = Sandbox::new();
+    sandbox.ok(&["init", "--bare", "repo.git"]);
+    let repo = sandbox.work().join("repo.git");
+    assert!(repo.join("objects").is_dir());
+    assert!(repo.join("refs/heads").is_dir());
+    assert!(!repo.join(".git").exists());
+    assert_eq!(
+        fs::read_to_string(repo.join("HEAD")).unwrap(),
+        "ref: refs/heads/main\n"
+    );
+    assert_eq!(
+        fs::read_to_string(repo.join("config")).unwrap(),
+        "[core]\n\tbare = true\n"
+    );
+
+    for command in ["status", "add"] {
+        let output = sandbox.run_in(&repo, &[command, "."]);
+        assert_eq!(output.status.code(), Some(128));
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .ends_with("fatal: this operation must be run in a work tree\n"));
+    }
+}
+
+#[test]
+fn branch_lists_creates_and_refuses_overwrites() {
+    let sandbox = Sandbox::init();
+    let output = sandbox.run(&["branch", "topic"]);
+    assert_eq!(output.status.code(), Some(128));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HEAD does not point at a commit"));
+
+    sandbox.write("a.txt", "a\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit", "-m", "first"]);
+    sandbox.ok(&["branch", "topic"]);
+    assert_eq!(
+        sandbox.read(".git/refs/heads/topic"),
+        sandbox.read(".git/refs/heads/main")
+    );
+    assert_eq!(sandbox.ok(&["branch"]), "* main\n  topic\n");
+
+    let output = sandbox.run(&["branch", "topic"]);
+    assert_eq!(output.status.code(), Some(128));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .ends_with("fatal: a branch named 'topic' already exists\n"));
+}
+
+#[test]
+fn commands_outside_a_repository_fail_up_to_the_root() {
+    let sandbox = Sandbox::new();
+    let nested = sandbox.work().join("a").join("b");
+    fs::create_dir_all(&nested).unwrap();
+    for dir in [nested.as_path(), Path::new("/")] {
+        let output = sandbox.run_in(dir, &["status"]);
+        assert_eq!(output.status.code(), Some(128));
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .ends_with("fatal: not a git repository (or any of the parent directories): .git\n"));
+    }
+}
+
+// The message of the commit main points at.
+fn head_message(sandbox: &Sandbox) -> String {
+    let oid = sandbox.read(".git/refs/heads/main");
+    let database = Database::new(sandbox.work().join(".git/objects"));
+    let (_, data) = database.read_object(oid.trim()).unwrap();
+    commit::parse_commit(&data).unwrap().message
+}
+
+#[test]
+fn commit_messages_come_from_flags_a_file_or_the_editor() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit", "-m", "subject", "-m", "body  "]);
+    assert_eq!(head_message(&sandbox), "subject\n\nbody\n");
+
+    sandbox.write("a.txt", "b\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.write("msg.txt", "\n\nfrom a file\n# kept\n\n");
+    sandbox.ok(&["commit", "-F", "msg.txt"]);
+    assert_eq!(head_message(&sandbox), "from a file\n# kept\n");
+
+    // The stub editor replaces the prepared message, comments included.
+    let editor = sandbox.home().join("editor.sh");
+    fs::write(
+        &editor,
+        "#!/bin/sh\nprintf '# dropped\\nfrom the editor\\n' > \"$1\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&editor, fs::Permissions::from_mode(0o755)).unwrap();
+    let config = sandbox.read(".git/config");
+    sandbox.write(
+        ".git/config",
+        &format!("{}[core]\n\teditor = {}\n", config, editor.display()),
+    );
+    sandbox.write("a.txt", "c\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit"]);
+    assert_eq!(head_message(&sandbox), "from the editor\n");
+    assert_eq!(
+        sandbox.read(".git/COMMIT_EDITMSG"),
+        "# dropped\nfrom the editor\n"
+    );
+}
+
+#[test]
+fn empty_commit_message_aborts_the_commit() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.write("msg.txt", "\n  \n");
+    for args in [&["commit", "-m", ""][..], &["commit", "-F", "msg.txt"]] {
+        let output = sandbox.run(args);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .ends_with("Aborting commit due to empty commit message.\n"));
+    }
+    assert!(!sandbox.work().join(".git/refs/heads/main").exists());
+}
+
+#[test]
+fn commit_tree_prints_the_oid_git_produces() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "hello\n");
+    sandbox.write("lib/b.txt", "world\n");
+    sandbox.ok(&["add", "."]);
+    let tree = "8522d47075ad7804031c0d8aca3d89f4fa480edd";
+    assert_eq!(sandbox.ok(&["write-tree"]), format!("{}\n", tree));
+
+    let root = "4b180778a17141d691a27441ad1419cdeedfff1c";
+    let second = "2a60e148f77194b9713a52b20770c6d9cfb4fbac";
+    assert_eq!(
+        sandbox.ok(&["commit-tree", tree, "-m", "root"]),
+        format!("{}\n", root)
+    );
+    assert_eq!(
+        sandbox.ok(&["commit-tree", tree, "-p", root, "-m", "second"]),
+        format!("{}\n", second)
+    );
+    assert_eq!(
+        sandbox.ok(&["commit-tree", tree, "-p", root, "-p", second, "-m", "merge"]),
+        "eb2a75ea91f8d5f4914163d82f3937a8ea081ffd\n"
+    );
+
+    let output = sandbox.run_with_input(&["commit-tree", tree, "-p", root], "from stdin\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "520dc3322d24444c98cb5c946458cc5ee6b0be0c\n"
+    );
+    // Plumbing leaves the branch alone.
+    assert!(!sandbox.work().join(".git/refs/heads/main").exists());
+}
+
+#[test]
+fn autocrlf_stores_lf_and_checks_out_crlf() {
+    let sandbox = Sandbox::init();
+    let config = sandbox.read(".git/config");
+    sandbox.write(
+        ".git/config",
+        &format!("{}[core]\n\tautocrlf = true\n", config),
+    );
+    sandbox.write("a.txt", "one\r\ntwo\r\n");
+    fs::write(sandbox.work().join("bin.dat"), b"a\0\r\n").unwrap();
+    sandbox.ok(&["add", "a.txt", "bin.dat"]);
+
+    // Text is stored with LF, content that looks binary as it is.
+    let database = Database::new(sandbox.work().join(".git/objects"));
+    let (_, text) = database
+        .read_object("814f4a422927b82f5f8a43f8fab6d3839e3983f2")
+        .unwrap();
+    assert_eq!(text, b"one\ntwo\n");
+    let (_, binary) = database
+        .read_object("343dc4e20e41d53af6cc9e3cd875775a63e01b8a")
+        .unwrap();
+    assert_eq!(binary, b"a\0\r\n");
+
+    fs::remove_file(sandbox.work().join("a.txt")).unwrap();
+    fs::remove_file(sandbox.work().join("bin.dat")).unwrap();
+    sandbox.ok(&["checkout-index", "-a"]);
+    assert_eq!(sandbox.read("a.txt"), "one\r\ntwo\r\n");
+    assert_eq!(
+        fs::read(sandbox.work().join("bin.dat")).unwrap(),
+        b"a\0\r\n"
+    );
+}
+
+// The paths staged in the work directory's index.
+fn staged_paths(sandbox: &Sandbox) -> Vec<String> {
+    let mut index = Index::load(sandbox.work().join(".git/index")).unwrap();
+    index
+        .each_entry()
+        .iter()
+        .map(|entry| entry.path().to_string())
+        .collect()
+}
+
+#[test]
+fn add_reads_pathspecs_from_stdin_or_a_file() {
+    let sandbox = Sandbox::init();
+    for path in ["a.txt", "lib/b.txt", "c d.txt", "e.txt"] {
+        sandbox.write(path, path);
+    }
+    let output = sandbox.run_with_input(
+        &["add", "--pathspec-from-file=-"],
+        "a.txt\nlib/b.txt\n\nc d.txt\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(staged_paths(&sandbox), ["a.txt", "c d.txt", "lib/b.txt"]);
+
+    sandbox.write("specs", "e.txt\0lib\0");
+    sandbox.write("lib/f.txt", "f");
+    sandbox.ok(&[
+        "add",
+        "--pathspec-from-file",
+        "specs",
+        "--pathspec-file-nul",
+    ]);
+    assert_eq!(
+        staged_paths(&sandbox),
+        ["a.txt", "c d.txt", "e.txt", "lib/b.txt", "lib/f.txt"]
+    );
+}
+
+#[test]
+fn add_of_a_missing_pathspec_fails_unless_ignored() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    let output = sandbox.run(&["add", "a.txt", "missing.txt"]);
+    assert_eq!(output.status.code(), Some(128));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("fatal: pathspec 'missing.txt' did not match any files\n"));
+    assert!(!sandbox.work().join(".git/index").exists());
+    assert!(!sandbox.work().join(".git/index.lock").exists());
+
+    sandbox.ok(&["add", "--ignore-missing", "a.txt", "missing.txt"]);
+    assert_eq!(staged_paths(&sandbox), ["a.txt"]);
+}
+
+#[test]
+fn add_fails_when_a_path_is_skipped() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    fs::write(sandbox.work().join("bin.dat"), [0xff, 0xfe, 0x00]).unwrap();
+
+    let output = sandbox.run(&["add", "bin.dat"]);
+    assert_eq!(output.status.code(), Some(128));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: unable to read 'bin.dat'"));
+    assert!(stderr.contains("fatal: adding files failed"));
+
+    // The readable files are still staged.
+    assert_eq!(sandbox.run(&["add", "."]).status.code(), Some(128));
+    assert_eq!(staged_paths(&sandbox), ["a.txt"]);
+}
+
+#[test]
+fn concurrent_adds_of_different_paths_both_land() {
+    let sandbox = Sandbox::init();
+    let mut expected = Vec::new();
+    for round in 0..10 {
+        let a = format!("a{:02}.txt", round);
+        let b = format!("b{:02}.txt", round);
+        sandbox.write(&a, &a);
+        sandbox.write(&b, &b);
+        let children = [&a, &b].map(|path| {
+            sandbox
+                .command(&sandbox.work(), &["add", path])
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap()
+        });
+        for child in children {
+            let output = child.wait_with_output().unwrap();
+            assert!(
+                output.status.success(),
+                "add failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        expected.extend([a, b]);
+        expected.sort();
+        assert_eq!(staged_paths(&sandbox), expected);
+    }
+}
+
+#[test]
+fn clean_requires_force_or_dry_run() {
+    let sandbox = Sandbox::init();
+    sandbox.write("junk.txt", "junk\n");
+    let output = sandbox.run(&["clean"]);
+    assert_eq!(output.status.code(), Some(128));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .ends_with("fatal: refusing to clean without -f or -n\n"));
+    assert_eq!(sandbox.ok(&["clean", "-n"]), "Would remove junk.txt\n");
+    assert_eq!(sandbox.ok(&["clean", "-f"]), "Removing junk.txt\n");
+    assert!(!sandbox.work().join("junk.txt").exists());
+}
+
+#[test]
+fn commits_without_changes_need_allow_empty() {
+    let sandbox = Sandbox::init();
+    // The root commit is never empty, even with nothing staged.
+    sandbox.ok(&["commit", "-m", "root"]);
+    let root = sandbox.read(".git/refs/heads/main");
+
+    let output = sandbox.run(&["commit", "-m", "nothing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "nothing to commit, working tree clean\n"
+    );
+    assert_eq!(sandbox.read(".git/refs/heads/main"), root);
+
+    sandbox.ok(&["commit", "--allow-empty", "-m", "marker"]);
+    let marker = sandbox.read(".git/refs/heads/main");
+    assert_ne!(marker, root);
+    let database = Database::new(sandbox.work().join(".git/objects"));
+    let (_, data) = database.read_object(marker.trim()).unwrap();
+    let commit = commit::parse_commit(&data).unwrap();
+    assert_eq!(commit.parents, [root.trim()]);
+    assert_eq!(commit.message, "marker\n");
+}
+
+#[test]
+fn show_ref_lists_loose_and_packed_refs_by_namespace() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit", "-m", "first"]);
+    let main = sandbox.read(".git/refs/heads/main");
+    let main = main.trim();
+    let packed = "1111111111111111111111111111111111111111";
+    sandbox.write(
+        ".git/packed-refs",
+        &format!("{} refs/heads/old\n{} refs/tags/v1\n", packed, main),
+    );
+
+    assert_eq!(
+        sandbox.ok(&["show-ref"]),
+        format!(
+            "{} refs/heads/main\n{} refs/heads/old\n{} refs/tags/v1\n",
+            main, packed, main
+        )
+    );
+    assert_eq!(
+        sandbox.ok(&["show-ref", "--heads"]),
+        format!("{} refs/heads/main\n{} refs/heads/old\n", main, packed)
+    );
+    assert_eq!(
+        sandbox.ok(&["show-ref", "--tags"]),
+        format!("{} refs/tags/v1\n", main)
+    );
+}
+
+#[test]
+fn color_is_added_only_when_asked_for() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "old\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit", "-m", "first"]);
+    sandbox.write("a.txt", "new\n");
+    sandbox.write("untracked.txt", "u\n");
+
+    let diff = sandbox.ok(&["diff", "--color=always"]);
+    assert!(diff.contains("\x1b[36m@@ -1 +1 @@\x1b[m"));
+    assert!(diff.contains("\x1b[31m-old\x1b[m\n"));
+    assert!(diff.contains("\x1b[32m+new\x1b[m\n"));
+    let status = sandbox.ok(&["status", "--color=always"]);
+    assert!(status.contains("\t\x1b[31mmodified:   a.txt\x1b[m\n"));
+    assert!(status.contains("\t\x1b[31muntracked.txt\x1b[m\n"));
+
+    // Piped output is plain unless color is forced.
+    for args in [&["diff"][..], &["diff", "--color=never"], &["status"]] {
+        assert!(!sandbox.ok(args).contains('\x1b'), "{:?}", args);
+    }
+
+    // color.ui applies when no flag is given, and a flag overrides it.
+    sandbox.write(".git/config", "[color]\n\tui = always\n");
+    assert!(sandbox.ok(&["diff"]).contains("\x1b[32m+new\x1b[m\n"));
+    assert!(!sandbox.ok(&["status", "--color=never"]).contains('\x1b'));
+    assert!(!sandbox.ok(&["status", "--porcelain"]).contains('\x1b'));
+}
+
+// Every file under dir, relative to it, with its content.
+fn snapshot(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(next) = pending.pop() {
+        for entry in fs::read_dir(next).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let content = fs::read(&path).unwrap();
+                files.push((path.strip_prefix(dir).unwrap().to_path_buf(), content));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn dry_run_commit_reports_without_writing() {
+    let sandbox = Sandbox::init();
+    sandbox.write("a.txt", "a\n");
+    sandbox.ok(&["add", "a.txt"]);
+    sandbox.ok(&["commit", "-m", "first"]);
+    sandbox.write("a.txt", "changed\n");
+    sandbox.write("lib/b.txt", "b\n");
+    sandbox.write("untracked.txt", "u\n");
+    sandbox.ok(&["add", "a.txt", "lib/b.txt"]);
+
+    let git_dir = sandbox.work().join(".git");
+    let before = snapshot(&git_dir);
+    let output = sandbox.ok(&["commit", "--dry-run", "-m", "second"]);
+    assert_eq!(snapshot(&git_dir), before);
+    let lines = output.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..3], ["M  a.txt", "A  lib/b.txt", "?? untracked.txt"]);
+
+    // The reported ids are the ones the real commit gets.
+    sandbox.ok(&["commit", "-m", "second"]);
+    let head = sandbox.read(".git/refs/heads/main");
+    let database = Database::new(git_dir.join("objects"));
+    let (_, data) = database.read_object(head.trim()).unwrap();
+    let commit = commit::parse_commit(&data).unwrap();
+    assert_eq!(
+        lines[3..],
+        [
+            format!("tree {}", commit.tree),
+            format!("commit {}", head.trim())
+        ]
+    );
+}
+
+#[test]
+fn git_dir_and_work_tree_come_from_the_environment() {
+    let sandbox = Sandbox::init();
+    let store = sandbox.dir.path().join("store.git");
+    fs::rename(sandbox.work().join(".git"), &store).unwrap();
+    let tree = sandbox.dir.path().join("tree");
+    fs::create_dir_all(&tree).unwrap();
+    fs::write(tree.join("a.txt"), "a\n").unwrap();
+
+    // Run from a directory outside both, where discovery would fail.
+    let jit = |args: &[&str]| {
+        let output = sandbox
+            .command(&sandbox.home(), args)
+            .env("GIT_DIR", &store)
+            .env("GIT_WORK_TREE", &tree)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "jit {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    jit(&["add", "a.txt"]);
+    jit(&["commit", "-m", "first"]);
+    assert_eq!(jit(&["status", "--porcelain"]), "");
+
+    let head = fs::read_to_string(store.join("refs/heads/main")).unwrap();
+    let database = Database::new(store.join("objects"));
+    let (_, data) = database.read_object(head.trim()).unwrap();
+    assert_eq!(commit::parse_commit(&data).unwrap().message, "first\n");
+    assert!(!tree.join(".git").exists() && !sandbox.work().join(".git").exists());
+}